@@ -0,0 +1,319 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use log::{error, info, warn};
+
+use crate::config::Alarm;
+use crate::queue::Queue;
+use crate::spotify::VOLUME_PERCENT;
+use crate::spotify_url::SpotifyUrl;
+
+/// Alarms that are noticed more than this many seconds late, i.e. after the machine was
+/// suspended, are missed.
+const MISSED_AFTER_SECS: i64 = 120;
+
+/// The longest period that is checked for missed alarms.
+const MAX_LOOKBACK_DAYS: i64 = 7;
+
+/// The number of steps the volume is ramped up in.
+const RAMP_STEPS: u64 = 50;
+
+/// A source of the current local time.
+pub trait Clock: Send {
+    fn now(&self) -> NaiveDateTime;
+}
+
+/// The clock of the system, in the local timezone.
+pub struct LocalClock;
+
+impl Clock for LocalClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+/// What happened to an alarm since the scheduler was last polled.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AlarmEvent {
+    /// The alarm is due now.
+    Ring(Alarm),
+    /// The alarm was due at the given time, but that was too long ago to still ring.
+    Missed(Alarm, NaiveDateTime),
+}
+
+/// When an alarm rings.
+struct Schedule {
+    time: NaiveTime,
+    days: Option<Vec<Weekday>>,
+}
+
+impl Schedule {
+    fn parse(alarm: &Alarm) -> Result<Self, String> {
+        let time = NaiveTime::parse_from_str(&alarm.time, "%H:%M")
+            .map_err(|e| format!("invalid time \"{}\": {e}", alarm.time))?;
+        let days = alarm
+            .days
+            .as_ref()
+            .map(|days| {
+                days.iter()
+                    .map(|day| {
+                        Weekday::from_str(day).map_err(|_| format!("invalid weekday \"{day}\""))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+        Ok(Self { time, days })
+    }
+
+    /// The last time the alarm rang after `after`, up to and including `until`.
+    fn last_between(&self, after: NaiveDateTime, until: NaiveDateTime) -> Option<NaiveDateTime> {
+        let first_day = after
+            .date()
+            .max(until.date() - chrono::Duration::days(MAX_LOOKBACK_DAYS));
+        first_day
+            .iter_days()
+            .take_while(|day| *day <= until.date())
+            .filter(|day| {
+                self.days
+                    .as_ref()
+                    .is_none_or(|days| days.contains(&day.weekday()))
+            })
+            .map(|day| day.and_time(self.time))
+            .filter(|time| *time > after && *time <= until)
+            .last()
+    }
+}
+
+/// Determines when the configured alarms ring.
+pub struct AlarmScheduler {
+    clock: Box<dyn Clock>,
+    last_poll: NaiveDateTime,
+    /// Configuration errors that were logged already, so they aren't logged on every poll.
+    reported: HashSet<String>,
+}
+
+impl AlarmScheduler {
+    pub fn new(clock: Box<dyn Clock>) -> Self {
+        let last_poll = clock.now();
+        Self {
+            clock,
+            last_poll,
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Return the `alarms` that became due since the last poll.
+    pub fn poll(&mut self, alarms: &[Alarm]) -> Vec<AlarmEvent> {
+        let now = self.clock.now();
+        let last_poll = std::mem::replace(&mut self.last_poll, now);
+        if now <= last_poll {
+            // the clock was turned back, alarms in between will ring again
+            return Vec::new();
+        }
+
+        let mut events = Vec::new();
+        for alarm in alarms {
+            let schedule = match Schedule::parse(alarm) {
+                Ok(schedule) => schedule,
+                Err(e) => {
+                    if self.reported.insert(e.clone()) {
+                        error!("Ignoring alarm for {}: {e}", alarm.uri);
+                    }
+                    continue;
+                }
+            };
+
+            if let Some(due) = schedule.last_between(last_poll, now) {
+                if (now - due).num_seconds() > MISSED_AFTER_SECS {
+                    events.push(AlarmEvent::Missed(alarm.clone(), due));
+                } else {
+                    events.push(AlarmEvent::Ring(alarm.clone()));
+                }
+            }
+        }
+        events
+    }
+}
+
+/// Play the item of `alarm` from the start of the queue, ramping up the volume.
+pub fn ring(alarm: &Alarm, queue: Arc<Queue>) {
+    let alarm = alarm.clone();
+    // resolving the item uses the blocking Web API client
+    thread::spawn(move || {
+        let spotify = queue.get_spotify();
+        let Some(mut item) =
+            SpotifyUrl::from_uri(&alarm.uri).and_then(|url| url.resolve(&spotify.api))
+        else {
+            error!("Alarm could not find \"{}\"", alarm.uri);
+            return;
+        };
+
+        info!("Alarm rings, playing {}", alarm.uri);
+        let target = alarm
+            .volume
+            .map(|percent| percent.min(100) * VOLUME_PERCENT)
+            .unwrap_or_else(|| spotify.volume());
+        let ramp = Duration::from_secs(alarm.ramp.unwrap_or(0));
+        if !ramp.is_zero() {
+            spotify.set_volume(0);
+        }
+
+        queue.clear();
+        item.play(&queue);
+
+        if !ramp.is_zero() {
+            for step in 1..=RAMP_STEPS {
+                thread::sleep(ramp / RAMP_STEPS as u32);
+                spotify.set_volume((target as u64 * step / RAMP_STEPS) as u16);
+            }
+        } else {
+            spotify.set_volume(target);
+        }
+    });
+}
+
+/// Log an alarm that was missed, i.e. because the machine was suspended.
+pub fn log_missed(alarm: &Alarm, due: NaiveDateTime) {
+    warn!(
+        "Missed the alarm for {} at {}, ncspot wasn't running or the machine was suspended",
+        alarm.uri,
+        due.format("%Y-%m-%d %H:%M")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    /// A clock that is set by the test.
+    #[derive(Clone)]
+    struct FakeClock(Arc<Mutex<NaiveDateTime>>);
+
+    impl FakeClock {
+        fn set(&self, time: NaiveDateTime) {
+            *self.0.lock().unwrap() = time;
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> NaiveDateTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    /// The given time on Monday, 2023-05-01 plus `days`.
+    fn at(days: u32, time: &str) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 5, 1 + days)
+            .unwrap()
+            .and_time(NaiveTime::parse_from_str(time, "%H:%M:%S").unwrap())
+    }
+
+    fn alarm(time: &str, days: Option<&[&str]>) -> Alarm {
+        Alarm {
+            time: time.to_string(),
+            days: days.map(|days| days.iter().map(|day| day.to_string()).collect()),
+            uri: "spotify:playlist:37i9dQZF1DX0UrRvztWcAU".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn scheduler(start: NaiveDateTime) -> (AlarmScheduler, FakeClock) {
+        let clock = FakeClock(Arc::new(Mutex::new(start)));
+        (AlarmScheduler::new(Box::new(clock.clone())), clock)
+    }
+
+    #[test]
+    fn rings_once() {
+        let alarms = [alarm("07:00", None)];
+        let (mut scheduler, clock) = scheduler(at(0, "06:59:00"));
+
+        clock.set(at(0, "06:59:59"));
+        assert_eq!(scheduler.poll(&alarms), vec![]);
+        clock.set(at(0, "07:00:00"));
+        assert_eq!(
+            scheduler.poll(&alarms),
+            vec![AlarmEvent::Ring(alarms[0].clone())]
+        );
+        clock.set(at(0, "07:00:01"));
+        assert_eq!(scheduler.poll(&alarms), vec![]);
+
+        // and again the next day
+        clock.set(at(1, "07:00:01"));
+        assert_eq!(
+            scheduler.poll(&alarms),
+            vec![AlarmEvent::Ring(alarms[0].clone())]
+        );
+    }
+
+    #[test]
+    fn only_on_weekdays() {
+        let alarms = [alarm("07:00", Some(&["mon", "Wednesday"]))];
+        let (mut scheduler, clock) = scheduler(at(0, "08:00:00"));
+
+        // tuesday
+        clock.set(at(1, "07:00:30"));
+        assert_eq!(scheduler.poll(&alarms), vec![]);
+        // wednesday
+        clock.set(at(2, "07:00:30"));
+        assert_eq!(
+            scheduler.poll(&alarms),
+            vec![AlarmEvent::Ring(alarms[0].clone())]
+        );
+    }
+
+    #[test]
+    fn missed_while_suspended() {
+        let alarms = [alarm("07:00", None), alarm("07:30", None)];
+        let (mut scheduler, clock) = scheduler(at(0, "06:00:00"));
+
+        // resumed just after the second alarm, the first one is missed
+        clock.set(at(0, "07:31:00"));
+        assert_eq!(
+            scheduler.poll(&alarms),
+            vec![
+                AlarmEvent::Missed(alarms[0].clone(), at(0, "07:00:00")),
+                AlarmEvent::Ring(alarms[1].clone()),
+            ]
+        );
+
+        // only the most recent occurrence is reported after days of suspend
+        clock.set(at(3, "12:00:00"));
+        assert_eq!(
+            scheduler.poll(&alarms),
+            vec![
+                AlarmEvent::Missed(alarms[0].clone(), at(3, "07:00:00")),
+                AlarmEvent::Missed(alarms[1].clone(), at(3, "07:30:00")),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_invalid_alarms() {
+        let alarms = [alarm("7 o'clock", None), alarm("07:00", Some(&["someday"]))];
+        let (mut scheduler, clock) = scheduler(at(0, "06:00:00"));
+        clock.set(at(0, "08:00:00"));
+        assert_eq!(scheduler.poll(&alarms), vec![]);
+        assert_eq!(scheduler.reported.len(), 2);
+    }
+
+    #[test]
+    fn clock_turned_back() {
+        let alarms = [alarm("07:00", None)];
+        let (mut scheduler, clock) = scheduler(at(0, "07:00:30"));
+        clock.set(at(0, "06:30:00"));
+        assert_eq!(scheduler.poll(&alarms), vec![]);
+        clock.set(at(0, "07:00:00"));
+        assert_eq!(
+            scheduler.poll(&alarms),
+            vec![AlarmEvent::Ring(alarms[0].clone())]
+        );
+    }
+}
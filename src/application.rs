@@ -1,6 +1,7 @@
 use std::path::Path;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use cursive::traits::Nameable;
 use cursive::{Cursive, CursiveRunner};
@@ -9,16 +10,22 @@ use log::{error, info, trace};
 #[cfg(unix)]
 use signal_hook::{consts::SIGHUP, consts::SIGTERM, iterator::Signals};
 
+use crate::alarm::{self, AlarmEvent, AlarmScheduler, LocalClock};
 use crate::command::Command;
-use crate::commands::CommandManager;
+use crate::commands::{CommandExecutor, CommandManager};
 use crate::config::Config;
 use crate::events::{Event, EventManager};
 use crate::library::Library;
+use crate::lyrics::LocalLyrics;
+use crate::model::play::Play;
+use crate::play_tracker::PlayTracker;
 use crate::queue::Queue;
+use crate::scrobbler::Scrobbler;
+use crate::sleep_timer::SleepAction;
 use crate::spotify::{PlayerEvent, Spotify};
 use crate::ui::create_cursive;
-use crate::{authentication, ui};
-use crate::{command, queue, spotify};
+use crate::{authentication, ui, utils};
+use crate::{command, config, queue, spotify};
 
 #[cfg(feature = "mpris")]
 use crate::mpris::{self, MprisManager};
@@ -26,6 +33,12 @@ use crate::mpris::{self, MprisManager};
 #[cfg(unix)]
 use crate::ipc::{self, IpcSocket};
 
+#[cfg(feature = "web_ui")]
+use crate::web::{Controller, WebServer};
+
+#[cfg(feature = "connect")]
+use crate::connect::Connect;
+
 /// Set up the global logger to log to `filename`.
 pub fn setup_logging(filename: &Path) -> Result<(), fern::InitError> {
     fern::Dispatch::new()
@@ -55,6 +68,10 @@ pub struct UserDataInner {
     pub cmd: CommandManager,
 }
 
+/// How long the event loop waits for new events before checking for signals when running without a
+/// user interface.
+const DAEMON_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 lazy_static!(
     /// The global Tokio runtime for running asynchronous tasks.
     pub static ref ASYNC_RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_multi_thread()
@@ -67,6 +84,8 @@ lazy_static!(
 pub struct Application {
     /// The music queue which controls playback order.
     queue: Arc<Queue>,
+    /// The user's library.
+    library: Arc<Library>,
     /// Internally shared
     spotify: Spotify,
     /// Internally shared
@@ -74,11 +93,34 @@ pub struct Application {
     /// An IPC implementation using the D-Bus MPRIS protocol, used to control and inspect ncspot.
     #[cfg(feature = "mpris")]
     mpris_manager: MprisManager,
+    /// Whether the queue changed since it was last announced through MPRIS.
+    #[cfg(feature = "mpris")]
+    tracklist_outdated: bool,
     /// An IPC implementation using a Unix domain socket, used to control and inspect ncspot.
     #[cfg(unix)]
     ipc: IpcSocket,
-    /// The object to render to the terminal.
-    cursive: CursiveRunner<Cursive>,
+    /// Whether the state that is published to IPC clients may have changed since it was last
+    /// published.
+    #[cfg(unix)]
+    ipc_outdated: bool,
+    /// A remote control web interface served over HTTP, stopped when dropped.
+    #[cfg(feature = "web_ui")]
+    _web_server: Option<WebServer>,
+    /// The Spotify Connect device, which controllers can play on.
+    #[cfg(feature = "connect")]
+    connect: Option<Connect>,
+    /// Determines how long each item was played for.
+    play_tracker: PlayTracker,
+    /// Submits listens to the configured services.
+    scrobbler: Option<Scrobbler>,
+    /// Determines when the configured alarms ring.
+    alarm_scheduler: AlarmScheduler,
+    /// Executes commands when there is no user interface.
+    executor: CommandExecutor,
+    /// The object to render to the terminal. None when running as a daemon.
+    cursive: Option<CursiveRunner<Cursive>>,
+    /// Whether the event loop should keep running when there is no user interface.
+    running: bool,
 }
 
 impl Application {
@@ -87,28 +129,27 @@ impl Application {
     /// # Arguments
     ///
     /// * `configuration_file_path` - Relative path to the configuration file inside the base path
-    pub fn new(configuration_file_path: Option<String>) -> Result<Self, String> {
+    /// * `daemon` - Run without a user interface, only controllable through IPC and MPRIS
+    pub fn new(configuration_file_path: Option<String>, daemon: bool) -> Result<Self, String> {
         // Things here may cause the process to abort; we must do them before creating curses
         // windows otherwise the error message will not be seen by a user
 
         let configuration = Arc::new(Config::new(configuration_file_path));
-        let credentials = authentication::get_credentials(&configuration)?;
-        let theme = configuration.build_theme();
+        let credentials = authentication::get_credentials(&configuration, !daemon)?;
 
         println!("Connecting to Spotify..");
 
         // DON'T USE STDOUT AFTER THIS CALL!
-        let mut cursive = create_cursive().map_err(|error| error.to_string())?;
-
-        cursive.set_theme(theme.clone());
-
-        #[cfg(all(unix, feature = "pancurses_backend"))]
-        cursive.add_global_callback(cursive::event::Event::CtrlChar('z'), |_s| unsafe {
-            libc::raise(libc::SIGTSTP);
-        });
+        let mut cursive = if daemon {
+            None
+        } else {
+            Some(create_cursive().map_err(|error| error.to_string())?)
+        };
 
-        let event_manager = EventManager::new(cursive.cb_sink().clone());
+        let event_manager = EventManager::new(cursive.as_ref().map(|c| c.cb_sink().clone()));
 
+        #[cfg(feature = "connect")]
+        let connect_credentials = credentials.clone();
         let spotify =
             spotify::Spotify::new(event_manager.clone(), credentials, configuration.clone());
 
@@ -119,6 +160,7 @@ impl Application {
         ));
 
         let queue = Arc::new(queue::Queue::new(
+            event_manager.clone(),
             spotify.clone(),
             configuration.clone(),
             library.clone(),
@@ -130,6 +172,7 @@ impl Application {
             queue.clone(),
             library.clone(),
             spotify.clone(),
+            configuration.clone(),
         );
 
         #[cfg(unix)]
@@ -137,19 +180,118 @@ impl Application {
             ASYNC_RUNTIME.handle(),
             crate::config::cache_path("ncspot.sock"),
             event_manager.clone(),
+            queue.clone(),
+            library.clone(),
+            configuration.values().remote.clone().unwrap_or_default(),
         )
         .map_err(|e| e.to_string())?;
 
-        let mut cmd_manager = CommandManager::new(
+        #[cfg(feature = "web_ui")]
+        let web_server = match configuration.values().web_ui_address.clone() {
+            Some(address) => {
+                let token = configuration
+                    .values()
+                    .web_ui_token
+                    .clone()
+                    .ok_or("web_ui_token has to be set to serve the web interface")?;
+                Some(WebServer::start(
+                    &address,
+                    token,
+                    Controller::new(event_manager.clone(), queue.clone(), library.clone()),
+                )?)
+            }
+            None => None,
+        };
+
+        #[cfg(feature = "connect")]
+        let connect = match configuration.values().connect.as_ref() {
+            Some(cfg) => Some(Connect::start(
+                cfg,
+                connect_credentials,
+                event_manager.clone(),
+                spotify.volume(),
+            )?),
+            None => None,
+        };
+
+        let scrobbler = configuration
+            .values()
+            .scrobbling
+            .as_ref()
+            .and_then(Scrobbler::new);
+
+        let executor = CommandExecutor::new(
             spotify.clone(),
             queue.clone(),
             library.clone(),
             configuration.clone(),
-            event_manager.clone(),
         );
 
+        if let Some(cursive) = cursive.as_mut() {
+            let cmd_manager = CommandManager::new(
+                spotify.clone(),
+                queue.clone(),
+                library.clone(),
+                configuration.clone(),
+                event_manager.clone(),
+            );
+            Self::create_ui(
+                cursive,
+                cmd_manager,
+                &event_manager,
+                queue.clone(),
+                library.clone(),
+                configuration,
+            );
+        } else {
+            info!("Running without a user interface");
+        }
+
+        Ok(Self {
+            queue,
+            library,
+            spotify,
+            event_manager,
+            #[cfg(feature = "mpris")]
+            mpris_manager,
+            #[cfg(feature = "mpris")]
+            tracklist_outdated: false,
+            #[cfg(unix)]
+            ipc,
+            #[cfg(unix)]
+            ipc_outdated: true,
+            #[cfg(feature = "web_ui")]
+            _web_server: web_server,
+            #[cfg(feature = "connect")]
+            connect,
+            play_tracker: PlayTracker::default(),
+            scrobbler,
+            alarm_scheduler: AlarmScheduler::new(Box::new(LocalClock)),
+            executor,
+            cursive,
+            running: true,
+        })
+    }
+
+    /// Set up the views and keybindings of the user interface.
+    fn create_ui(
+        cursive: &mut Cursive,
+        mut cmd_manager: CommandManager,
+        event_manager: &EventManager,
+        queue: Arc<Queue>,
+        library: Arc<Library>,
+        configuration: Arc<Config>,
+    ) {
+        let theme = configuration.build_theme();
+        cursive.set_theme(theme.clone());
+
+        #[cfg(all(unix, feature = "pancurses_backend"))]
+        cursive.add_global_callback(cursive::event::Event::CtrlChar('z'), |_s| unsafe {
+            libc::raise(libc::SIGTSTP);
+        });
+
         cmd_manager.register_all();
-        cmd_manager.register_keybindings(&mut cursive);
+        cmd_manager.register_keybindings(cursive);
 
         cursive.set_user_data(Rc::new(UserDataInner { cmd: cmd_manager }));
 
@@ -160,16 +302,31 @@ impl Application {
 
         let queueview = ui::queue::QueueView::new(queue.clone(), library.clone());
 
+        let statsview = ui::stats::StatsView::new(queue.clone(), library.clone());
+
+        let lyrics_directory = configuration
+            .values()
+            .lyrics_directory
+            .as_deref()
+            .map(utils::expand_home)
+            .unwrap_or_else(|| config::config_path("lyrics"));
+        let lyricsview = ui::lyrics::LyricsView::new(
+            queue.clone(),
+            vec![Box::new(LocalLyrics::new(lyrics_directory))],
+        );
+
         #[cfg(feature = "cover")]
         let coverview = ui::cover::CoverView::new(queue.clone(), library.clone(), &configuration);
 
         let status = ui::statusbar::StatusBar::new(queue.clone(), Arc::clone(&library));
 
         let mut layout =
-            ui::layout::Layout::new(status, &event_manager, theme, Arc::clone(&configuration))
+            ui::layout::Layout::new(status, event_manager, theme, Arc::clone(&configuration))
                 .screen("search", search.with_name("search"))
                 .screen("library", libraryview.with_name("library"))
-                .screen("queue", queueview);
+                .screen("queue", queueview)
+                .screen("lyrics", lyricsview)
+                .screen("stats", statsview);
 
         #[cfg(feature = "cover")]
         layout.add_screen("cover", coverview.with_name("cover"));
@@ -188,17 +345,6 @@ impl Application {
         }
 
         cursive.add_fullscreen_layer(layout.with_name("main"));
-
-        Ok(Self {
-            queue,
-            spotify,
-            event_manager,
-            #[cfg(feature = "mpris")]
-            mpris_manager,
-            #[cfg(unix)]
-            ipc,
-            cursive,
-        })
     }
 
     /// Start the application and run the event loop.
@@ -207,52 +353,222 @@ impl Application {
         let mut signals =
             Signals::new([SIGTERM, SIGHUP]).expect("could not register signal handler");
 
-        // cursive event loop
-        while self.cursive.is_running() {
-            self.cursive.step();
+        while self.is_running() {
+            match self.cursive.as_mut() {
+                // cursive event loop
+                Some(cursive) => {
+                    cursive.step();
+                }
+                None => {
+                    if let Some(event) = self.event_manager.recv_timeout(DAEMON_POLL_INTERVAL) {
+                        self.handle_event(event);
+                    }
+                }
+            }
             #[cfg(unix)]
             for signal in signals.pending() {
                 if signal == SIGTERM || signal == SIGHUP {
                     info!("Caught {}, cleaning up and closing", signal);
-                    if let Some(data) = self.cursive.user_data::<UserData>().cloned() {
-                        data.cmd.handle(&mut self.cursive, Command::Quit);
+                    if let Err(e) = self.execute(Command::Quit) {
+                        error!("Could not quit cleanly: {e}");
                     }
                 }
             }
-            for event in self.event_manager.msg_iter() {
-                match event {
-                    Event::Player(state) => {
-                        trace!("event received: {:?}", state);
-                        self.spotify.update_status(state.clone());
+            let event_manager = self.event_manager.clone();
+            for event in event_manager.msg_iter() {
+                self.handle_event(event);
+            }
 
-                        #[cfg(feature = "mpris")]
-                        self.mpris_manager.update();
+            self.update_sleep_timer();
+            self.update_alarms();
 
-                        #[cfg(unix)]
-                        self.ipc.publish(&state, self.queue.get_current());
+            #[cfg(unix)]
+            if std::mem::take(&mut self.ipc_outdated) {
+                self.ipc.update();
+            }
 
-                        if state == PlayerEvent::FinishedTrack {
-                            self.queue.next(false);
-                        }
-                    }
-                    Event::Queue(event) => {
-                        self.queue.handle_event(event);
+            #[cfg(feature = "mpris")]
+            if std::mem::take(&mut self.tracklist_outdated) {
+                self.mpris_manager.update_tracklist();
+            }
+        }
+
+        // The current item was played until now.
+        if let Some(play) = self.play_tracker.finish() {
+            self.record_play(play);
+        }
+        Ok(())
+    }
+
+    /// Add `play` to the listening history and submit it as a listen.
+    fn record_play(&self, play: Play) {
+        if let Some(scrobbler) = self.scrobbler.as_ref() {
+            scrobbler.submit(&play);
+        }
+        self.library.add_play(play);
+    }
+
+    /// Start playback for the configured alarms that are due.
+    fn update_alarms(&mut self) {
+        let values = self.library.cfg.values();
+        let alarms = values.alarms.as_deref().unwrap_or_default();
+        for event in self.alarm_scheduler.poll(alarms) {
+            match event {
+                AlarmEvent::Ring(alarm) => alarm::ring(&alarm, self.queue.clone()),
+                AlarmEvent::Missed(alarm, due) => alarm::log_missed(&alarm, due),
+            }
+        }
+    }
+
+    /// Fade out or stop playback when the sleep timer runs out.
+    fn update_sleep_timer(&self) {
+        let track_remaining = self.queue.get_current().map(|playable| {
+            Duration::from_millis(playable.duration() as u64)
+                .saturating_sub(self.spotify.get_current_progress())
+        });
+        match self.spotify.sleep_timer.update(
+            SystemTime::now(),
+            self.spotify.volume(),
+            track_remaining,
+        ) {
+            Some(SleepAction::Fade(volume)) => self.spotify.set_volume(volume),
+            Some(SleepAction::Stop(volume)) => self.sleep(volume),
+            None => {}
+        }
+    }
+
+    /// Stop playback for the sleep timer and restore the volume from before fading out.
+    fn sleep(&self, volume: Option<u16>) {
+        info!("Sleep timer ran out, stopping playback");
+        self.spotify.stop();
+        if let Some(volume) = volume {
+            self.spotify.set_volume(volume);
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        match &self.cursive {
+            Some(cursive) => cursive.is_running(),
+            None => self.running,
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        // Any event can change the state that is published to IPC clients.
+        #[cfg(unix)]
+        {
+            self.ipc_outdated = true;
+        }
+
+        match event {
+            Event::Player(state) => {
+                trace!("event received: {:?}", state);
+                self.spotify.update_status(state.clone());
+
+                #[cfg(feature = "mpris")]
+                self.mpris_manager.update();
+
+                #[cfg(unix)]
+                self.ipc.publish(&state, self.queue.get_current());
+
+                #[cfg(feature = "connect")]
+                if let Some(connect) = &self.connect {
+                    connect.update(&self.queue);
+                }
+
+                let current = self.queue.get_current();
+                if let Some(play) = self.play_tracker.update(&state, current.as_ref()) {
+                    self.record_play(play);
+                }
+
+                if state == PlayerEvent::FinishedTrack {
+                    match self.spotify.sleep_timer.track_finished() {
+                        Some(SleepAction::Stop(volume)) => self.sleep(volume),
+                        _ => self.queue.next(false),
                     }
-                    Event::SessionDied => self.spotify.start_worker(None),
-                    Event::IpcInput(input) => match command::parse(&input) {
-                        Ok(commands) => {
-                            if let Some(data) = self.cursive.user_data::<UserData>().cloned() {
-                                for cmd in commands {
-                                    info!("Executing command from IPC: {cmd}");
-                                    data.cmd.handle(&mut self.cursive, cmd);
-                                }
-                            }
+                }
+            }
+            Event::Queue(event) => {
+                self.queue.handle_event(event);
+            }
+            Event::QueueChanged => {
+                #[cfg(feature = "mpris")]
+                {
+                    self.tracklist_outdated = true;
+                }
+            }
+            Event::SessionDied => self.spotify.start_worker(None),
+            Event::StateChanged => {}
+            Event::Quit => {
+                info!("Quit requested, cleaning up and closing");
+                if let Err(e) = self.execute(Command::Quit) {
+                    error!("Could not quit cleanly: {e}");
+                }
+            }
+            #[cfg_attr(not(feature = "mpris"), allow(unused_variables))]
+            Event::PlaylistUpdated(id) => {
+                #[cfg(feature = "mpris")]
+                self.mpris_manager.playlist_changed(id);
+            }
+            Event::IpcInput(input) => match command::parse(&input) {
+                Ok(commands) => {
+                    for cmd in commands {
+                        info!("Executing command from IPC: {cmd}");
+                        if let Err(e) = self.execute(cmd) {
+                            error!("Error executing command from IPC: {e}");
                         }
-                        Err(e) => error!("Parsing error: {e}"),
-                    },
+                    }
+                }
+                Err(e) => error!("Parsing error: {e}"),
+            },
+            Event::IpcCommand(input, reply) => {
+                let result = self.execute_ipc_command(&input);
+                if reply.send(result).is_err() {
+                    error!("Could not reply to IPC request, client is gone");
+                }
+            }
+            #[cfg(feature = "connect")]
+            Event::Connect(event) => {
+                if let Some(connect) = &self.connect {
+                    connect.handle(event, &self.queue);
                 }
             }
         }
-        Ok(())
+    }
+
+    /// Execute `cmd` through the user interface, or through the command executor when running
+    /// without one.
+    fn execute(&mut self, cmd: Command) -> Result<Option<String>, String> {
+        match self.cursive.as_mut() {
+            Some(cursive) => {
+                let data = cursive
+                    .user_data::<UserData>()
+                    .cloned()
+                    .ok_or_else(|| String::from("Command manager is not available"))?;
+                data.cmd.handle_with_result(cursive, cmd)
+            }
+            None => {
+                let result = self.executor.execute(&cmd);
+                if let (Command::Quit, Ok(_)) = (&cmd, &result) {
+                    self.running = false;
+                }
+                result
+            }
+        }
+    }
+
+    /// Parse and execute the commands in `input`, stopping at the first command that fails.
+    /// Returns the output of the last command that produced any.
+    fn execute_ipc_command(&mut self, input: &str) -> Result<Option<String>, String> {
+        let commands = command::parse(input).map_err(|e| e.to_string())?;
+
+        let mut output = None;
+        for cmd in commands {
+            info!("Executing command from IPC request: {cmd}");
+            if let Some(message) = self.execute(cmd)? {
+                output = Some(message);
+            }
+        }
+        Ok(output)
     }
 }
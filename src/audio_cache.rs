@@ -0,0 +1,320 @@
+//! The tracks whose audio files are in the audio cache of librespot, together with the keys to
+//! decrypt them, so that they can be played without a connection to Spotify.
+//!
+//! librespot only caches the encrypted audio files. The id of the file that is played and its key
+//! are fetched when a track is loaded, and the track counts as cached once its file is complete
+//! in the cache.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use librespot_audio::AudioDecrypt;
+use librespot_core::audio_key::AudioKey;
+use librespot_core::cache::Cache;
+use librespot_core::spotify_id::FileId;
+use librespot_metadata::FileFormat;
+use librespot_playback::config::Bitrate;
+use log::{debug, error};
+
+use crate::config::{self, Config};
+
+const CACHED_FILES: &str = "cached_files.db";
+
+/// The size of the header Spotify puts in front of the Ogg stream of its audio files.
+const HEADER_SIZE: u64 = 0xa7;
+
+/// The audio file of a track and the key to decrypt it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedFile {
+    /// The id of the file, in hexadecimal.
+    file_id: String,
+    /// The key to decrypt the file, in hexadecimal.
+    key: String,
+}
+
+impl CachedFile {
+    pub fn new(file_id: FileId, key: AudioKey) -> Self {
+        Self {
+            file_id: to_hex(&file_id.0),
+            key: to_hex(&key.0),
+        }
+    }
+
+    fn file_id(&self) -> Option<FileId> {
+        from_hex(&self.file_id).map(FileId)
+    }
+
+    fn key(&self) -> Option<AudioKey> {
+        from_hex(&self.key).map(AudioKey)
+    }
+}
+
+/// The cached tracks, shared by every clone.
+#[derive(Clone, Default)]
+pub struct AudioCache {
+    /// The audio cache of librespot, None if it is disabled.
+    cache: Option<Arc<Cache>>,
+    /// Where the list of cached files is saved.
+    path: Option<PathBuf>,
+    /// The files of the cached tracks by the id of the track.
+    files: Arc<RwLock<HashMap<String, CachedFile>>>,
+    /// The files of tracks that were loaded, which are moved to `files` once they are complete.
+    pending: Arc<RwLock<HashMap<String, CachedFile>>>,
+}
+
+impl AudioCache {
+    /// Open the audio cache if it is enabled in `cfg`, forgetting the files that were removed from
+    /// it since.
+    pub fn new(cfg: &Config) -> Self {
+        if !cfg.values().audio_cache.unwrap_or(true) {
+            return Self::default();
+        }
+        let directory = config::cache_path("librespot").join("files");
+        match Cache::new(None::<PathBuf>, None, Some(directory), None) {
+            Ok(cache) => Self::open(cache, config::cache_path(CACHED_FILES)),
+            Err(e) => {
+                error!("could not open the audio cache: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    fn open(cache: Cache, path: PathBuf) -> Self {
+        let files: HashMap<String, CachedFile> = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        let audio_cache = Self {
+            cache: Some(Arc::new(cache)),
+            path: Some(path),
+            ..Default::default()
+        };
+        let complete: HashMap<_, _> = files
+            .into_iter()
+            .filter(|(_, file)| audio_cache.is_complete(file))
+            .collect();
+        debug!("{} tracks are in the audio cache", complete.len());
+        *audio_cache.files.write().unwrap() = complete;
+        audio_cache
+    }
+
+    /// Whether the audio cache is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.cache.is_some()
+    }
+
+    /// Whether the track with `id` is in the cache.
+    pub fn contains(&self, id: &str) -> bool {
+        self.files.read().unwrap().contains_key(id)
+    }
+
+    /// Remember the file of the track with `id`, which is cached once it is complete.
+    pub fn add(&self, id: String, file: CachedFile) {
+        if !self.contains(&id) {
+            self.pending.write().unwrap().insert(id, file);
+            self.update();
+        }
+    }
+
+    /// Mark the tracks whose files were completed since as cached.
+    pub fn update(&self) {
+        let mut pending = self.pending.write().unwrap();
+        let complete: Vec<String> = pending
+            .iter()
+            .filter(|(_, file)| self.is_complete(file))
+            .map(|(id, _)| id.clone())
+            .collect();
+        if complete.is_empty() {
+            return;
+        }
+
+        let mut files = self.files.write().unwrap();
+        for id in complete {
+            if let Some(file) = pending.remove(&id) {
+                files.insert(id, file);
+            }
+        }
+        if let Some(path) = &self.path {
+            match serde_json::to_string(&*files) {
+                Ok(contents) => {
+                    if let Err(e) = std::fs::write(path, contents) {
+                        error!("could not save the cached files: {}", e);
+                    }
+                }
+                Err(e) => error!("could not save the cached files: {}", e),
+            }
+        }
+    }
+
+    fn is_complete(&self, file: &CachedFile) -> bool {
+        match (&self.cache, file.file_id()) {
+            (Some(cache), Some(file_id)) => cache.file(file_id).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Open the decrypted audio of the track with `id`.
+    pub fn audio(&self, id: &str) -> Result<CachedAudio, String> {
+        let file = self
+            .files
+            .read()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| format!("{id} isn't in the audio cache"))?;
+        let (file_id, key) = file
+            .file_id()
+            .zip(file.key())
+            .ok_or_else(|| format!("invalid cached file of {id}"))?;
+        let encrypted = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.file(file_id))
+            .ok_or_else(|| format!("the file of {id} was removed from the audio cache"))?;
+        CachedAudio::new(AudioDecrypt::new(key, encrypted)).map_err(|e| e.to_string())
+    }
+}
+
+/// The formats of the files librespot plays at `bitrate`, in the order it prefers them.
+pub fn file_formats(bitrate: Bitrate) -> [FileFormat; 3] {
+    match bitrate {
+        Bitrate::Bitrate96 => [
+            FileFormat::OGG_VORBIS_96,
+            FileFormat::OGG_VORBIS_160,
+            FileFormat::OGG_VORBIS_320,
+        ],
+        Bitrate::Bitrate160 => [
+            FileFormat::OGG_VORBIS_160,
+            FileFormat::OGG_VORBIS_96,
+            FileFormat::OGG_VORBIS_320,
+        ],
+        Bitrate::Bitrate320 => [
+            FileFormat::OGG_VORBIS_320,
+            FileFormat::OGG_VORBIS_160,
+            FileFormat::OGG_VORBIS_96,
+        ],
+    }
+}
+
+/// The decrypted Ogg stream of a cached audio file.
+pub struct CachedAudio {
+    stream: AudioDecrypt<File>,
+    len: u64,
+}
+
+impl CachedAudio {
+    fn new(mut stream: AudioDecrypt<File>) -> io::Result<Self> {
+        let len = stream.seek(SeekFrom::End(0))?.saturating_sub(HEADER_SIZE);
+        stream.seek(SeekFrom::Start(HEADER_SIZE))?;
+        Ok(Self { stream, len })
+    }
+
+    /// The length of the stream in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+impl Read for CachedAudio {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Seek for CachedAudio {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => SeekFrom::Start(offset + HEADER_SIZE),
+            pos => pos,
+        };
+        Ok(self.stream.seek(pos)?.saturating_sub(HEADER_SIZE))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_path;
+
+    const KEY: AudioKey = AudioKey([7; 16]);
+
+    fn open_cache(directory: &std::path::Path) -> Cache {
+        Cache::new(None::<PathBuf>, None, Some(directory.to_path_buf()), None).unwrap()
+    }
+
+    /// Put `contents` into the cache as the encrypted file `file_id`, after a header.
+    fn save_file(directory: &std::path::Path, file_id: FileId, contents: &[u8]) {
+        let cache = open_cache(directory);
+        let mut plain = vec![0; HEADER_SIZE as usize];
+        plain.extend_from_slice(contents);
+        // the cipher is symmetric, so decrypting encrypts the contents
+        let mut encrypted = Vec::new();
+        AudioDecrypt::new(KEY, io::Cursor::new(plain))
+            .read_to_end(&mut encrypted)
+            .unwrap();
+        cache.save_file(file_id, &mut io::Cursor::new(encrypted));
+    }
+
+    #[test]
+    fn converts_ids_to_and_from_hex() {
+        let file = CachedFile::new(FileId([0xab; 20]), KEY);
+        assert_eq!(file.file_id, "ab".repeat(20));
+        assert_eq!(file.file_id(), Some(FileId([0xab; 20])));
+        assert_eq!(file.key().map(|key| key.0), Some(KEY.0));
+        assert_eq!(from_hex::<2>("abc"), None);
+        assert_eq!(from_hex::<2>("zzzz"), None);
+    }
+
+    #[test]
+    fn caches_tracks_once_their_file_is_complete() {
+        let directory = temp_path("audio-cache");
+        std::fs::create_dir_all(&directory).unwrap();
+        let cache = open_cache(&directory);
+        let audio_cache = AudioCache::open(cache, directory.join(CACHED_FILES));
+
+        audio_cache.add("track".into(), CachedFile::new(FileId([1; 20]), KEY));
+        assert!(!audio_cache.contains("track"));
+        assert!(audio_cache.audio("track").is_err());
+
+        save_file(&directory, FileId([1; 20]), b"OggS audio");
+        audio_cache.update();
+        assert!(audio_cache.contains("track"));
+
+        let mut audio = audio_cache.audio("track").unwrap();
+        assert_eq!(audio.len(), 10);
+        let mut contents = String::new();
+        audio.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "OggS audio");
+        assert_eq!(audio.seek(SeekFrom::Start(5)).unwrap(), 5);
+        contents.clear();
+        audio.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "audio");
+
+        // the list of cached files is saved, and files that were removed are forgotten
+        let cache = open_cache(&directory);
+        assert!(AudioCache::open(cache, directory.join(CACHED_FILES)).contains("track"));
+        let cache = open_cache(&directory);
+        assert!(cache.remove_file(FileId([1; 20])).is_ok());
+        assert!(!AudioCache::open(cache, directory.join(CACHED_FILES)).contains("track"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -7,17 +7,27 @@ use cursive::Cursive;
 
 use librespot_core::authentication::Credentials as RespotCredentials;
 use librespot_core::cache::Cache;
+use librespot_core::session::SessionError;
 use librespot_protocol::authentication::AuthenticationType;
-use log::info;
+use log::{info, warn};
 
 use crate::config::{self, Config};
 use crate::spotify::Spotify;
 use crate::ui::create_cursive;
 
+const NO_CREDENTIALS_ERROR: &str = "No cached credentials found. Log in by running ncspot once \
+without --daemon, or configure the credential commands.";
+
 /// Get credentials for use with librespot. This first tries to get cached credentials. If no cached
 /// credentials are available, it will either try to get them from the user configured commands, or
-/// if that fails, it will prompt the user on stdout.
-pub fn get_credentials(configuration: &Config) -> Result<RespotCredentials, String> {
+/// if that fails, it will prompt the user on stdout. The user is never prompted if `interactive` is
+/// false, an error is returned instead. Cached credentials are used without testing them if Spotify
+/// can't be reached, so that cached tracks can be played offline.
+pub fn get_credentials(
+    configuration: &Config,
+    interactive: bool,
+) -> Result<RespotCredentials, String> {
+    let mut cached = false;
     let mut credentials = {
         let cache = Cache::new(Some(config::cache_path("librespot")), None, None, None)
             .expect("Could not create librespot cache");
@@ -25,6 +35,7 @@ pub fn get_credentials(configuration: &Config) -> Result<RespotCredentials, Stri
         match cached_credentials {
             Some(c) => {
                 info!("Using cached credentials");
+                cached = true;
                 c
             }
             None => {
@@ -39,14 +50,22 @@ pub fn get_credentials(configuration: &Config) -> Result<RespotCredentials, Stri
                     (Some(username_cmd), Some(password_cmd)) => {
                         credentials_eval(&username_cmd, &password_cmd)?
                     }
-                    _ => credentials_prompt(None)?,
+                    _ if interactive => credentials_prompt(None)?,
+                    _ => return Err(NO_CREDENTIALS_ERROR.into()),
                 }
             }
         }
     };
 
     while let Err(error) = Spotify::test_credentials(credentials.clone()) {
+        if cached && matches!(error, SessionError::IoError(_)) {
+            warn!("Could not connect to Spotify, starting offline: {error}");
+            break;
+        }
         let error_msg = format!("{error}");
+        if !interactive {
+            return Err(format!("Connection error: {error_msg}"));
+        }
         credentials = credentials_prompt(Some(error_msg))?;
     }
     Ok(credentials)
@@ -0,0 +1,172 @@
+//! A small client for the IPC socket of an already running ncspot instance, used by the
+//! command line subcommands.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+use serde_json::{json, Value};
+
+use crate::config;
+use crate::ipc::PROTOCOL_VERSION;
+use crate::model::playable::Playable;
+
+/// The id used for the single request a client invocation sends.
+const REQUEST_ID: u64 = 1;
+
+/// Run the client subcommand `name` with its arguments `matches`. Returns the exit code of the
+/// process.
+pub fn run(name: &str, matches: &ArgMatches) -> i32 {
+    let result = match name {
+        "send" => {
+            let command = matches
+                .get_many::<String>("command")
+                .unwrap_or_default()
+                .cloned()
+                .collect::<Vec<String>>()
+                .join(" ");
+            request("command", json!({ "command": command })).map(|result| {
+                if let Some(output) = result.as_str() {
+                    println!("{output}");
+                }
+            })
+        }
+        "status" => request("get_status", Value::Null).map(|result| {
+            if matches.get_flag("json") {
+                println!("{result}");
+            } else {
+                println!("{}", format_status(&result));
+            }
+        }),
+        "queue" => match matches.subcommand() {
+            Some(("add", add_matches)) => {
+                let uri = add_matches
+                    .get_one::<String>("uri")
+                    .expect("uri is required");
+                request("queue_add", json!({ "uri": uri })).map(|result| {
+                    if let Some(added) = result.as_str() {
+                        println!("Added {added}");
+                    }
+                })
+            }
+            Some(("list", _)) => request("get_queue", Value::Null).map(|result| {
+                print!("{}", format_queue(&result));
+            }),
+            _ => Err(String::from("Unknown queue subcommand")),
+        },
+        _ => Err(format!("Unknown subcommand \"{name}\"")),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{e}");
+            1
+        }
+    }
+}
+
+/// Find the sockets of running instances. The default socket comes first, followed by the
+/// sockets of additional instances (`ncspot.<pid>.sock`), newest first.
+fn socket_candidates() -> Vec<PathBuf> {
+    let default = config::cache_path("ncspot.sock");
+    let mut candidates = vec![default.clone()];
+
+    if let Some(Ok(entries)) = default.parent().map(std::fs::read_dir) {
+        let mut others: Vec<(std::time::SystemTime, PathBuf)> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with("ncspot.") && name.ends_with(".sock") && name != "ncspot.sock"
+            })
+            .filter_map(|entry| {
+                let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+                Some((modified, entry.path()))
+            })
+            .collect();
+        others.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+        candidates.extend(others.into_iter().map(|(_, path)| path));
+    }
+
+    candidates
+}
+
+/// Connect to the first socket of a running instance that accepts connections.
+fn connect() -> Result<UnixStream, String> {
+    socket_candidates()
+        .into_iter()
+        .find_map(|path| UnixStream::connect(path).ok())
+        .ok_or_else(|| String::from("Could not connect to ncspot, is it running?"))
+}
+
+/// Send a single request calling `method` with `params` and wait for its response.
+fn request(method: &str, params: Value) -> Result<Value, String> {
+    let mut stream = connect()?;
+
+    let mut request = json!({
+        "version": PROTOCOL_VERSION,
+        "id": REQUEST_ID,
+        "method": method,
+    });
+    if !params.is_null() {
+        request["params"] = params;
+    }
+    writeln!(stream, "{request}").map_err(|e| e.to_string())?;
+
+    // The instance also sends status updates over the connection, which have no id and are
+    // skipped.
+    let reader = BufReader::new(stream);
+    for line in reader.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        let response: Value = match serde_json::from_str(&line) {
+            Ok(response) => response,
+            Err(_) => continue,
+        };
+        if response.get("id") != Some(&json!(REQUEST_ID)) {
+            continue;
+        }
+        if let Some(error) = response.get("error") {
+            return Err(error.as_str().unwrap_or_default().to_string());
+        }
+        return Ok(response.get("result").cloned().unwrap_or(Value::Null));
+    }
+
+    Err(String::from(
+        "Connection closed before a response was received",
+    ))
+}
+
+/// Format the result of a `get_status` request for humans, i.e. `Playing: Artist - Title`.
+fn format_status(status: &Value) -> String {
+    let mode = match &status["mode"] {
+        Value::String(mode) => mode.clone(),
+        Value::Object(mode) => mode.keys().next().cloned().unwrap_or_default(),
+        _ => String::new(),
+    };
+
+    match serde_json::from_value::<Playable>(status["playable"].clone()) {
+        Ok(playable) => format!("{mode}: {playable}"),
+        Err(_) => mode,
+    }
+}
+
+/// Format the result of a `get_queue` request for humans, one item per line with the current
+/// item marked.
+fn format_queue(queue: &Value) -> String {
+    let current = queue["current"].as_u64().map(|index| index as usize);
+    let items = queue["items"].as_array().cloned().unwrap_or_default();
+
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let marker = if Some(index) == current { ">" } else { " " };
+            match serde_json::from_value::<Playable>(item) {
+                Ok(playable) => format!("{marker} {:>3}. {playable}\n", index + 1),
+                Err(_) => format!("{marker} {:>3}. <unknown>\n", index + 1),
+            }
+        })
+        .collect()
+}
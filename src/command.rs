@@ -1,5 +1,6 @@
 use crate::queue::RepeatSetting;
 use crate::spotify_url::SpotifyUrl;
+use crate::time_stretch::{MAX_SPEED, MIN_SPEED};
 use std::collections::HashMap;
 use std::fmt;
 
@@ -83,6 +84,36 @@ pub enum GotoMode {
     Artist,
 }
 
+/// The period of time that listening statistics are shown for.
+#[derive(Display, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum StatsWindow {
+    Week,
+    Month,
+    All,
+}
+
+/// What the sleep timer waits for before stopping playback.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub enum SleepMode {
+    Off,
+    Duration(std::time::Duration),
+    EndOfTrack,
+    Tracks(u32),
+}
+
+impl fmt::Display for SleepMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SleepMode::Off => write!(f, "off"),
+            SleepMode::Duration(duration) => write!(f, "{}s", duration.as_secs()),
+            SleepMode::EndOfTrack => write!(f, "end-of-track"),
+            SleepMode::Tracks(tracks) => write!(f, "after {tracks}"),
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum SeekDirection {
     Relative(i32),
@@ -131,6 +162,7 @@ pub enum Command {
     PlayNext,
     Play,
     UpdateLibrary,
+    Rescan,
     Save,
     SaveCurrent,
     SaveQueue,
@@ -143,6 +175,7 @@ pub enum Command {
     VolumeDown(u16),
     Repeat(Option<RepeatSetting>),
     Shuffle(Option<bool>),
+    Offline(Option<bool>),
     #[cfg(feature = "share_clipboard")]
     Share(TargetMode),
     Back,
@@ -157,6 +190,16 @@ pub enum Command {
     Noop,
     Insert(InsertSource),
     NewPlaylist(String),
+    ExportHistory(String),
+    Stats(StatsWindow),
+    Sleep(Option<SleepMode>),
+    Eq(Option<String>),
+    Speed(Option<f64>),
+    Volnorm(Option<bool>),
+    Outputs,
+    Output(Option<String>),
+    Devices,
+    Device(Option<String>),
     Sort(SortKey, SortDirection),
     Logout,
     ShowRecommendations(TargetMode),
@@ -177,7 +220,7 @@ impl fmt::Display for Command {
                 Some(mode) => vec![mode.to_string()],
                 None => vec![],
             },
-            Command::Shuffle(on) => match on {
+            Command::Shuffle(on) | Command::Offline(on) | Command::Volnorm(on) => match on {
                 Some(b) => vec![(if *b { "on" } else { "off" }).into()],
                 None => vec![],
             },
@@ -202,9 +245,26 @@ impl fmt::Display for Command {
             },
             Command::Insert(source) => vec![source.to_string()],
             Command::NewPlaylist(name) => vec![name.to_owned()],
+            Command::ExportHistory(path) => vec![path.to_owned()],
+            Command::Stats(window) => vec![window.to_string()],
+            Command::Sleep(mode) => match mode {
+                Some(mode) => vec![mode.to_string()],
+                None => vec![],
+            },
+            Command::Eq(preset) => vec![preset.clone().unwrap_or_else(|| "off".to_string())],
+            Command::Speed(speed) => match speed {
+                Some(speed) => vec![speed.to_string()],
+                None => vec![],
+            },
             Command::Sort(key, direction) => vec![key.to_string(), direction.to_string()],
             Command::ShowRecommendations(mode) => vec![mode.to_string()],
             Command::Execute(cmd) => vec![cmd.to_owned()],
+            Command::Output(device) => {
+                vec![device.clone().unwrap_or_else(|| "default".to_string())]
+            }
+            Command::Device(device) => {
+                vec![device.clone().unwrap_or_else(|| "local".to_string())]
+            }
             Command::Quit
             | Command::TogglePlay
             | Command::Stop
@@ -215,6 +275,7 @@ impl fmt::Display for Command {
             | Command::PlayNext
             | Command::Play
             | Command::UpdateLibrary
+            | Command::Rescan
             | Command::Save
             | Command::SaveCurrent
             | Command::SaveQueue
@@ -223,6 +284,8 @@ impl fmt::Display for Command {
             | Command::Delete
             | Command::Back
             | Command::Help
+            | Command::Outputs
+            | Command::Devices
             | Command::ReloadConfig
             | Command::Noop
             | Command::Logout
@@ -247,6 +310,7 @@ impl Command {
             Command::PlayNext => "playnext",
             Command::Play => "play",
             Command::UpdateLibrary => "update",
+            Command::Rescan => "rescan",
             Command::Save => "save",
             Command::SaveCurrent => "save current",
             Command::SaveQueue => "save queue",
@@ -259,6 +323,7 @@ impl Command {
             Command::VolumeDown(_) => "voldown",
             Command::Repeat(_) => "repeat",
             Command::Shuffle(_) => "shuffle",
+            Command::Offline(_) => "offline",
             #[cfg(feature = "share_clipboard")]
             Command::Share(_) => "share",
             Command::Back => "back",
@@ -275,6 +340,16 @@ impl Command {
             Command::Noop => "noop",
             Command::Insert(_) => "insert",
             Command::NewPlaylist(_) => "newplaylist",
+            Command::ExportHistory(_) => "exporthistory",
+            Command::Stats(_) => "stats",
+            Command::Sleep(_) => "sleep",
+            Command::Eq(_) => "eq",
+            Command::Speed(_) => "speed",
+            Command::Volnorm(_) => "volnorm",
+            Command::Outputs => "outputs",
+            Command::Output(_) => "output",
+            Command::Devices => "devices",
+            Command::Device(_) => "device",
             Command::Sort(_, _) => "sort",
             Command::Logout => "logout",
             Command::ShowRecommendations(_) => "similar",
@@ -412,6 +487,7 @@ pub fn parse(input: &str) -> Result<Vec<Command>, CommandParseError> {
                 "playnext" => Command::PlayNext,
                 "play" => Command::Play,
                 "update" => Command::UpdateLibrary,
+                "rescan" => Command::Rescan,
                 "add" => match args.first().cloned() {
                     Some("current") => Ok(Command::AddCurrent),
                     Some(arg) => Err(BadEnumArg {
@@ -547,6 +623,19 @@ pub fn parse(input: &str) -> Result<Vec<Command>, CommandParseError> {
                     }?;
                     Command::Shuffle(switch)
                 }
+                "offline" => {
+                    let switch = match args.first().cloned() {
+                        Some("on") => Ok(Some(true)),
+                        Some("off") => Ok(Some(false)),
+                        Some(arg) => Err(BadEnumArg {
+                            arg: arg.into(),
+                            accept: vec!["on".into(), "off".into()],
+                            optional: true,
+                        }),
+                        None => Ok(None),
+                    }?;
+                    Command::Offline(switch)
+                }
                 #[cfg(feature = "share_clipboard")]
                 "share" => {
                     let &target_mode_raw = args.first().ok_or(InsufficientArgs {
@@ -727,6 +816,140 @@ pub fn parse(input: &str) -> Result<Vec<Command>, CommandParseError> {
                         })
                     }?
                 }
+                "exporthistory" => {
+                    if !args.is_empty() {
+                        Ok(Command::ExportHistory(args.join(" ")))
+                    } else {
+                        Err(InsufficientArgs {
+                            cmd: command.into(),
+                            hint: Some("a .json or .csv file".into()),
+                        })
+                    }?
+                }
+                "stats" => {
+                    let &window_raw = args.first().ok_or(InsufficientArgs {
+                        cmd: command.into(),
+                        hint: Some("week|month|all".into()),
+                    })?;
+                    let window = match window_raw {
+                        "week" => Ok(StatsWindow::Week),
+                        "month" => Ok(StatsWindow::Month),
+                        "all" => Ok(StatsWindow::All),
+                        _ => Err(BadEnumArg {
+                            arg: window_raw.into(),
+                            accept: vec!["week".into(), "month".into(), "all".into()],
+                            optional: false,
+                        }),
+                    }?;
+                    Command::Stats(window)
+                }
+                "sleep" => {
+                    let mode = match args.first().copied() {
+                        None => None,
+                        Some("off" | "cancel") => Some(SleepMode::Off),
+                        Some("end-of-track") => Some(SleepMode::EndOfTrack),
+                        Some("after") => {
+                            let &tracks_raw = args.get(1).ok_or(InsufficientArgs {
+                                cmd: command.into(),
+                                hint: Some("a number of tracks".into()),
+                            })?;
+                            let tracks =
+                                tracks_raw.parse::<u32>().map_err(|err| ArgParseError {
+                                    arg: tracks_raw.into(),
+                                    err: err.to_string(),
+                                })?;
+                            Some(SleepMode::Tracks(tracks))
+                        }
+                        Some(_) => {
+                            let duration_raw = args.join(" ");
+                            let too_long = || ArgParseError {
+                                arg: duration_raw.clone(),
+                                err: "the duration is too long".into(),
+                            };
+                            let duration = match duration_raw.parse::<u64>() {
+                                // accept raw minutes
+                                Ok(minutes) => minutes
+                                    .checked_mul(60)
+                                    .map(std::time::Duration::from_secs)
+                                    .ok_or_else(too_long),
+                                Err(_) => parse_duration::parse(&duration_raw).map_err(|err| {
+                                    ArgParseError {
+                                        arg: duration_raw.clone(),
+                                        err: err.to_string(),
+                                    }
+                                }),
+                            }?;
+                            // the timer has to tell when it runs out
+                            if std::time::SystemTime::now().checked_add(duration).is_none() {
+                                return Err(too_long());
+                            }
+                            Some(SleepMode::Duration(duration))
+                        }
+                    };
+                    Command::Sleep(mode)
+                }
+                "eq" => match args.join(" ").as_str() {
+                    "" => Err(InsufficientArgs {
+                        cmd: command.into(),
+                        hint: Some("a preset name or \"off\"".into()),
+                    }),
+                    "off" => Ok(Command::Eq(None)),
+                    preset => Ok(Command::Eq(Some(preset.to_string()))),
+                }?,
+                "speed" => {
+                    let speed = match args.first() {
+                        Some(&speed_raw) => {
+                            let speed =
+                                speed_raw
+                                    .trim_end_matches('x')
+                                    .parse::<f64>()
+                                    .map_err(|err| ArgParseError {
+                                        arg: speed_raw.into(),
+                                        err: err.to_string(),
+                                    })?;
+                            if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+                                return Err(ArgParseError {
+                                    arg: speed_raw.into(),
+                                    err: format!("must be between {MIN_SPEED} and {MAX_SPEED}"),
+                                });
+                            }
+                            Some(speed)
+                        }
+                        None => None,
+                    };
+                    Command::Speed(speed)
+                }
+                "outputs" => Command::Outputs,
+                "output" => match args.join(" ").as_str() {
+                    "" => Err(InsufficientArgs {
+                        cmd: command.into(),
+                        hint: Some("a device name or \"default\"".into()),
+                    }),
+                    "default" => Ok(Command::Output(None)),
+                    device => Ok(Command::Output(Some(device.to_string()))),
+                }?,
+                "devices" => Command::Devices,
+                "device" => match args.join(" ").as_str() {
+                    "" => Err(InsufficientArgs {
+                        cmd: command.into(),
+                        hint: Some("a device name or \"local\"".into()),
+                    }),
+                    "local" => Ok(Command::Device(None)),
+                    device => Ok(Command::Device(Some(device.to_string()))),
+                }?,
+                "volnorm" => {
+                    let switch = match args.first().cloned() {
+                        Some("on") => Ok(Some(true)),
+                        Some("off") => Ok(Some(false)),
+                        Some(arg) => Err(BadEnumArg {
+                            arg: arg.into(),
+                            accept: vec!["on".into(), "off".into()],
+                            optional: true,
+                        }),
+                        None => Ok(None),
+                    }?;
+                    Command::Volnorm(switch)
+                }
                 "sort" => {
                     let &key_raw = args.first().ok_or(InsufficientArgs {
                         cmd: command.into(),
@@ -1,12 +1,13 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use crate::application::UserData;
 use crate::command::{
     parse, Command, GotoMode, JumpMode, MoveAmount, MoveMode, SeekDirection, ShiftMode, TargetMode,
 };
 use crate::config::Config;
+use crate::devices;
 use crate::events::EventManager;
 use crate::ext_traits::CursiveExt;
 use crate::library::Library;
@@ -14,12 +15,14 @@ use crate::queue::{Queue, RepeatSetting};
 use crate::spotify::{Spotify, VOLUME_PERCENT};
 use crate::traits::{IntoBoxedViewExt, ListItem, ViewExt};
 use crate::ui::contextmenu::{
-    AddToPlaylistMenu, ContextMenu, SelectArtistActionMenu, SelectArtistMenu,
+    AddToPlaylistMenu, ContextMenu, SelectArtistActionMenu, SelectArtistMenu, SelectDeviceMenu,
+    SelectOutputMenu,
 };
 use crate::ui::help::HelpView;
 use crate::ui::layout::Layout;
 use crate::ui::modal::Modal;
 use crate::ui::search_results::SearchResultsView;
+use crate::utils;
 use cursive::event::{Event, Key};
 use cursive::traits::View;
 use cursive::views::Dialog;
@@ -34,9 +37,251 @@ pub enum CommandResult {
     Ignored,
 }
 
+/// Executes the commands that don't depend on the user interface, i.e. playback, queue, volume
+/// and library commands. This allows ncspot to be controlled when it runs without a UI.
+#[derive(Clone)]
+pub struct CommandExecutor {
+    spotify: Spotify,
+    queue: Arc<Queue>,
+    library: Arc<Library>,
+    config: Arc<Config>,
+}
+
+impl CommandExecutor {
+    pub fn new(
+        spotify: Spotify,
+        queue: Arc<Queue>,
+        library: Arc<Library>,
+        config: Arc<Config>,
+    ) -> CommandExecutor {
+        CommandExecutor {
+            spotify,
+            queue,
+            library,
+            config,
+        }
+    }
+
+    /// Save the queue and playback position, so they can be restored on the next start.
+    pub fn save_state(&self) {
+        let queue = self.queue.queue.read().expect("can't readlock queue");
+        self.config.with_state_mut(move |mut s| {
+            debug!(
+                "saving state, {} items, current track: {:?}",
+                queue.len(),
+                self.queue.get_current_index()
+            );
+            s.queuestate.queue = queue.clone();
+            s.queuestate.random_order = self.queue.get_random_order();
+            s.queuestate.current_track = self.queue.get_current_index();
+            s.queuestate.track_progress = self.spotify.get_current_progress();
+            // the queue of a Spotify Connect controller is only played for the time being
+            #[cfg(feature = "connect")]
+            if let Some(local) = self.queue.local_state() {
+                s.queuestate = local;
+            }
+        });
+        self.config.save_state();
+    }
+
+    /// Execute `cmd`, or return an error if it can only be executed with a user interface.
+    ///
+    /// Quitting only saves the state; stopping the program is up to the caller.
+    pub fn execute(&self, cmd: &Command) -> Result<Option<String>, String> {
+        // transport commands steer the controlled device instead of the local player
+        if devices::send_command(&self.spotify, cmd) {
+            return Ok(None);
+        }
+
+        match cmd {
+            Command::Noop => Ok(None),
+            Command::Quit => {
+                self.save_state();
+                Ok(None)
+            }
+            Command::Stop => {
+                self.queue.stop();
+                Ok(None)
+            }
+            Command::Previous => {
+                if self.spotify.get_current_progress() < Duration::from_secs(5) {
+                    self.queue.previous();
+                } else {
+                    self.spotify.seek(0);
+                }
+                Ok(None)
+            }
+            Command::Next => {
+                self.queue.next(true);
+                Ok(None)
+            }
+            Command::Clear => {
+                self.queue.clear();
+                Ok(None)
+            }
+            Command::UpdateLibrary => {
+                self.library.update_library();
+                Ok(None)
+            }
+            Command::Rescan => {
+                self.library.rescan_local_files();
+                Ok(None)
+            }
+            Command::TogglePlay => {
+                self.queue.toggleplayback();
+                Ok(None)
+            }
+            Command::Shuffle(mode) => {
+                let mode = mode.unwrap_or_else(|| !self.queue.get_shuffle());
+                self.queue.set_shuffle(mode);
+                Ok(None)
+            }
+            Command::Offline(mode) => {
+                let mode = mode.unwrap_or_else(|| !self.queue.get_offline());
+                self.queue.set_offline(mode);
+                Ok(None)
+            }
+            Command::Repeat(mode) => {
+                let mode = mode.unwrap_or_else(|| match self.queue.get_repeat() {
+                    RepeatSetting::None => RepeatSetting::RepeatPlaylist,
+                    RepeatSetting::RepeatPlaylist => RepeatSetting::RepeatTrack,
+                    RepeatSetting::RepeatTrack => RepeatSetting::None,
+                });
+
+                self.queue.set_repeat(mode);
+                Ok(None)
+            }
+            Command::Seek(direction) => {
+                match *direction {
+                    SeekDirection::Relative(rel) => self.spotify.seek_relative(rel),
+                    SeekDirection::Absolute(abs) => self.spotify.seek(abs),
+                }
+                Ok(None)
+            }
+            Command::VolumeUp(amount) => {
+                let volume = self
+                    .spotify
+                    .volume()
+                    .saturating_add(VOLUME_PERCENT * amount);
+                self.spotify.set_volume(volume);
+                Ok(None)
+            }
+            Command::VolumeDown(amount) => {
+                let volume = self
+                    .spotify
+                    .volume()
+                    .saturating_sub(VOLUME_PERCENT * amount);
+                debug!("vol {}", volume);
+                self.spotify.set_volume(volume);
+                Ok(None)
+            }
+            Command::ReloadConfig => {
+                self.config.reload();
+                // the active preset may have been changed or removed
+                let preset = self.spotify.equalizer.preset_name();
+                if let Err(e) = self.spotify.set_eq_preset(preset.as_deref()) {
+                    self.spotify.set_eq_preset(None)?;
+                    return Err(e);
+                }
+                Ok(None)
+            }
+            Command::NewPlaylist(name) => {
+                match self.spotify.api.create_playlist(name, None, None) {
+                    Some(_) => self.library.update_library(),
+                    None => error!("could not create playlist {}", name),
+                }
+                Ok(None)
+            }
+            Command::ExportHistory(path) => {
+                let path = utils::expand_home(path);
+                self.library.export_history(&path)?;
+                Ok(Some(format!("History exported to {}", path.display())))
+            }
+            Command::Sleep(mode) => {
+                let timer = &self.spotify.sleep_timer;
+                let now = SystemTime::now();
+                if let Some(mode) = mode {
+                    // cancelling while fading out restores the volume
+                    if let Some(volume) = timer.set(mode, now)? {
+                        self.spotify.set_volume(volume);
+                    }
+                }
+                Ok(Some(match timer.status(now) {
+                    Some(status) => status.to_string(),
+                    None => "Sleep timer is off".to_string(),
+                }))
+            }
+            Command::Eq(preset) => {
+                self.spotify.set_eq_preset(preset.as_deref())?;
+                Ok(Some(match preset {
+                    Some(preset) => format!("Equalizer preset: {preset}"),
+                    None => "Equalizer is off".to_string(),
+                }))
+            }
+            Command::Speed(speed) => {
+                if let Some(speed) = speed {
+                    self.queue.set_speed(*speed);
+                }
+                Ok(Some(format!("Playback speed: {}x", self.spotify.speed())))
+            }
+            Command::Output(device) => {
+                // the devices of some backends can't be listed, but they can still be switched to
+                if let (Some(device), Ok(devices)) = (device, self.spotify.output_devices()) {
+                    if !devices.iter().any(|d| &d.name == device) {
+                        return Err(format!("Unknown output device \"{device}\""));
+                    }
+                }
+                self.spotify.set_output_device(device.clone());
+                Ok(Some(format!(
+                    "Output device: {}",
+                    self.spotify.output_device().as_deref().unwrap_or("default")
+                )))
+            }
+            Command::Device(name) => {
+                devices::switch(&self.spotify, name.clone());
+                Ok(Some(match name {
+                    Some(name) => format!("Switching to {name}"),
+                    None => "Playing on ncspot".to_string(),
+                }))
+            }
+            Command::Volnorm(mode) => {
+                let mode = mode.unwrap_or_else(|| !self.spotify.volnorm());
+                self.spotify.set_volnorm(mode);
+                Ok(Some(format!(
+                    "Volume normalization is {}",
+                    if mode { "on" } else { "off" }
+                )))
+            }
+            Command::Execute(cmd) => {
+                log::info!("Executing command: {}", cmd);
+                let cmd = std::ffi::CString::new(cmd.clone())
+                    .map_err(|_| String::from("Command contains a null character"))?;
+                let result = unsafe { libc::system(cmd.as_ptr()) };
+                log::info!("Exit code: {}", result);
+                Ok(None)
+            }
+            Command::Reconnect => {
+                self.spotify.shutdown();
+                Ok(None)
+            }
+            Command::SaveCurrent => {
+                if let Some(mut track) = self.queue.get_current() {
+                    track.save(&self.library);
+                }
+                Ok(None)
+            }
+            _ => Err(format!(
+                "The command \"{}\" is unsupported without a user interface",
+                cmd.basename()
+            )),
+        }
+    }
+}
+
 pub struct CommandManager {
     aliases: HashMap<String, String>,
     bindings: RefCell<HashMap<String, Vec<Command>>>,
+    executor: CommandExecutor,
     spotify: Spotify,
     queue: Arc<Queue>,
     library: Arc<Library>,
@@ -56,6 +301,12 @@ impl CommandManager {
         CommandManager {
             aliases: HashMap::new(),
             bindings,
+            executor: CommandExecutor::new(
+                spotify.clone(),
+                queue.clone(),
+                library.clone(),
+                config.clone(),
+            ),
             spotify,
             queue,
             library,
@@ -110,21 +361,8 @@ impl CommandManager {
         cmd: &Command,
     ) -> Result<Option<String>, String> {
         match cmd {
-            Command::Noop => Ok(None),
             Command::Quit => {
-                let queue = self.queue.queue.read().expect("can't readlock queue");
-                self.config.with_state_mut(move |mut s| {
-                    debug!(
-                        "saving state, {} items, current track: {:?}",
-                        queue.len(),
-                        self.queue.get_current_index()
-                    );
-                    s.queuestate.queue = queue.clone();
-                    s.queuestate.random_order = self.queue.get_random_order();
-                    s.queuestate.current_track = self.queue.get_current_index();
-                    s.queuestate.track_progress = self.spotify.get_current_progress();
-                });
-                self.config.save_state();
+                self.executor.save_state();
                 s.quit();
                 Ok(None)
             }
@@ -133,22 +371,6 @@ impl CommandManager {
                 s.clear();
                 Ok(None)
             }
-            Command::Stop => {
-                self.queue.stop();
-                Ok(None)
-            }
-            Command::Previous => {
-                if self.spotify.get_current_progress() < Duration::from_secs(5) {
-                    self.queue.previous();
-                } else {
-                    self.spotify.seek(0);
-                }
-                Ok(None)
-            }
-            Command::Next => {
-                self.queue.next(true);
-                Ok(None)
-            }
             Command::Clear => {
                 let queue = self.queue.clone();
                 let confirmation = Dialog::text("Clear queue?")
@@ -160,60 +382,54 @@ impl CommandManager {
                 s.add_layer(Modal::new(confirmation));
                 Ok(None)
             }
-            Command::UpdateLibrary => {
-                self.library.update_library();
-                Ok(None)
-            }
-            Command::TogglePlay => {
-                self.queue.toggleplayback();
-                Ok(None)
-            }
-            Command::Shuffle(mode) => {
-                let mode = mode.unwrap_or_else(|| !self.queue.get_shuffle());
-                self.queue.set_shuffle(mode);
+            Command::Help => {
+                let view = Box::new(HelpView::new(self.bindings.borrow().clone()));
+                s.call_on_name("main", move |v: &mut Layout| v.push_view(view));
                 Ok(None)
             }
-            Command::Repeat(mode) => {
-                let mode = mode.unwrap_or_else(|| match self.queue.get_repeat() {
-                    RepeatSetting::None => RepeatSetting::RepeatPlaylist,
-                    RepeatSetting::RepeatPlaylist => RepeatSetting::RepeatTrack,
-                    RepeatSetting::RepeatTrack => RepeatSetting::None,
+            Command::Outputs => {
+                // listing the devices can take a while, so the dialog is opened once they are known
+                let spotify = self.spotify.clone();
+                let cb_sink = s.cb_sink().clone();
+                std::thread::spawn(move || {
+                    let devices = spotify.output_devices();
+                    cb_sink
+                        .send(Box::new(move |s| match devices {
+                            Ok(devices) => {
+                                s.add_layer(ContextMenu::select_output_dialog(spotify, devices))
+                            }
+                            Err(e) => {
+                                s.call_on_name("main", |v: &mut Layout| v.set_result(Err(e)));
+                            }
+                        }))
+                        .ok();
                 });
-
-                self.queue.set_repeat(mode);
-                Ok(None)
-            }
-            Command::Seek(direction) => {
-                match *direction {
-                    SeekDirection::Relative(rel) => self.spotify.seek_relative(rel),
-                    SeekDirection::Absolute(abs) => self.spotify.seek(abs),
-                }
-                Ok(None)
-            }
-            Command::VolumeUp(amount) => {
-                let volume = self
-                    .spotify
-                    .volume()
-                    .saturating_add(VOLUME_PERCENT * amount);
-                self.spotify.set_volume(volume);
                 Ok(None)
             }
-            Command::VolumeDown(amount) => {
-                let volume = self
-                    .spotify
-                    .volume()
-                    .saturating_sub(VOLUME_PERCENT * amount);
-                debug!("vol {}", volume);
-                self.spotify.set_volume(volume);
-                Ok(None)
-            }
-            Command::Help => {
-                let view = Box::new(HelpView::new(self.bindings.borrow().clone()));
-                s.call_on_name("main", move |v: &mut Layout| v.push_view(view));
+            Command::Devices => {
+                // the devices are fetched from the Web API, so the dialog is opened once they are
+                // known
+                let spotify = self.spotify.clone();
+                let cb_sink = s.cb_sink().clone();
+                std::thread::spawn(move || {
+                    let devices = spotify.api.devices();
+                    cb_sink
+                        .send(Box::new(move |s| match devices {
+                            Some(devices) => {
+                                s.add_layer(ContextMenu::select_device_dialog(spotify, devices))
+                            }
+                            None => {
+                                s.call_on_name("main", |v: &mut Layout| {
+                                    v.set_result(Err("Could not fetch the devices".to_string()))
+                                });
+                            }
+                        }))
+                        .ok();
+                });
                 Ok(None)
             }
             Command::ReloadConfig => {
-                self.config.reload();
+                self.executor.execute(cmd)?;
 
                 // update theme
                 let theme = self.config.build_theme();
@@ -225,13 +441,6 @@ impl CommandManager {
                 self.register_keybindings(s);
                 Ok(None)
             }
-            Command::NewPlaylist(name) => {
-                match self.spotify.api.create_playlist(name, None, None) {
-                    Some(_) => self.library.update_library(),
-                    None => error!("could not create playlist {}", name),
-                }
-                Ok(None)
-            }
             Command::Search(term) => {
                 let view = if !term.is_empty() {
                     Some(SearchResultsView::new(
@@ -239,7 +448,7 @@ impl CommandManager {
                         self.events.clone(),
                         self.queue.clone(),
                         self.library.clone(),
-                    ))
+                    )?)
                 } else {
                     None
                 };
@@ -261,17 +470,6 @@ impl CommandManager {
                 s.quit();
                 Ok(None)
             }
-            Command::Execute(cmd) => {
-                log::info!("Executing command: {}", cmd);
-                let cmd = std::ffi::CString::new(cmd.clone()).unwrap();
-                let result = unsafe { libc::system(cmd.as_ptr()) };
-                log::info!("Exit code: {}", result);
-                Ok(None)
-            }
-            Command::Reconnect => {
-                self.spotify.shutdown();
-                Ok(None)
-            }
             Command::AddCurrent => {
                 if let Some(track) = self.queue.get_current() {
                     if let Some(track) = track.track() {
@@ -285,13 +483,6 @@ impl CommandManager {
                 }
                 Ok(None)
             }
-            Command::SaveCurrent => {
-                if let Some(mut track) = self.queue.get_current() {
-                    track.save(&self.library);
-                }
-                Ok(None)
-            }
-
             Command::Queue
             | Command::PlayNext
             | Command::Play
@@ -308,6 +499,7 @@ impl CommandManager {
             | Command::Jump(_)
             | Command::Insert(_)
             | Command::ShowRecommendations(_)
+            | Command::Stats(_)
             | Command::Sort(_, _) => Err(format!(
                 "The command \"{}\" is unsupported in this view",
                 cmd.basename()
@@ -317,10 +509,39 @@ impl CommandManager {
                 "The command \"{}\" is unsupported in this view",
                 cmd.basename()
             )),
+            Command::TogglePlay
+            | Command::Stop
+            | Command::Previous
+            | Command::Next
+            | Command::UpdateLibrary
+            | Command::Rescan
+            | Command::SaveCurrent
+            | Command::Seek(_)
+            | Command::VolumeUp(_)
+            | Command::VolumeDown(_)
+            | Command::Repeat(_)
+            | Command::Shuffle(_)
+            | Command::Offline(_)
+            | Command::Noop
+            | Command::NewPlaylist(_)
+            | Command::ExportHistory(_)
+            | Command::Sleep(_)
+            | Command::Eq(_)
+            | Command::Speed(_)
+            | Command::Volnorm(_)
+            | Command::Output(_)
+            | Command::Device(_)
+            | Command::Execute(_)
+            | Command::Reconnect => self.executor.execute(cmd),
         }
     }
 
     fn handle_callbacks(&self, s: &mut Cursive, cmd: &Command) -> Result<Option<String>, String> {
+        // views don't get to handle the transport commands that are for the controlled device
+        if devices::send_command(&self.spotify, cmd) {
+            return Ok(None);
+        }
+
         let local = if let Some(mut contextmenu) = s.find_name::<ContextMenu>("contextmenu") {
             contextmenu.on_command(s, cmd)?
         } else if let Some(mut add_track_menu) = s.find_name::<AddToPlaylistMenu>("addtrackmenu") {
@@ -331,6 +552,10 @@ impl CommandManager {
             s.find_name::<SelectArtistActionMenu>("selectartistaction")
         {
             select_artist_action.on_command(s, cmd)?
+        } else if let Some(mut select_output) = s.find_name::<SelectOutputMenu>("selectoutput") {
+            select_output.on_command(s, cmd)?
+        } else if let Some(mut select_device) = s.find_name::<SelectDeviceMenu>("selectdevice") {
+            select_device.on_command(s, cmd)?
         } else {
             s.on_layout(|siv, mut l| l.on_command(siv, cmd))?
         };
@@ -353,6 +578,23 @@ impl CommandManager {
 
     pub fn handle(&self, s: &mut Cursive, cmd: Command) {
         let result = self.handle_callbacks(s, &cmd);
+        self.show_result(s, result);
+    }
+
+    /// Handle `cmd` like [CommandManager::handle], but also return the result to the caller.
+    pub fn handle_with_result(
+        &self,
+        s: &mut Cursive,
+        cmd: Command,
+    ) -> Result<Option<String>, String> {
+        let result = self.handle_callbacks(s, &cmd);
+        self.show_result(s, result.clone());
+        result
+    }
+
+    fn show_result(&self, s: &mut Cursive, result: Result<Option<String>, String>) {
+        // the command may have changed the state that is published to IPC clients
+        self.events.send(crate::events::Event::StateChanged);
 
         s.call_on_name("main", |v: &mut Layout| {
             v.set_result(result);
@@ -462,6 +704,8 @@ impl CommandManager {
         kb.insert("F1".into(), vec![Command::Focus("queue".into())]);
         kb.insert("F2".into(), vec![Command::Focus("search".into())]);
         kb.insert("F3".into(), vec![Command::Focus("library".into())]);
+        kb.insert("F4".into(), vec![Command::Focus("stats".into())]);
+        kb.insert("F7".into(), vec![Command::Focus("lyrics".into())]);
         #[cfg(feature = "cover")]
         kb.insert("F8".into(), vec![Command::Focus("cover".into())]);
         kb.insert("?".into(), vec![Command::Help]);
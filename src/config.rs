@@ -7,7 +7,7 @@ use cursive::theme::Theme;
 use log::{debug, error};
 use platform_dirs::AppDirs;
 
-use crate::command::{SortDirection, SortKey};
+use crate::command::{SortDirection, SortKey, StatsWindow};
 use crate::model::playable::Playable;
 use crate::queue;
 use crate::serialization::{Serializer, CBOR, TOML};
@@ -35,6 +35,7 @@ pub enum LibraryTab {
     Playlists,
     Podcasts,
     Browse,
+    History,
 }
 
 /// The format used to represent tracks in a list.
@@ -87,9 +88,13 @@ pub struct ConfigValues {
     pub backend_device: Option<String>,
     pub volnorm: Option<bool>,
     pub volnorm_pregain: Option<f64>,
+    pub volnorm_mode: Option<VolnormMode>,
+    pub volnorm_limiter: Option<bool>,
     pub notify: Option<bool>,
     pub bitrate: Option<u32>,
     pub gapless: Option<bool>,
+    pub crossfade: Option<u64>,
+    pub crossfade_albums: Option<bool>,
     pub shuffle: Option<bool>,
     pub repeat: Option<queue::RepeatSetting>,
     pub cover_max_scale: Option<f32>,
@@ -100,6 +105,17 @@ pub struct ConfigValues {
     pub library_tabs: Option<Vec<LibraryTab>>,
     pub hide_display_names: Option<bool>,
     pub credentials: Option<Credentials>,
+    pub remote: Option<RemoteControl>,
+    pub web_ui_address: Option<String>,
+    pub web_ui_token: Option<String>,
+    pub connect: Option<SpotifyConnect>,
+    pub raise_cmd: Option<String>,
+    pub music_directory: Option<String>,
+    pub lyrics_directory: Option<String>,
+    pub scrobbling: Option<Scrobbling>,
+    pub stats_window: Option<StatsWindow>,
+    pub alarms: Option<Vec<Alarm>>,
+    pub eq_presets: Option<HashMap<String, EqPreset>>,
 }
 
 /// Commands used to obtain user credentials automatically.
@@ -109,6 +125,118 @@ pub struct Credentials {
     pub password_cmd: Option<String>,
 }
 
+/// Network listeners that allow controlling ncspot from other machines.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RemoteControl {
+    /// Address to listen on for TCP connections, i.e. `0.0.0.0:8988`.
+    pub tcp: Option<String>,
+    /// Address to listen on for WebSocket connections, i.e. `0.0.0.0:8989`.
+    pub websocket: Option<String>,
+    /// Shared secret that clients have to authenticate with before sending anything else.
+    pub token: Option<String>,
+}
+
+/// Advertises ncspot as a Spotify Connect device, so that the Spotify apps can play on it.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SpotifyConnect {
+    /// The name of the device, `ncspot` if not set.
+    pub name: Option<String>,
+    /// Whether to advertise the device on the local network, or only to the devices of the user.
+    pub discovery: Option<bool>,
+    /// The port to serve the discovery API on, a random port if not set.
+    pub port: Option<u16>,
+}
+
+/// An alarm that starts playing an item at set times.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Alarm {
+    /// The time of day in the local timezone, i.e. `07:00`.
+    pub time: String,
+    /// The weekdays to ring on, i.e. `["mon", "tue"]`. Rings every day if not set.
+    pub days: Option<Vec<String>>,
+    /// The Spotify URI of the playlist, album or track to play.
+    pub uri: String,
+    /// The volume in percent to ramp up to, the current volume if not set.
+    pub volume: Option<u16>,
+    /// How long to ramp up the volume for in seconds.
+    pub ramp: Option<u64>,
+}
+
+/// Which loudness volume normalisation adjusts tracks by.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VolnormMode {
+    /// The loudness of every track on its own.
+    #[default]
+    Track,
+    /// The loudness of the album, which keeps the differences between its tracks.
+    Album,
+    /// The album loudness while tracks of the same album are played after one another, and the
+    /// track loudness otherwise.
+    Auto,
+}
+
+/// The shape of an equalizer band.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterType {
+    /// Boost or cut around the frequency.
+    #[default]
+    Peak,
+    /// Boost or cut below the frequency.
+    LowShelf,
+    /// Boost or cut above the frequency.
+    HighShelf,
+}
+
+/// A band of a parametric equalizer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EqBand {
+    #[serde(default, rename = "type")]
+    pub filter: FilterType,
+    /// The center or corner frequency in Hz.
+    pub freq: f64,
+    /// The gain in dB.
+    pub gain: f64,
+    /// The quality factor, `0.707` if not set.
+    pub q: Option<f64>,
+}
+
+/// A named set of equalizer bands.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct EqPreset {
+    /// The gain in dB applied before the bands, to make room for boosts.
+    pub preamp: Option<f64>,
+    pub bands: Vec<EqBand>,
+}
+
+/// Services that listens are submitted to.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Scrobbling {
+    pub listenbrainz: Option<ListenBrainz>,
+    pub lastfm: Option<LastFm>,
+}
+
+/// A ListenBrainz account.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ListenBrainz {
+    /// The user token from the ListenBrainz settings.
+    pub token: String,
+    /// The API root, i.e. `https://api.listenbrainz.org`.
+    pub url: Option<String>,
+}
+
+/// An account of Last.fm or a service with a compatible API, i.e. Libre.fm.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct LastFm {
+    pub api_key: String,
+    pub api_secret: String,
+    /// The session key of an authenticated user.
+    pub session_key: String,
+    /// The API endpoint, i.e. `https://ws.audioscrobbler.com/2.0/`.
+    pub url: Option<String>,
+}
+
 /// The ncspot theme.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct ConfigTheme {
@@ -159,6 +287,16 @@ pub struct UserState {
     pub playlist_orders: HashMap<String, SortingOrder>,
     pub cache_version: u16,
     pub playback_state: PlaybackState,
+    #[serde(default)]
+    pub offline: bool,
+    #[serde(default)]
+    pub eq_preset: Option<String>,
+    /// The playback speed of shows by their ID, if it isn't the normal speed.
+    #[serde(default)]
+    pub show_speeds: HashMap<String, f64>,
+    /// The output device that was switched to last, which is used instead of `backend_device`.
+    #[serde(default)]
+    pub output_device: Option<String>,
 }
 
 impl Default for UserState {
@@ -171,6 +309,10 @@ impl Default for UserState {
             playlist_orders: HashMap::new(),
             cache_version: 0,
             playback_state: PlaybackState::Default,
+            offline: false,
+            eq_preset: None,
+            show_speeds: HashMap::new(),
+            output_device: None,
         }
     }
 }
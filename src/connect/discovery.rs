@@ -0,0 +1,392 @@
+//! Lets Spotify clients on the local network find ncspot, using the zeroconf protocol of Spotify
+//! Connect.
+//!
+//! The device is advertised as a `_spotify-connect._tcp` service over mDNS, which points clients
+//! to a small HTTP server. Clients ask it about the device with `getInfo`, and log in to it with
+//! `addUser`. As ncspot is logged in already, only the logged in user is accepted.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use hmac::{Hmac, Mac, NewMac};
+use librespot_core::diffie_hellman::DhLocalKeys;
+use log::{debug, error, info, warn};
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// The mDNS service type of Spotify Connect devices.
+pub const SERVICE_TYPE: &str = "_spotify-connect._tcp";
+
+/// The TXT records clients need to find the zeroconf server.
+const TXT: &[&str] = &["CPath=/", "VERSION=1.0", "Stack=SP"];
+
+/// The largest request body that is accepted, logins only take a few kilobytes.
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+/// Advertises services on the local network.
+pub trait Responder {
+    /// A registered service, which is advertised until it is dropped.
+    type Service: Send;
+
+    /// Advertise the service `name` of the type `service_type` on `port`.
+    fn register(
+        &self,
+        service_type: &str,
+        name: &str,
+        port: u16,
+        txt: &[&str],
+    ) -> Result<Self::Service, String>;
+}
+
+/// Advertises services with the mDNS responder of libmdns, which runs on its own thread.
+pub struct Mdns(libmdns::Responder);
+
+impl Mdns {
+    pub fn new() -> Result<Self, String> {
+        libmdns::Responder::new()
+            .map(Self)
+            .map_err(|e| format!("Can't start the mDNS responder: {e}"))
+    }
+}
+
+impl Responder for Mdns {
+    type Service = libmdns::Service;
+
+    fn register(
+        &self,
+        service_type: &str,
+        name: &str,
+        port: u16,
+        txt: &[&str],
+    ) -> Result<Self::Service, String> {
+        Ok(self
+            .0
+            .register(service_type.to_string(), name.to_string(), port, txt))
+    }
+}
+
+/// What clients are told about the device.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub device_id: String,
+    pub name: String,
+    /// The user ncspot is logged in as.
+    pub username: String,
+}
+
+/// The zeroconf server and its advertisement, which are stopped when dropped.
+pub struct Discovery<R: Responder> {
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+    _service: R::Service,
+    _responder: R,
+}
+
+impl<R: Responder> Discovery<R> {
+    /// Serve the zeroconf API of the device `info` on `address`, and advertise it with
+    /// `responder`.
+    pub fn start(address: &str, info: DeviceInfo, responder: R) -> Result<Self, String> {
+        let server = Arc::new(
+            Server::http(address).map_err(|e| format!("Can't serve Spotify Connect: {e}"))?,
+        );
+        let port = server
+            .server_addr()
+            .to_ip()
+            .map(|address| address.port())
+            .ok_or("Spotify Connect isn't served on an IP address")?;
+        let service = responder.register(SERVICE_TYPE, &info.name, port, TXT)?;
+
+        let handler = Handler {
+            keys: DhLocalKeys::random(&mut rand::thread_rng()),
+            info,
+        };
+        let worker_server = server.clone();
+        let thread = std::thread::spawn(move || {
+            for request in worker_server.incoming_requests() {
+                handler.handle(request);
+            }
+        });
+
+        let discovery = Self {
+            server,
+            thread: Some(thread),
+            _service: service,
+            _responder: responder,
+        };
+        if let Some(address) = discovery.address() {
+            info!("Advertising Spotify Connect device, discovery served on {address}");
+        }
+        Ok(discovery)
+    }
+
+    /// The address the zeroconf server is listening on.
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+}
+
+impl<R: Responder> Drop for Discovery<R> {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Spotify Connect discovery thread panicked");
+            }
+        }
+    }
+}
+
+/// Answers the requests of clients.
+struct Handler {
+    keys: DhLocalKeys,
+    info: DeviceInfo,
+}
+
+impl Handler {
+    fn handle(&self, mut request: Request) {
+        let mut params: HashMap<String, String> = HashMap::new();
+        if let Some((_, query)) = request.url().split_once('?') {
+            params.extend(url::form_urlencoded::parse(query.as_bytes()).into_owned());
+        }
+        // one more byte than allowed is read to tell whether the body is too large
+        let mut body = Vec::new();
+        if let Err(e) = request
+            .as_reader()
+            .take(MAX_BODY_SIZE + 1)
+            .read_to_end(&mut body)
+        {
+            warn!("Could not read Spotify Connect request: {e}");
+        }
+        let too_large = body.len() as u64 > MAX_BODY_SIZE;
+        if !too_large {
+            params.extend(url::form_urlencoded::parse(&body).into_owned());
+        }
+
+        let action = params.get("action").map(String::as_str);
+        debug!("Spotify Connect request: {} {action:?}", request.method());
+        let (status, body) = match (request.method(), action) {
+            _ if too_large => (413, status(203, "ERROR-INVALID-ARGUMENTS")),
+            (Method::Get, Some("getInfo")) => (200, self.info()),
+            (Method::Post, Some("addUser")) => (200, self.add_user(&params)),
+            (_, Some(_)) => (400, status(202, "ERROR-INVALID-ACTION")),
+            (_, None) => (400, status(201, "ERROR-MISSING-ACTION")),
+        };
+
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                    .expect("valid header"),
+            );
+        if let Err(e) = request.respond(response) {
+            error!("Could not answer Spotify Connect request: {e}");
+        }
+    }
+
+    fn info(&self) -> Value {
+        json!({
+            "status": 101,
+            "statusString": "ERROR-OK",
+            "spotifyError": 0,
+            "version": "2.7.1",
+            "deviceID": self.info.device_id,
+            "remoteName": self.info.name,
+            "activeUser": self.info.username,
+            "publicKey": base64::encode(self.keys.public_key()),
+            "deviceType": "COMPUTER",
+            "libraryVersion": env!("CARGO_PKG_VERSION"),
+            "accountReq": "PREMIUM",
+            "brandDisplayName": "ncspot",
+            "modelDisplayName": "ncspot",
+            "resolverVersion": "0",
+            "groupStatus": "NONE",
+            "voiceSupport": "NO",
+        })
+    }
+
+    /// Check the login of a user, whose credentials are encrypted with a key that is derived
+    /// from our public key and the client key.
+    fn add_user(&self, params: &HashMap<String, String>) -> Value {
+        let decode = |name: &str| base64::decode(params.get(name)?).ok();
+        let (Some(username), Some(blob), Some(client_key)) =
+            (params.get("userName"), decode("blob"), decode("clientKey"))
+        else {
+            return status(203, "ERROR-INVALID-ARGUMENTS");
+        };
+        // the blob consists of an IV, the encrypted credentials and their MAC
+        if blob.len() < 16 + 20 {
+            return status(203, "ERROR-INVALID-ARGUMENTS");
+        }
+        let (encrypted, mac) = blob[16..].split_at(blob.len() - 16 - 20);
+
+        let shared_key = self.keys.shared_secret(&client_key);
+        let base_key = &Sha1::digest(&shared_key)[..16];
+        let checksum_key = hmac(base_key, b"checksum");
+        let mut checksum = Hmac::<Sha1>::new_from_slice(&checksum_key).expect("any key size");
+        checksum.update(encrypted);
+        if checksum.verify(mac).is_err() {
+            warn!("Spotify Connect login of {username} failed: MAC mismatch");
+            return json!({ "status": 102, "spotifyError": 1, "statusString": "ERROR-MAC" });
+        }
+
+        if !username.eq_ignore_ascii_case(&self.info.username) {
+            warn!("Spotify Connect login of {username} refused, logged in as another user");
+            return status(105, "ERROR-LOGIN-FAILED");
+        }
+
+        info!("Spotify Connect login of {username}");
+        status(101, "ERROR-OK")
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("any key size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn status(status: u16, message: &str) -> Value {
+    json!({
+        "status": status,
+        "spotifyError": if status == 101 { 0 } else { 1 },
+        "statusString": message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// A service that was registered with the mock responder.
+    #[derive(Clone, Debug)]
+    struct Registration {
+        service_type: String,
+        name: String,
+        port: u16,
+        txt: Vec<String>,
+    }
+
+    /// Records the services that are registered, instead of advertising them.
+    #[derive(Default)]
+    struct MockResponder {
+        services: Arc<Mutex<Vec<Registration>>>,
+    }
+
+    impl Responder for MockResponder {
+        type Service = ();
+
+        fn register(
+            &self,
+            service_type: &str,
+            name: &str,
+            port: u16,
+            txt: &[&str],
+        ) -> Result<(), String> {
+            self.services.lock().unwrap().push(Registration {
+                service_type: service_type.to_string(),
+                name: name.to_string(),
+                port,
+                txt: txt.iter().map(|s| s.to_string()).collect(),
+            });
+            Ok(())
+        }
+    }
+
+    fn start() -> (Discovery<MockResponder>, String) {
+        let info = DeviceInfo {
+            device_id: "0123abcd".to_string(),
+            name: "ncspot test".to_string(),
+            username: "alice".to_string(),
+        };
+        let discovery = Discovery::start("127.0.0.1:0", info, MockResponder::default()).unwrap();
+        let base = format!("http://{}/", discovery.address().unwrap());
+        (discovery, base)
+    }
+
+    /// Log in as `username` like a client does, with a MAC that is valid if `valid_mac` is set.
+    fn add_user(base: &str, username: &str, valid_mac: bool) -> Value {
+        let client = reqwest::blocking::Client::new();
+        let info: Value = client
+            .get(format!("{base}?action=getInfo"))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        let device_key = base64::decode(info["publicKey"].as_str().unwrap()).unwrap();
+
+        let keys = DhLocalKeys::random(&mut rand::thread_rng());
+        let base_key = &Sha1::digest(&keys.shared_secret(&device_key))[..16];
+        let encrypted = b"encrypted credentials".to_vec();
+        let mut mac = hmac(&hmac(base_key, b"checksum"), &encrypted);
+        if !valid_mac {
+            mac[0] ^= 0xff;
+        }
+        let blob = [vec![0; 16], encrypted, mac].concat();
+
+        client
+            .post(base)
+            .form(&[
+                ("action", "addUser"),
+                ("userName", username),
+                ("blob", &base64::encode(blob)),
+                ("clientKey", &base64::encode(keys.public_key())),
+            ])
+            .send()
+            .unwrap()
+            .json()
+            .unwrap()
+    }
+
+    #[test]
+    fn advertises_the_server() {
+        let (discovery, base) = start();
+        let services = discovery._responder.services.lock().unwrap().clone();
+        assert_eq!(services.len(), 1);
+        let service = &services[0];
+        assert_eq!(service.service_type, SERVICE_TYPE);
+        assert_eq!(service.name, "ncspot test");
+        assert_eq!(service.port, discovery.address().unwrap().port());
+        assert!(service.txt.contains(&"CPath=/".to_string()));
+
+        let info: Value = reqwest::blocking::get(format!("{base}?action=getInfo"))
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(info["status"], 101);
+        assert_eq!(info["deviceID"], "0123abcd");
+        assert_eq!(info["remoteName"], "ncspot test");
+        assert_eq!(info["activeUser"], "alice");
+    }
+
+    #[test]
+    fn accepts_the_logged_in_user() {
+        let (_discovery, base) = start();
+        assert_eq!(add_user(&base, "alice", true)["status"], 101);
+        assert_eq!(add_user(&base, "alice", false)["statusString"], "ERROR-MAC");
+        assert_eq!(add_user(&base, "bob", true)["status"], 105);
+    }
+
+    #[test]
+    fn rejects_unknown_actions() {
+        let (_discovery, base) = start();
+        let response = reqwest::blocking::get(format!("{base}?action=rebootDevice")).unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[test]
+    fn rejects_large_bodies() {
+        let (_discovery, base) = start();
+        let response = reqwest::blocking::Client::new()
+            .post(format!("{base}?action=addUser"))
+            .body(vec![b'a'; MAX_BODY_SIZE as usize + 1])
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 413);
+    }
+}
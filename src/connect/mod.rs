@@ -0,0 +1,161 @@
+//! A Spotify Connect receiver, so that the Spotify apps can play on ncspot.
+//!
+//! While a controller plays on ncspot, the queue holds the tracks of the controller and the local
+//! queue is kept aside. It is brought back when another device takes over.
+
+use std::sync::Arc;
+
+use librespot_core::authentication::Credentials;
+use librespot_core::session::Session;
+use log::error;
+use sha1::{Digest, Sha1};
+use tokio::sync::mpsc;
+
+use crate::application::ASYNC_RUNTIME;
+use crate::config::SpotifyConnect;
+use crate::events::EventManager;
+use crate::model::playable::Playable;
+use crate::model::track::Track;
+use crate::queue::{Queue, RepeatSetting};
+use crate::spotify::{PlayerEvent, Spotify};
+
+mod discovery;
+mod spirc;
+
+use discovery::{DeviceInfo, Discovery, Mdns};
+use spirc::{Receiver, Snapshot, SpircCommand};
+
+pub use spirc::ConnectEvent;
+
+/// The Connect device of ncspot, which is taken offline when dropped.
+pub struct Connect {
+    commands: mpsc::UnboundedSender<SpircCommand>,
+    _discovery: Option<Discovery<Mdns>>,
+}
+
+impl Connect {
+    /// Announce ncspot as a Connect device of the user of `credentials`, with its own session.
+    pub fn start(
+        cfg: &SpotifyConnect,
+        credentials: Credentials,
+        events: EventManager,
+        volume: u16,
+    ) -> Result<Self, String> {
+        let name = cfg.name.clone().unwrap_or_else(|| String::from("ncspot"));
+        // a stable id, so controllers recognize the device after restarts
+        let device_id = Sha1::digest(name.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+
+        let discovery = match cfg.discovery.unwrap_or(true) {
+            true => Some(Discovery::start(
+                &format!("0.0.0.0:{}", cfg.port.unwrap_or(0)),
+                DeviceInfo {
+                    device_id: device_id.clone(),
+                    name: name.clone(),
+                    username: credentials.username.clone(),
+                },
+                Mdns::new()?,
+            )?),
+            false => None,
+        };
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let receiver = Receiver::new(device_id.clone(), name, volume);
+        ASYNC_RUNTIME.spawn(async move {
+            let mut session_config = Spotify::session_config();
+            session_config.device_id = device_id;
+            match Session::connect(session_config, credentials, None, false).await {
+                Ok((session, _)) => spirc::run(session, receiver, rx, events).await,
+                Err(e) => error!("Could not start Spotify Connect: {e}"),
+            }
+        });
+
+        Ok(Self {
+            commands: tx,
+            _discovery: discovery,
+        })
+    }
+
+    /// Carry out the request of a controller.
+    pub fn handle(&self, event: ConnectEvent, queue: &Arc<Queue>) {
+        let spotify = queue.get_spotify();
+        match event {
+            ConnectEvent::Load {
+                controller,
+                tracks,
+                index,
+                position_ms,
+                playing,
+            } => {
+                // the tracks are fetched in the background, the controllers are updated once they
+                // are played
+                let queue = queue.clone();
+                let commands = self.commands.clone();
+                std::thread::spawn(move || match spotify.api.tracks(&tracks) {
+                    Some(tracks) => {
+                        let items = tracks
+                            .iter()
+                            .map(|track| Playable::Track(Track::from(track)))
+                            .collect();
+                        queue.play_remote(&controller, items, index, position_ms, playing);
+                        update(&commands, &queue);
+                    }
+                    None => error!("Could not load the tracks of {controller}"),
+                });
+                return;
+            }
+            ConnectEvent::Play => spotify.play(),
+            ConnectEvent::Pause => spotify.pause(),
+            ConnectEvent::PlayPause => queue.toggleplayback(),
+            ConnectEvent::Next => queue.next(true),
+            ConnectEvent::Previous => queue.previous(),
+            ConnectEvent::Seek(position_ms) => spotify.seek(position_ms),
+            ConnectEvent::Volume(volume) => spotify.set_volume(volume),
+            ConnectEvent::Repeat(repeat) => queue.set_repeat(match repeat {
+                true => RepeatSetting::RepeatPlaylist,
+                false => RepeatSetting::None,
+            }),
+            ConnectEvent::Shuffle(shuffle) => queue.set_shuffle(shuffle),
+            ConnectEvent::Deactivated => queue.resume_local(),
+        }
+        self.update(queue);
+    }
+
+    /// Report the playback state to the controllers.
+    pub fn update(&self, queue: &Queue) {
+        update(&self.commands, queue);
+    }
+}
+
+/// Report the playback state of `queue` to the controllers through the Connect task.
+fn update(commands: &mpsc::UnboundedSender<SpircCommand>, queue: &Queue) {
+    let spotify = queue.get_spotify();
+    let current = queue.get_current_index();
+    let mut snapshot = Snapshot {
+        playing: matches!(spotify.get_current_status(), PlayerEvent::Playing(_)),
+        position_ms: spotify.get_current_progress().as_millis() as u32,
+        volume: spotify.volume(),
+        shuffle: queue.get_shuffle(),
+        repeat: queue.get_repeat() != RepeatSetting::None,
+        ..Default::default()
+    };
+    // controllers only know about tracks
+    for (index, playable) in queue.queue.read().unwrap().iter().enumerate() {
+        if let Playable::Track(Track { id: Some(id), .. }) = playable {
+            if current == Some(index) {
+                snapshot.index = Some(snapshot.tracks.len());
+            }
+            snapshot.tracks.push(id.clone());
+        }
+    }
+    // the task is gone if the session could not be established, which was logged already
+    commands.send(SpircCommand::Update(snapshot)).ok();
+}
+
+impl Drop for Connect {
+    fn drop(&mut self) {
+        self.commands.send(SpircCommand::Shutdown).ok();
+    }
+}
@@ -0,0 +1,469 @@
+//! The Spotify Connect protocol, through which controllers like the mobile apps play on ncspot.
+//!
+//! The devices of a user exchange protobuf frames over a Mercury channel of the user. ncspot says
+//! hello when it comes online, becomes the active device when a controller loads tracks onto it,
+//! and notifies the controllers about its state as long as it is active. It is deactivated when
+//! another device becomes active.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use librespot_core::session::Session;
+use librespot_core::spotify_id::SpotifyId;
+use librespot_protocol::spirc::{
+    CapabilityType, DeviceState, Frame, MessageType, PlayStatus, State, TrackRef,
+};
+use log::{debug, error, info, warn};
+use protobuf::Message;
+use tokio::sync::mpsc;
+
+use crate::events::{Event, EventManager};
+
+/// The number of volume steps controllers can change the volume by.
+const VOLUME_STEPS: i64 = 64;
+/// The volume change of a single step.
+const VOLUME_STEP: u16 = 1024;
+
+/// Requests of a controller that ncspot carries out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectEvent {
+    /// Replace the queue with the tracks of the controller, and play the one at `index` from
+    /// `position_ms`, or load it paused if `playing` isn't set.
+    Load {
+        controller: String,
+        tracks: Vec<String>,
+        index: usize,
+        position_ms: u32,
+        playing: bool,
+    },
+    Play,
+    Pause,
+    PlayPause,
+    Next,
+    Previous,
+    Seek(u32),
+    /// Set the volume to a value between 0 and 65535.
+    Volume(u16),
+    Repeat(bool),
+    Shuffle(bool),
+    /// Another device became active, so the controller doesn't play on ncspot anymore.
+    Deactivated,
+}
+
+/// The playback state of ncspot that is reported to controllers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The ids of the tracks in the queue.
+    pub tracks: Vec<String>,
+    /// The index of the current track in `tracks`.
+    pub index: Option<usize>,
+    pub playing: bool,
+    pub position_ms: u32,
+    pub volume: u16,
+    pub shuffle: bool,
+    pub repeat: bool,
+}
+
+/// Messages from ncspot to the protocol task.
+#[derive(Debug)]
+pub enum SpircCommand {
+    /// The playback state changed.
+    Update(Snapshot),
+    /// Say goodbye to the controllers and stop.
+    Shutdown,
+}
+
+/// What should happen in response to a frame.
+#[derive(Debug, PartialEq)]
+pub enum Handled {
+    Ignored,
+    /// Send this frame back.
+    Reply(Box<Frame>),
+    /// Carry out the request of a controller.
+    Event(ConnectEvent),
+}
+
+/// The Connect state of the device, which turns frames of controllers into events and the
+/// playback state into frames.
+pub struct Receiver {
+    ident: String,
+    device: DeviceState,
+    state: State,
+    seq_nr: u32,
+}
+
+impl Receiver {
+    /// A device with the id `ident` that is shown as `name`.
+    pub fn new(ident: String, name: String, volume: u16) -> Self {
+        let mut device = DeviceState::new();
+        device.set_sw_version(format!("ncspot {}", env!("CARGO_PKG_VERSION")));
+        device.set_can_play(true);
+        device.set_volume(volume as u32);
+        device.set_name(name);
+        let capabilities = device.mut_capabilities();
+        for (typ, value) in [
+            (CapabilityType::kCanBePlayer, 1),
+            // a computer
+            (CapabilityType::kDeviceType, 1),
+            (CapabilityType::kGaiaEqConnectId, 1),
+            (CapabilityType::kSupportsLogout, 0),
+            (CapabilityType::kIsObservable, 1),
+            (CapabilityType::kVolumeSteps, VOLUME_STEPS),
+            (CapabilityType::kSupportsPlaylistV2, 1),
+        ] {
+            let capability = capabilities.push_default();
+            capability.set_typ(typ);
+            capability.mut_intValue().push(value);
+        }
+        for (typ, values) in [
+            (
+                CapabilityType::kSupportedContexts,
+                &[
+                    "album", "playlist", "search", "inbox", "toplist", "starred", "track",
+                ][..],
+            ),
+            (
+                CapabilityType::kSupportedTypes,
+                &["audio/track", "track"][..],
+            ),
+        ] {
+            let capability = capabilities.push_default();
+            capability.set_typ(typ);
+            for value in values {
+                capability.mut_stringValue().push(value.to_string());
+            }
+        }
+
+        let mut state = State::new();
+        state.set_status(PlayStatus::kPlayStatusStop);
+
+        Self {
+            ident,
+            device,
+            state,
+            seq_nr: 0,
+        }
+    }
+
+    /// Whether a controller is playing on ncspot.
+    pub fn is_active(&self) -> bool {
+        self.device.get_is_active()
+    }
+
+    /// A frame of the type `typ` for `recipient`, or for every device if there is none.
+    pub fn frame(&mut self, typ: MessageType, recipient: Option<&str>, now_ms: i64) -> Frame {
+        self.seq_nr += 1;
+        let mut frame = Frame::new();
+        frame.set_version(1);
+        frame.set_protocol_version("2.0.0".to_string());
+        frame.set_ident(self.ident.clone());
+        frame.set_seq_nr(self.seq_nr);
+        frame.set_typ(typ);
+        frame.set_device_state(self.device.clone());
+        frame.set_state_update_id(now_ms);
+        if let Some(recipient) = recipient {
+            frame.mut_recipient().push(recipient.to_string());
+        }
+        if self.is_active() {
+            frame.set_state(self.state.clone());
+        }
+        frame
+    }
+
+    /// Take over the playback state of ncspot.
+    pub fn update(&mut self, snapshot: &Snapshot, now_ms: i64) {
+        self.device.set_volume(snapshot.volume as u32);
+        self.state
+            .set_status(match (snapshot.index, snapshot.playing) {
+                (None, _) => PlayStatus::kPlayStatusStop,
+                (Some(_), true) => PlayStatus::kPlayStatusPlay,
+                (Some(_), false) => PlayStatus::kPlayStatusPause,
+            });
+        self.state.set_position_ms(snapshot.position_ms);
+        self.state.set_position_measured_at(now_ms as u64);
+        self.state
+            .set_playing_track_index(snapshot.index.unwrap_or_default() as u32);
+        self.state.set_shuffle(snapshot.shuffle);
+        self.state.set_repeat(snapshot.repeat);
+
+        let tracks = self.state.mut_track();
+        tracks.clear();
+        for id in &snapshot.tracks {
+            let track = tracks.push_default();
+            if let Ok(spotify_id) = SpotifyId::from_base62(id) {
+                track.set_gid(spotify_id.to_raw().to_vec());
+            }
+            track.set_uri(format!("spotify:track:{id}"));
+        }
+    }
+
+    /// Handle a frame that was sent to the devices of the user.
+    pub fn handle(&mut self, frame: &Frame, now_ms: i64) -> Handled {
+        if frame.get_ident() == self.ident
+            || (!frame.get_recipient().is_empty() && !frame.get_recipient().contains(&self.ident))
+        {
+            return Handled::Ignored;
+        }
+        debug!(
+            "Spotify Connect frame {:?} from {}",
+            frame.get_typ(),
+            frame.get_device_state().get_name()
+        );
+
+        let event = match frame.get_typ() {
+            MessageType::kMessageTypeHello => {
+                let reply = self.frame(
+                    MessageType::kMessageTypeNotify,
+                    Some(frame.get_ident()),
+                    now_ms,
+                );
+                return Handled::Reply(Box::new(reply));
+            }
+            MessageType::kMessageTypeLoad => {
+                if !self.is_active() {
+                    self.device.set_is_active(true);
+                    self.device.set_became_active_at(now_ms);
+                }
+                let state = frame.get_state();
+                let (tracks, index) = track_ids(state.get_track(), state.get_playing_track_index());
+                ConnectEvent::Load {
+                    controller: frame.get_device_state().get_name().to_string(),
+                    tracks,
+                    index,
+                    position_ms: state.get_position_ms(),
+                    playing: state.get_status() == PlayStatus::kPlayStatusPlay,
+                }
+            }
+            MessageType::kMessageTypeNotify => {
+                let device = frame.get_device_state();
+                if self.is_active()
+                    && device.get_is_active()
+                    && self.device.get_became_active_at() <= device.get_became_active_at()
+                {
+                    info!("Spotify Connect device {} took over", device.get_name());
+                    self.device.set_is_active(false);
+                    ConnectEvent::Deactivated
+                } else {
+                    return Handled::Ignored;
+                }
+            }
+            // the remaining requests are only for the active device
+            _ if !self.is_active() => return Handled::Ignored,
+            MessageType::kMessageTypePlay => ConnectEvent::Play,
+            MessageType::kMessageTypePause => ConnectEvent::Pause,
+            MessageType::kMessageTypePlayPause => ConnectEvent::PlayPause,
+            MessageType::kMessageTypeNext => ConnectEvent::Next,
+            MessageType::kMessageTypePrev => ConnectEvent::Previous,
+            MessageType::kMessageTypeSeek => ConnectEvent::Seek(frame.get_position()),
+            MessageType::kMessageTypeVolume => {
+                ConnectEvent::Volume(frame.get_volume().min(u16::MAX as u32) as u16)
+            }
+            MessageType::kMessageTypeVolumeUp => {
+                ConnectEvent::Volume((self.device.get_volume() as u16).saturating_add(VOLUME_STEP))
+            }
+            MessageType::kMessageTypeVolumeDown => {
+                ConnectEvent::Volume((self.device.get_volume() as u16).saturating_sub(VOLUME_STEP))
+            }
+            MessageType::kMessageTypeRepeat => ConnectEvent::Repeat(frame.get_state().get_repeat()),
+            MessageType::kMessageTypeShuffle => {
+                ConnectEvent::Shuffle(frame.get_state().get_shuffle())
+            }
+            _ => return Handled::Ignored,
+        };
+        Handled::Event(event)
+    }
+}
+
+/// The ids of the tracks in `tracks`, and the index of the track at `index` among them. Items
+/// that aren't tracks, like episodes, are left out.
+fn track_ids(tracks: &[TrackRef], index: u32) -> (Vec<String>, usize) {
+    let mut ids = Vec::new();
+    let mut playing = 0;
+    for (i, track) in tracks.iter().enumerate() {
+        let id = match track.get_uri().strip_prefix("spotify:track:") {
+            Some(id) => Some(id.to_string()),
+            None if track.get_uri().is_empty() => SpotifyId::from_raw(track.get_gid())
+                .ok()
+                .and_then(|id| id.to_base62().ok()),
+            None => None,
+        };
+        if let Some(id) = id {
+            if i < index as usize {
+                playing += 1;
+            }
+            ids.push(id);
+        }
+    }
+    let playing = playing.min(ids.len().saturating_sub(1));
+    (ids, playing)
+}
+
+/// The current time in the clock of the Spotify servers.
+fn now_ms(session: &Session) -> i64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_millis() as i64 + 1000 * session.time_delta()
+}
+
+/// Exchange frames with the controllers of the user of `session` until ncspot shuts down.
+pub async fn run(
+    session: Session,
+    mut receiver: Receiver,
+    mut commands: mpsc::UnboundedReceiver<SpircCommand>,
+    events: EventManager,
+) {
+    let username: String =
+        url::form_urlencoded::byte_serialize(session.username().as_bytes()).collect();
+    let uri = format!("hm://remote/user/{username}/");
+    let mut subscription = match session.mercury().subscribe(uri.clone()).await {
+        Ok(subscription) => subscription,
+        Err(e) => {
+            error!("Could not subscribe to Spotify Connect frames: {e:?}");
+            return;
+        }
+    };
+    let mut sender = session.mercury().sender(uri);
+    let send = |frame: Frame| frame.write_to_bytes().expect("frame can be serialized");
+
+    sender.send(send(receiver.frame(
+        MessageType::kMessageTypeHello,
+        None,
+        now_ms(&session),
+    )));
+    info!("Spotify Connect is ready");
+
+    loop {
+        tokio::select! {
+            response = subscription.recv() => {
+                let Some(response) = response else {
+                    error!("Spotify Connect connection was closed");
+                    break;
+                };
+                let Some(frame) = response
+                    .payload
+                    .first()
+                    .and_then(|payload| Frame::parse_from_bytes(payload).ok())
+                else {
+                    warn!("Invalid Spotify Connect frame");
+                    continue;
+                };
+                match receiver.handle(&frame, now_ms(&session)) {
+                    Handled::Ignored => {}
+                    Handled::Reply(frame) => sender.send(send(*frame)),
+                    Handled::Event(event) => events.send(Event::Connect(event)),
+                }
+            }
+            command = commands.recv() => match command {
+                Some(SpircCommand::Update(snapshot)) => {
+                    receiver.update(&snapshot, now_ms(&session));
+                    if receiver.is_active() {
+                        let frame =
+                            receiver.frame(MessageType::kMessageTypeNotify, None, now_ms(&session));
+                        sender.send(send(frame));
+                    }
+                }
+                Some(SpircCommand::Shutdown) | None => {
+                    let frame =
+                        receiver.frame(MessageType::kMessageTypeGoodbye, None, now_ms(&session));
+                    sender.send(send(frame));
+                    if sender.flush().await.is_err() {
+                        warn!("Could not say goodbye to Spotify Connect controllers");
+                    }
+                    break;
+                }
+            },
+            result = sender.flush(), if !sender.is_flushed() => {
+                if let Err(e) = result {
+                    warn!("Could not send Spotify Connect frame: {e:?}");
+                }
+            }
+        }
+    }
+    session.shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receiver() -> Receiver {
+        Receiver::new("ncspot".to_string(), "ncspot".to_string(), 0)
+    }
+
+    fn frame(typ: MessageType, ident: &str) -> Frame {
+        let mut frame = Frame::new();
+        frame.set_typ(typ);
+        frame.set_ident(ident.to_string());
+        frame.mut_device_state().set_name("phone".to_string());
+        frame
+    }
+
+    fn load(tracks: &[&str], index: u32) -> Frame {
+        let mut frame = frame(MessageType::kMessageTypeLoad, "phone");
+        let state = frame.mut_state();
+        state.set_status(PlayStatus::kPlayStatusPlay);
+        state.set_position_ms(1000);
+        state.set_playing_track_index(index);
+        for uri in tracks {
+            state.mut_track().push_default().set_uri(uri.to_string());
+        }
+        frame
+    }
+
+    #[test]
+    fn answers_hello() {
+        let mut receiver = receiver();
+        let Handled::Reply(reply) =
+            receiver.handle(&frame(MessageType::kMessageTypeHello, "phone"), 0)
+        else {
+            panic!("no reply to hello");
+        };
+        assert_eq!(reply.get_typ(), MessageType::kMessageTypeNotify);
+        assert_eq!(reply.get_recipient(), ["phone".to_string()]);
+
+        let own = frame(MessageType::kMessageTypeHello, "ncspot");
+        assert_eq!(receiver.handle(&own, 0), Handled::Ignored);
+    }
+
+    #[test]
+    fn loads_tracks_and_becomes_active() {
+        let mut receiver = receiver();
+        let play = frame(MessageType::kMessageTypePlay, "phone");
+        assert_eq!(receiver.handle(&play, 0), Handled::Ignored);
+
+        let tracks = ["spotify:track:a", "spotify:episode:b", "spotify:track:c"];
+        assert_eq!(
+            receiver.handle(&load(&tracks, 2), 10),
+            Handled::Event(ConnectEvent::Load {
+                controller: "phone".to_string(),
+                tracks: vec!["a".to_string(), "c".to_string()],
+                index: 1,
+                position_ms: 1000,
+                playing: true,
+            })
+        );
+        assert!(receiver.is_active());
+        assert_eq!(
+            receiver.handle(&play, 20),
+            Handled::Event(ConnectEvent::Play)
+        );
+    }
+
+    #[test]
+    fn deactivates_when_another_device_takes_over() {
+        let mut receiver = receiver();
+        receiver.handle(&load(&["spotify:track:a"], 0), 10);
+
+        let mut notify = frame(MessageType::kMessageTypeNotify, "speaker");
+        notify.mut_device_state().set_is_active(true);
+        notify.mut_device_state().set_became_active_at(5);
+        assert_eq!(receiver.handle(&notify, 20), Handled::Ignored);
+
+        notify.mut_device_state().set_became_active_at(15);
+        assert_eq!(
+            receiver.handle(&notify, 20),
+            Handled::Event(ConnectEvent::Deactivated)
+        );
+        assert!(!receiver.is_active());
+    }
+}
@@ -0,0 +1,156 @@
+//! Crossfading between consecutive tracks.
+//!
+//! librespot plays one track per player, so the worker keeps a second player around. The next
+//! track is loaded into it ahead of time and started while the current one is still playing, and
+//! the volume of both players is faded by [FadeVolume]s wrapping the shared mixer volume.
+
+use std::f64::consts::FRAC_PI_2;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use librespot_playback::mixer::VolumeGetter;
+
+use crate::model::playable::Playable;
+use crate::model::track::Track;
+use crate::queue::RepeatSetting;
+
+/// The longest crossfade, which has to be shorter than the time librespot preloads tracks at.
+pub const MAX_CROSSFADE: Duration = Duration::from_secs(15);
+
+/// The gain of one player during a crossfade, shared with its [FadeVolume].
+#[derive(Clone)]
+pub struct Gain(Arc<AtomicU64>);
+
+impl Default for Gain {
+    fn default() -> Self {
+        Self(Arc::new(AtomicU64::new(1.0_f64.to_bits())))
+    }
+}
+
+impl Gain {
+    pub fn set(&self, gain: f64) {
+        self.0
+            .store(gain.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// The volume of the mixer, scaled by the [Gain] of a player.
+pub struct FadeVolume {
+    volume: Box<dyn VolumeGetter + Send>,
+    gain: Gain,
+}
+
+impl FadeVolume {
+    pub fn new(volume: Box<dyn VolumeGetter + Send>, gain: Gain) -> Self {
+        Self { volume, gain }
+    }
+}
+
+impl VolumeGetter for FadeVolume {
+    fn attenuation_factor(&self) -> f64 {
+        self.volume.attenuation_factor() * self.gain.get()
+    }
+}
+
+/// The gains of the outgoing and the incoming track at `progress` through a crossfade, from 0 to
+/// 1. The fade keeps the power constant, so there is no dip in loudness halfway through.
+pub fn gains(progress: f64) -> (f64, f64) {
+    let angle = progress.clamp(0.0, 1.0) * FRAC_PI_2;
+    (angle.cos(), angle.sin())
+}
+
+/// How long to crossfade from `current` into `next`, if at all. Repeated tracks and local files
+/// aren't crossfaded, and consecutive tracks of an album only if `albums` is set.
+pub fn duration(
+    seconds: u64,
+    albums: bool,
+    repeat: RepeatSetting,
+    current: &Playable,
+    next: &Playable,
+) -> Option<Duration> {
+    if seconds == 0 || repeat == RepeatSetting::RepeatTrack {
+        return None;
+    }
+    match (current, next) {
+        (Playable::LocalFile(_), _) | (_, Playable::LocalFile(_)) => return None,
+        (Playable::Track(current), Playable::Track(next))
+            if !albums && continues_album(current, next) =>
+        {
+            return None
+        }
+        _ => {}
+    }
+    Some(Duration::from_secs(seconds).min(MAX_CROSSFADE))
+}
+
+/// Whether `next` is the track after `current` on the same album.
+fn continues_album(current: &Track, next: &Track) -> bool {
+    current.album_id.is_some()
+        && current.album_id == next.album_id
+        && ((next.disc_number == current.disc_number
+            && next.track_number == current.track_number + 1)
+            || (next.disc_number == current.disc_number + 1 && next.track_number == 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(album: &str, disc_number: i32, track_number: u32) -> Playable {
+        Playable::Track(Track {
+            track_number,
+            disc_number,
+            album_id: Some(album.to_string()),
+            ..Track::test(&format!("{album}{disc_number}{track_number}"))
+        })
+    }
+
+    #[test]
+    fn constant_power() {
+        assert_eq!(gains(0.0), (1.0, 0.0));
+        for step in 0..=10 {
+            let (outgoing, incoming) = gains(step as f64 / 10.0);
+            assert!((outgoing.powi(2) + incoming.powi(2) - 1.0).abs() < 1e-9);
+        }
+        let (outgoing, incoming) = gains(2.0);
+        assert!(outgoing.abs() < 1e-9 && incoming == 1.0);
+    }
+
+    #[test]
+    fn skips_albums_in_order() {
+        let off = RepeatSetting::None;
+        let fade = Some(Duration::from_secs(5));
+        let (first, second) = (track("a", 1, 1), track("a", 1, 2));
+
+        assert_eq!(duration(5, true, off, &first, &second), fade);
+        assert_eq!(duration(5, false, off, &first, &second), None);
+        // the next disc continues the album
+        assert_eq!(duration(5, false, off, &second, &track("a", 2, 1)), None);
+        // skipping tracks or changing albums doesn't
+        assert_eq!(duration(5, false, off, &first, &track("a", 1, 3)), fade);
+        assert_eq!(duration(5, false, off, &second, &first), fade);
+        assert_eq!(duration(5, false, off, &first, &track("b", 1, 2)), fade);
+    }
+
+    #[test]
+    fn respects_settings() {
+        let (first, second) = (track("a", 1, 1), track("b", 1, 1));
+        assert_eq!(
+            duration(0, true, RepeatSetting::None, &first, &second),
+            None
+        );
+        assert_eq!(
+            duration(5, true, RepeatSetting::RepeatTrack, &first, &second),
+            None
+        );
+        assert_eq!(
+            duration(60, true, RepeatSetting::RepeatPlaylist, &first, &second),
+            Some(MAX_CROSSFADE)
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! Controlling the other Spotify Connect devices of the user through the Web API, i.e. a phone or
+//! a speaker.
+//!
+//! While a device is controlled, ncspot acts as a remote: transport commands are sent to the
+//! device instead of the local player. The requests block, so they are sent from a background
+//! thread.
+
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use log::error;
+use rspotify::model::Device;
+
+use crate::command::{Command, SeekDirection};
+use crate::spotify::{PlayerEvent, Spotify};
+use crate::spotify_api::WebApi;
+
+/// The device that is controlled instead of playing locally, shared by every clone.
+#[derive(Clone, Default)]
+pub struct RemoteDevice(Arc<RwLock<Option<Device>>>);
+
+impl RemoteDevice {
+    /// The controlled device, None while ncspot is the player.
+    pub fn get(&self) -> Option<Device> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn set(&self, device: Option<Device>) {
+        *self.0.write().unwrap() = device;
+    }
+
+    /// Remember the volume the controlled device was set to.
+    fn set_volume(&self, volume: u8) {
+        if let Some(device) = self.0.write().unwrap().as_mut() {
+            device.volume_percent = Some(volume as u32);
+        }
+    }
+}
+
+/// A playback request for the controlled device.
+#[derive(Clone, Debug)]
+pub enum Transport {
+    Play,
+    Pause,
+    TogglePlay,
+    Next,
+    Previous,
+    Seek(SeekDirection),
+    /// Change the volume by this many percent.
+    ChangeVolume(i32),
+    /// Set the volume in percent.
+    SetVolume(u8),
+}
+
+impl Transport {
+    /// The request of the transport command `cmd`, None for commands that aren't for devices.
+    pub fn from_command(cmd: &Command) -> Option<Self> {
+        Some(match cmd {
+            Command::TogglePlay => Self::TogglePlay,
+            Command::Stop => Self::Pause,
+            Command::Next => Self::Next,
+            Command::Previous => Self::Previous,
+            Command::Seek(direction) => Self::Seek(direction.clone()),
+            Command::VolumeUp(amount) => Self::ChangeVolume(*amount as i32),
+            Command::VolumeDown(amount) => Self::ChangeVolume(-(*amount as i32)),
+            _ => return None,
+        })
+    }
+}
+
+/// The label of `device` in lists, which marks the active device.
+pub fn label(device: &Device) -> String {
+    format!(
+        "{} ({:?}){}",
+        device.name,
+        device._type,
+        if device.is_active { " - active" } else { "" }
+    )
+}
+
+/// The device of the user called `name`.
+pub fn find(api: &WebApi, name: &str) -> Result<Device, String> {
+    api.devices()
+        .ok_or("Could not fetch the devices")?
+        .into_iter()
+        .find(|device| device.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("Unknown device \"{name}\""))
+}
+
+/// Control the device of the user called `name` in the background like [control], or play
+/// locally again for None.
+pub fn switch(spotify: &Spotify, name: Option<String>) {
+    let Some(name) = name else {
+        spotify.remote_device.set(None);
+        return;
+    };
+    let spotify = spotify.clone();
+    thread::spawn(move || {
+        let device = find(&spotify.api, &name);
+        if let Err(e) = device.and_then(|device| control(&spotify, Some(device))) {
+            error!("{e}");
+        }
+    });
+}
+
+/// Control `device` from ncspot, or play locally again for None. Playback is transferred to the
+/// device unless it is the active one already, and local playback is paused.
+pub fn control(spotify: &Spotify, device: Option<Device>) -> Result<(), String> {
+    if let Some(device) = &device {
+        let id = match device.id.as_deref() {
+            Some(id) if !device.is_restricted => id,
+            _ => return Err(format!("{} can't be controlled", device.name)),
+        };
+        if !device.is_active {
+            spotify
+                .api
+                .transfer_playback(id)
+                .ok_or_else(|| format!("Could not transfer playback to {}", device.name))?;
+        }
+        if let PlayerEvent::Playing(_) = spotify.get_current_status() {
+            spotify.pause();
+        }
+    }
+    spotify.remote_device.set(device);
+    Ok(())
+}
+
+/// Send `transport` to the controlled device in the background. Returns false while ncspot is
+/// the player, so that the request is carried out locally instead.
+pub fn send(spotify: &Spotify, transport: Transport) -> bool {
+    let Some(device) = spotify.remote_device.get() else {
+        return false;
+    };
+    // the volume is remembered, so that changing it doesn't need to fetch it first
+    match transport {
+        Transport::ChangeVolume(change) => spotify
+            .remote_device
+            .set_volume(changed_volume(&device, change)),
+        Transport::SetVolume(volume) => spotify.remote_device.set_volume(volume),
+        _ => {}
+    }
+
+    let api = spotify.api.clone();
+    thread::spawn(move || {
+        if let Err(e) = execute(&api, &device, &transport) {
+            error!("{e}");
+        }
+    });
+    true
+}
+
+/// Send the transport command `cmd` to the controlled device, see [send]. Returns false for
+/// commands that are carried out locally.
+pub fn send_command(spotify: &Spotify, cmd: &Command) -> bool {
+    Transport::from_command(cmd).is_some_and(|transport| send(spotify, transport))
+}
+
+/// The volume of `device` after changing it by `change` percent.
+fn changed_volume(device: &Device, change: i32) -> u8 {
+    let volume = device.volume_percent.unwrap_or_default() as i32;
+    (volume + change).clamp(0, 100) as u8
+}
+
+/// Send `transport` to `device`, blocking until it is done.
+fn execute(api: &WebApi, device: &Device, transport: &Transport) -> Result<(), String> {
+    let id = device
+        .id
+        .as_deref()
+        .ok_or_else(|| format!("{} can't be controlled", device.name))?;
+    let result = match transport {
+        Transport::Play => api.remote_play(id),
+        Transport::Pause => api.remote_pause(id),
+        Transport::TogglePlay => match api.current_playback() {
+            Some(playback) if playback.is_playing && playback.device.id.as_deref() == Some(id) => {
+                api.remote_pause(id)
+            }
+            _ => api.remote_play(id),
+        },
+        Transport::Next => api.remote_next(id),
+        Transport::Previous => api.remote_previous(id),
+        Transport::Seek(SeekDirection::Absolute(position_ms)) => api.remote_seek(id, *position_ms),
+        Transport::Seek(SeekDirection::Relative(delta)) => {
+            let progress = api
+                .current_playback()
+                .and_then(|playback| playback.progress)
+                .map(|progress| progress.num_milliseconds())
+                .unwrap_or_default();
+            api.remote_seek(id, (progress + *delta as i64).max(0) as u32)
+        }
+        Transport::ChangeVolume(change) => api.remote_volume(id, changed_volume(device, *change)),
+        Transport::SetVolume(volume) => api.remote_volume(id, *volume),
+    };
+    result.ok_or_else(|| format!("Could not control {}", device.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify_api::stub_api;
+
+    const DEVICES: &str = r#"{"devices": [
+        {"id": "phone", "is_active": true, "is_private_session": false, "is_restricted": false,
+         "name": "Phone", "type": "Smartphone", "volume_percent": 98},
+        {"id": "speaker", "is_active": false, "is_private_session": false,
+         "is_restricted": false, "name": "Kitchen", "type": "Speaker", "volume_percent": 40}
+    ]}"#;
+
+    const PLAYBACK: &str = r#"{
+        "device": {"id": "phone", "is_active": true, "is_private_session": false,
+                   "is_restricted": false, "name": "Phone", "type": "Smartphone",
+                   "volume_percent": 98},
+        "repeat_state": "off", "shuffle_state": false, "context": null,
+        "timestamp": 1700000000000, "progress_ms": 60000, "is_playing": true, "item": null,
+        "currently_playing_type": "track", "actions": {"disallows": {}}
+    }"#;
+
+    fn phone(api: &WebApi) -> Device {
+        find(api, "phone").unwrap()
+    }
+
+    #[test]
+    fn lists_devices() {
+        let (address, server) = stub_api(vec![DEVICES]);
+        let api = WebApi::with_base_url(&address);
+        let devices = api.devices().unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(label(&devices[0]), "Phone (Smartphone) - active");
+        assert_eq!(label(&devices[1]), "Kitchen (Speaker)");
+        assert_eq!(server.join().unwrap(), ["GET /me/player/devices"]);
+    }
+
+    #[test]
+    fn toggles_playback_of_the_device() {
+        let (address, server) = stub_api(vec![DEVICES, PLAYBACK, ""]);
+        let api = WebApi::with_base_url(&address);
+        let device = phone(&api);
+        assert_eq!(execute(&api, &device, &Transport::TogglePlay), Ok(()));
+        let requests = server.join().unwrap();
+        assert_eq!(requests[2], "PUT /me/player/pause?device_id=phone");
+    }
+
+    #[test]
+    fn seeks_and_changes_the_volume_relatively() {
+        let (address, server) = stub_api(vec![DEVICES, PLAYBACK, "", ""]);
+        let api = WebApi::with_base_url(&address);
+        let device = phone(&api);
+        let seek = Transport::Seek(SeekDirection::Relative(-10000));
+        assert_eq!(execute(&api, &device, &seek), Ok(()));
+        // the volume of the device is known already
+        assert_eq!(execute(&api, &device, &Transport::ChangeVolume(5)), Ok(()));
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            requests[2],
+            "PUT /me/player/seek?position_ms=50000&device_id=phone"
+        );
+        assert_eq!(
+            requests[3],
+            "PUT /me/player/volume?volume_percent=100&device_id=phone"
+        );
+    }
+
+    #[test]
+    fn leaves_other_commands_to_the_local_player() {
+        assert!(Transport::from_command(&Command::VolumeDown(5)).is_some());
+        assert!(Transport::from_command(&Command::Shuffle(None)).is_none());
+    }
+}
@@ -0,0 +1,322 @@
+//! A parametric equalizer that is applied to the decoded audio before it is written to the sink.
+//!
+//! Every sink of the audio backend is wrapped in an [EqSink], which filters the samples with the
+//! bands of the active preset. The preset is shared through an [Equalizer], so it can be switched
+//! while playing.
+
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+use std::sync::{Arc, RwLock};
+
+use librespot_playback::audio_backend::{Sink, SinkResult};
+use librespot_playback::convert::Converter;
+use librespot_playback::decoder::AudioPacket;
+use librespot_playback::{NUM_CHANNELS, SAMPLE_RATE};
+
+use crate::config::{EqBand, EqPreset, FilterType};
+
+/// A second order IIR filter, with coefficients from the Audio EQ Cookbook by Robert
+/// Bristow-Johnson.
+#[derive(Clone, Debug, Default)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn new(band: &EqBand, sample_rate: f64) -> Self {
+        let freq = band.freq.clamp(1.0, sample_rate / 2.0 - 1.0);
+        let q = band.q.filter(|q| *q > 0.0).unwrap_or(FRAC_1_SQRT_2);
+        let a = 10_f64.powf(band.gain / 40.0);
+        let w0 = 2.0 * PI * freq / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.filter {
+            FilterType::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            FilterType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            ..Default::default()
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+/// The filters of a preset, with separate state for every channel.
+struct FilterChain {
+    preamp: f64,
+    channels: Vec<Vec<Biquad>>,
+}
+
+impl FilterChain {
+    fn new(preset: &EqPreset, channels: usize, sample_rate: f64) -> Self {
+        let filters: Vec<Biquad> = preset
+            .bands
+            .iter()
+            .map(|band| Biquad::new(band, sample_rate))
+            .collect();
+        Self {
+            preamp: 10_f64.powf(preset.preamp.unwrap_or(0.0) / 20.0),
+            channels: vec![filters; channels],
+        }
+    }
+
+    /// Filter interleaved `samples` in place.
+    fn process(&mut self, samples: &mut [f64]) {
+        let channels = self.channels.len();
+        for (index, sample) in samples.iter_mut().enumerate() {
+            let filters = &mut self.channels[index % channels];
+            *sample = filters
+                .iter_mut()
+                .fold(*sample * self.preamp, |sample, filter| {
+                    filter.process(sample)
+                });
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Incremented whenever the preset changes, so sinks know when to rebuild their filters.
+    version: u64,
+    preset: Option<(String, EqPreset)>,
+}
+
+/// The active equalizer preset, shared by every sink.
+#[derive(Clone, Default)]
+pub struct Equalizer {
+    state: Arc<RwLock<State>>,
+}
+
+impl Equalizer {
+    /// Switch to the preset with `name`, or turn the equalizer off for `None`.
+    pub fn set(&self, preset: Option<(String, EqPreset)>) {
+        let mut state = self.state.write().unwrap();
+        state.version += 1;
+        state.preset = preset;
+    }
+
+    /// The name of the active preset, if the equalizer is on.
+    pub fn preset_name(&self) -> Option<String> {
+        let state = self.state.read().unwrap();
+        state.preset.as_ref().map(|(name, _)| name.clone())
+    }
+}
+
+/// A sink that applies the [Equalizer] before writing to another sink.
+pub struct EqSink {
+    sink: Box<dyn Sink>,
+    equalizer: Equalizer,
+    /// The version of the preset `chain` was built for.
+    version: Option<u64>,
+    chain: Option<FilterChain>,
+}
+
+impl EqSink {
+    pub fn new(sink: Box<dyn Sink>, equalizer: Equalizer) -> Self {
+        Self {
+            sink,
+            equalizer,
+            version: None,
+            chain: None,
+        }
+    }
+
+    /// Rebuild the filters if the preset changed.
+    fn update(&mut self) {
+        let state = self.equalizer.state.read().unwrap();
+        if self.version != Some(state.version) {
+            self.version = Some(state.version);
+            self.chain = state.preset.as_ref().map(|(_, preset)| {
+                FilterChain::new(preset, NUM_CHANNELS as usize, SAMPLE_RATE as f64)
+            });
+        }
+    }
+}
+
+impl Sink for EqSink {
+    fn start(&mut self) -> SinkResult<()> {
+        self.sink.start()
+    }
+
+    fn stop(&mut self) -> SinkResult<()> {
+        // a new track shouldn't start with the tail of the filter response of the previous one
+        self.version = None;
+        self.sink.stop()
+    }
+
+    fn write(&mut self, packet: AudioPacket, converter: &mut Converter) -> SinkResult<()> {
+        self.update();
+        match (packet, self.chain.as_mut()) {
+            (AudioPacket::Samples(mut samples), Some(chain)) => {
+                chain.process(&mut samples);
+                self.sink.write(AudioPacket::Samples(samples), converter)
+            }
+            (packet, _) => self.sink.write(packet, converter),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    const RATE: f64 = 44_100.0;
+
+    /// One second of a stereo sine wave with amplitude 0.25 on the left and silence on the right.
+    fn sine(freq: f64) -> Vec<f64> {
+        (0..RATE as usize)
+            .flat_map(|n| [0.25 * (2.0 * PI * freq * n as f64 / RATE).sin(), 0.0])
+            .collect()
+    }
+
+    /// The amplitude of `channel` in the second half of `samples`, after the filters settled.
+    fn amplitude(samples: &[f64], channel: usize) -> f64 {
+        samples[samples.len() / 2..]
+            .iter()
+            .skip(channel)
+            .step_by(2)
+            .fold(0.0, |max, sample| sample.abs().max(max))
+    }
+
+    fn filtered(preset: &EqPreset, freq: f64) -> Vec<f64> {
+        let mut samples = sine(freq);
+        FilterChain::new(preset, 2, RATE).process(&mut samples);
+        samples
+    }
+
+    fn db(gain: f64) -> f64 {
+        20.0 * (gain / 0.25).log10()
+    }
+
+    fn preset(preamp: Option<f64>, bands: &[(FilterType, f64, f64)]) -> EqPreset {
+        EqPreset {
+            preamp,
+            bands: bands
+                .iter()
+                .map(|&(filter, freq, gain)| EqBand {
+                    filter,
+                    freq,
+                    gain,
+                    q: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn flat_is_transparent() {
+        let flat = preset(None, &[(FilterType::Peak, 1_000.0, 0.0)]);
+        let samples = filtered(&flat, 440.0);
+        for (filtered, original) in samples.iter().zip(sine(440.0)) {
+            assert!((filtered - original).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn peak_boosts_around_frequency() {
+        let boost = preset(None, &[(FilterType::Peak, 1_000.0, 6.0)]);
+        assert!((db(amplitude(&filtered(&boost, 1_000.0), 0)) - 6.0).abs() < 0.1);
+        assert!(db(amplitude(&filtered(&boost, 50.0), 0)).abs() < 0.1);
+        assert!(db(amplitude(&filtered(&boost, 15_000.0), 0)).abs() < 0.2);
+        // channels are filtered separately
+        assert_eq!(amplitude(&filtered(&boost, 1_000.0), 1), 0.0);
+    }
+
+    #[test]
+    fn shelves_and_preamp() {
+        let shelves = preset(
+            Some(-3.0),
+            &[
+                (FilterType::LowShelf, 200.0, -12.0),
+                (FilterType::HighShelf, 4_000.0, 6.0),
+            ],
+        );
+        assert!((db(amplitude(&filtered(&shelves, 30.0), 0)) + 15.0).abs() < 0.2);
+        assert!((db(amplitude(&filtered(&shelves, 1_000.0), 0)) + 3.0).abs() < 1.0);
+        assert!((db(amplitude(&filtered(&shelves, 16_000.0), 0)) - 3.0).abs() < 0.2);
+    }
+
+    /// A sink that keeps the samples written to it.
+    struct CaptureSink(Arc<Mutex<Vec<f64>>>);
+
+    impl Sink for CaptureSink {
+        fn write(&mut self, packet: AudioPacket, _: &mut Converter) -> SinkResult<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .extend_from_slice(packet.samples().unwrap());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sink_switches_presets() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let equalizer = Equalizer::default();
+        let mut sink = EqSink::new(Box::new(CaptureSink(written.clone())), equalizer.clone());
+        let mut converter = Converter::new(None);
+        let mut write = |sink: &mut EqSink| {
+            written.lock().unwrap().clear();
+            sink.write(AudioPacket::Samples(sine(441.0)), &mut converter)
+                .unwrap();
+            amplitude(&written.lock().unwrap(), 0)
+        };
+
+        assert!(db(write(&mut sink)).abs() < 1e-6);
+        let loud = preset(Some(6.0), &[]);
+        equalizer.set(Some(("loud".to_string(), loud)));
+        assert!((db(write(&mut sink)) - 6.0).abs() < 1e-6);
+        assert_eq!(equalizer.preset_name(), Some("loud".to_string()));
+        equalizer.set(None);
+        assert!(db(write(&mut sink)).abs() < 1e-6);
+    }
+}
@@ -1,14 +1,33 @@
+use std::time::Duration;
+
 use crossbeam_channel::{unbounded, Receiver, Sender, TryIter};
 use cursive::{CbSink, Cursive};
+use tokio::sync::oneshot;
 
+#[cfg(feature = "connect")]
+use crate::connect::ConnectEvent;
 use crate::queue::QueueEvent;
 use crate::spotify::PlayerEvent;
 
 pub enum Event {
     Player(PlayerEvent),
     Queue(QueueEvent),
+    /// Items were added to, removed from or moved in the queue.
+    QueueChanged,
     SessionDied,
+    /// The state that is published to IPC clients changed outside of the other events, i.e. after
+    /// a command or a change of the volume.
+    StateChanged,
     IpcInput(String),
+    /// Quit ncspot, i.e. when requested by the desktop environment.
+    Quit,
+    /// The playlist with the given id was updated in the library.
+    PlaylistUpdated(String),
+    /// Commands received through an IPC request, whose result should be reported back.
+    IpcCommand(String, oneshot::Sender<Result<Option<String>, String>>),
+    /// A request of a Spotify Connect controller.
+    #[cfg(feature = "connect")]
+    Connect(ConnectEvent),
 }
 
 pub type EventSender = Sender<Event>;
@@ -17,11 +36,12 @@ pub type EventSender = Sender<Event>;
 pub struct EventManager {
     tx: EventSender,
     rx: Receiver<Event>,
-    cursive_sink: CbSink,
+    /// The sink used to wake up the Cursive event loop. None when running without a UI.
+    cursive_sink: Option<CbSink>,
 }
 
 impl EventManager {
-    pub fn new(cursive_sink: CbSink) -> EventManager {
+    pub fn new(cursive_sink: Option<CbSink>) -> EventManager {
         let (tx, rx) = unbounded();
 
         EventManager {
@@ -35,6 +55,11 @@ impl EventManager {
         self.rx.try_iter()
     }
 
+    /// Wait for the next event for at most `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Event> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+
     pub fn send(&self, event: Event) {
         self.tx.send(event).expect("could not send event");
         self.trigger();
@@ -42,8 +67,10 @@ impl EventManager {
 
     pub fn trigger(&self) {
         // send a no-op to trigger event loop processing
-        self.cursive_sink
-            .send(Box::new(Cursive::noop))
-            .expect("could not send no-op event to cursive");
+        if let Some(cursive_sink) = &self.cursive_sink {
+            cursive_sink
+                .send(Box::new(Cursive::noop))
+                .expect("could not send no-op event to cursive");
+        }
     }
 }
@@ -1,20 +1,45 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::SystemTime;
 use std::{io, path::PathBuf};
 
-use futures::SinkExt;
+use futures::{Sink, SinkExt, Stream};
 use log::{debug, error, info};
-use tokio::net::{UnixListener, UnixStream};
+use serde_json::{Map, Value};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
 use tokio::runtime::Handle;
+use tokio::sync::oneshot;
 use tokio::sync::watch::{Receiver, Sender};
 use tokio_stream::wrappers::WatchStream;
 use tokio_stream::StreamExt;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
 use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec};
 
+use crate::config::RemoteControl;
+
 use crate::events::{Event, EventManager};
+use crate::library::Library;
 use crate::model::playable::Playable;
+use crate::queue::{Queue, RepeatSetting};
+use crate::sleep_timer::SleepStatus;
 use crate::spotify::PlayerEvent;
+use crate::spotify_url::SpotifyUrl;
+use crate::utils::tokens_match;
+
+/// The version of the request/response protocol spoken on the IPC socket. Requests that specify a
+/// different version are rejected.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// The longest line or WebSocket message a remote client may send. Remote connections are closed
+/// when it is exceeded, before the line takes up more memory.
+const MAX_REMOTE_LINE_LENGTH: usize = 64 * 1024;
 
 pub struct IpcSocket {
     tx: Sender<Status>,
+    state_tx: Sender<State>,
+    context: Context,
     path: PathBuf,
 }
 
@@ -24,6 +49,251 @@ struct Status {
     playable: Option<Playable>,
 }
 
+/// A request sent by a client, i.e.
+/// `{"version":1,"id":3,"method":"command","params":{"command":"next"}}`.
+#[derive(Debug, Deserialize)]
+struct Request {
+    version: u16,
+    #[serde(default)]
+    id: Value,
+    #[serde(flatten)]
+    method: Method,
+}
+
+/// The methods that can be called using a [Request].
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+enum Method {
+    /// Authenticate a connection from the network with the configured token. Connections through
+    /// the domain socket don't need to authenticate.
+    Authenticate {
+        token: String,
+    },
+    /// Parse and execute one or more commands, exactly like they would be entered on the command
+    /// line.
+    Command {
+        command: String,
+    },
+    /// Append the item identified by a Spotify URI or URL to the queue.
+    QueueAdd {
+        uri: String,
+    },
+    /// Receive change notifications for the given categories. The result contains the current
+    /// state of each category.
+    Subscribe {
+        categories: Vec<Category>,
+    },
+    /// Stop receiving change notifications for the given categories.
+    Unsubscribe {
+        categories: Vec<Category>,
+    },
+    GetStatus,
+    GetQueue,
+    GetLibrary,
+    GetVolume,
+    GetShuffle,
+    GetRepeat,
+    GetSleepTimer,
+}
+
+/// The reply to a [Request], carrying the same `id` as the request.
+#[derive(Debug, Serialize)]
+struct Response {
+    version: u16,
+    id: Value,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Result(Value),
+    Error(String),
+}
+
+/// A group of related state that clients can subscribe to.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+enum Category {
+    Player,
+    Queue,
+    Options,
+    Library,
+}
+
+/// The serialized state of every [Category] at one point in time.
+type State = BTreeMap<Category, Value>;
+
+/// A notification sent to subscribed clients, containing only the fields of `event` that changed,
+/// i.e. `{"version":1,"event":"options","changes":{"volume":45}}`.
+#[derive(Debug, Serialize)]
+struct Notification {
+    version: u16,
+    event: Category,
+    changes: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct PlayerState {
+    mode: PlayerEvent,
+    playable: Option<Playable>,
+}
+
+#[derive(Debug, Serialize)]
+struct QueueState {
+    position: Option<usize>,
+    length: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct OptionsState {
+    volume: u16,
+    shuffle: bool,
+    repeat: RepeatSetting,
+    sleep_timer: Option<SleepStatus>,
+    speed: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct LibraryState {
+    /// Whether the currently playing item is saved in the library.
+    current_saved: bool,
+    #[serde(flatten)]
+    info: LibraryInfo,
+}
+
+#[derive(Debug, Serialize)]
+struct QueueInfo {
+    current: Option<usize>,
+    items: Vec<Playable>,
+}
+
+#[derive(Debug, Serialize)]
+struct LibraryInfo {
+    loaded: bool,
+    tracks: usize,
+    albums: usize,
+    artists: usize,
+    playlists: usize,
+    shows: usize,
+}
+
+/// Everything that's needed to answer requests from within the IPC worker.
+#[derive(Clone)]
+struct Context {
+    ev: EventManager,
+    queue: Arc<Queue>,
+    library: Arc<Library>,
+    state: Receiver<State>,
+}
+
+impl Context {
+    fn library_info(&self) -> LibraryInfo {
+        LibraryInfo {
+            loaded: *self.library.is_done.read().unwrap(),
+            tracks: self.library.tracks.read().unwrap().len(),
+            albums: self.library.albums.read().unwrap().len(),
+            artists: self.library.artists.read().unwrap().len(),
+            playlists: self.library.playlists().len(),
+            shows: self.library.shows.read().unwrap().len(),
+        }
+    }
+
+    /// The current volume in percent.
+    fn volume(&self) -> u16 {
+        let volume = self.queue.get_spotify().volume();
+        (volume as f64 / 65535_f64 * 100.0).round() as u16
+    }
+
+    /// Take a snapshot of the current state of every [Category].
+    fn snapshot(&self) -> State {
+        let spotify = self.queue.get_spotify();
+        let current = self.queue.get_current();
+
+        let player = PlayerState {
+            mode: spotify.get_current_status(),
+            playable: current.clone(),
+        };
+        let queue = QueueState {
+            position: self.queue.get_current_index(),
+            length: self.queue.len(),
+        };
+        let options = OptionsState {
+            volume: self.volume(),
+            shuffle: self.queue.get_shuffle(),
+            repeat: self.queue.get_repeat(),
+            sleep_timer: spotify.sleep_timer.status(SystemTime::now()),
+            speed: spotify.speed(),
+        };
+        let library = LibraryState {
+            current_saved: current
+                .map(|playable| self.library.is_saved_track(&playable))
+                .unwrap_or(false),
+            info: self.library_info(),
+        };
+
+        let mut state = State::new();
+        let mut insert = |category, value: Result<Value, serde_json::Error>| match value {
+            Ok(value) => {
+                state.insert(category, value);
+            }
+            Err(e) => error!("Could not serialize {category:?} state: {e}"),
+        };
+        insert(Category::Player, serde_json::to_value(player));
+        insert(Category::Queue, serde_json::to_value(queue));
+        insert(Category::Options, serde_json::to_value(options));
+        insert(Category::Library, serde_json::to_value(library));
+        state
+    }
+}
+
+/// The state of a single client connection.
+struct Session {
+    /// The token the client has to authenticate with, if any.
+    token: Option<String>,
+    /// Whether the client may send requests and receive updates.
+    authenticated: bool,
+    /// The categories the client is subscribed to, together with the state that was last sent to
+    /// it for each of them.
+    subscriptions: HashMap<Category, Value>,
+}
+
+impl Session {
+    fn new(token: Option<String>) -> Self {
+        Self {
+            authenticated: token.is_none(),
+            token,
+            subscriptions: HashMap::new(),
+        }
+    }
+
+    /// Authenticate the client with `token`, which has to match the configured token, if any.
+    fn authenticate(&mut self, token: &str) -> Outcome {
+        match &self.token {
+            Some(expected) if !tokens_match(expected, token) => {
+                Outcome::Error(String::from("Invalid token"))
+            }
+            _ => {
+                self.authenticated = true;
+                Outcome::Result(Value::Null)
+            }
+        }
+    }
+}
+
+/// Return the fields of the object `new` whose values differ from those in the object `old`.
+fn diff(old: &Value, new: &Value) -> Map<String, Value> {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => new
+            .iter()
+            .filter(|(key, value)| old.get(*key) != Some(value))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        _ => Map::new(),
+    }
+}
+
 impl Drop for IpcSocket {
     fn drop(&mut self) {
         log::info!("Removing IPC socket: {:?}", self.path);
@@ -32,7 +302,14 @@ impl Drop for IpcSocket {
 }
 
 impl IpcSocket {
-    pub fn new(handle: &Handle, path: PathBuf, ev: EventManager) -> io::Result<IpcSocket> {
+    pub fn new(
+        handle: &Handle,
+        path: PathBuf,
+        ev: EventManager,
+        queue: Arc<Queue>,
+        library: Arc<Library>,
+        remote: RemoteControl,
+    ) -> io::Result<IpcSocket> {
         let path = if path.exists() && Self::is_open_socket(&path) {
             let mut new_path = path;
             new_path.set_file_name(format!("ncspot.{}.sock", std::process::id()));
@@ -52,14 +329,55 @@ impl IpcSocket {
         };
 
         let (tx, rx) = tokio::sync::watch::channel(status);
+        let (state_tx, state_rx) = tokio::sync::watch::channel(State::new());
         let listener_path = path.clone();
+        let context = Context {
+            ev,
+            queue,
+            library,
+            state: state_rx,
+        };
+        let worker_context = context.clone();
+        let worker_rx = rx.clone();
         handle.spawn(async move {
             let listener =
                 UnixListener::bind(listener_path).expect("Could not create IPC domain socket");
-            Self::worker(listener, ev, rx.clone()).await;
+            Self::worker(listener, worker_context, worker_rx).await;
         });
 
-        Ok(IpcSocket { tx, path })
+        match remote.token.filter(|token| !token.is_empty()) {
+            Some(token) => {
+                if let Some(address) = remote.tcp {
+                    handle.spawn(Self::remote_worker(
+                        address,
+                        false,
+                        token.clone(),
+                        context.clone(),
+                        rx.clone(),
+                    ));
+                }
+                if let Some(address) = remote.websocket {
+                    handle.spawn(Self::remote_worker(
+                        address,
+                        true,
+                        token,
+                        context.clone(),
+                        rx.clone(),
+                    ));
+                }
+            }
+            None if remote.tcp.is_some() || remote.websocket.is_some() => {
+                error!("Not starting remote control listeners, a token has to be configured");
+            }
+            None => {}
+        }
+
+        Ok(IpcSocket {
+            tx,
+            state_tx,
+            context,
+            path,
+        })
     }
 
     fn is_open_socket(path: &PathBuf) -> bool {
@@ -74,14 +392,27 @@ impl IpcSocket {
         self.tx.send(status).expect("Error publishing IPC update");
     }
 
-    async fn worker(listener: UnixListener, ev: EventManager, tx: Receiver<Status>) {
+    /// Take a snapshot of the current state and notify subscribed clients of any changes.
+    pub fn update(&self) {
+        let state = self.context.snapshot();
+        self.state_tx.send_if_modified(|current| {
+            if *current != state {
+                *current = state;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    async fn worker(listener: UnixListener, context: Context, tx: Receiver<Status>) {
         loop {
             match listener.accept().await {
                 Ok((stream, sockaddr)) => {
                     debug!("Connection from {:?}", sockaddr);
                     tokio::spawn(Self::stream_handler(
                         stream,
-                        ev.clone(),
+                        context.clone(),
                         WatchStream::new(tx.clone()),
                     ));
                 }
@@ -90,24 +421,138 @@ impl IpcSocket {
         }
     }
 
+    /// Accept connections from the network on `address`, either as plain TCP or as WebSocket
+    /// connections. Clients have to authenticate with `token`.
+    async fn remote_worker(
+        address: String,
+        websocket: bool,
+        token: String,
+        context: Context,
+        tx: Receiver<Status>,
+    ) {
+        let listener = match TcpListener::bind(&address).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Could not listen for remote control connections on {address}: {e}");
+                return;
+            }
+        };
+        info!("Listening for remote control connections on {address}");
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, sockaddr)) => {
+                    debug!("Remote connection from {:?}", sockaddr);
+                    let context = context.clone();
+                    let rx = WatchStream::new(tx.clone());
+                    let token = Some(token.clone());
+                    tokio::spawn(async move {
+                        let result = if websocket {
+                            Self::websocket_handler(stream, context, rx, token).await
+                        } else {
+                            Self::tcp_handler(stream, context, rx, token).await
+                        };
+                        if let Err(e) = result {
+                            error!("Remote connection from {sockaddr:?} failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => error!("Error accepting remote connection: {e}"),
+            }
+        }
+    }
+
     async fn stream_handler(
         mut stream: UnixStream,
-        ev: EventManager,
-        mut rx: WatchStream<Status>,
+        context: Context,
+        rx: WatchStream<Status>,
     ) -> Result<(), String> {
         let (reader, writer) = stream.split();
-        let mut framed_reader = FramedRead::new(reader, LinesCodec::new());
-        let mut framed_writer = FramedWrite::new(writer, LinesCodec::new());
+        let framed_reader = FramedRead::new(reader, LinesCodec::new());
+        let framed_writer = FramedWrite::new(writer, LinesCodec::new());
+        Self::connection_handler(framed_reader, framed_writer, context, rx, None).await
+    }
+
+    async fn tcp_handler(
+        mut stream: TcpStream,
+        context: Context,
+        rx: WatchStream<Status>,
+        token: Option<String>,
+    ) -> Result<(), String> {
+        let (reader, writer) = stream.split();
+        let framed_reader = FramedRead::new(
+            reader,
+            LinesCodec::new_with_max_length(MAX_REMOTE_LINE_LENGTH),
+        );
+        let framed_writer = FramedWrite::new(writer, LinesCodec::new());
+        Self::connection_handler(framed_reader, framed_writer, context, rx, token).await
+    }
+
+    /// Handle a WebSocket connection, where every text message corresponds to one line of the
+    /// line protocol.
+    async fn websocket_handler(
+        stream: TcpStream,
+        context: Context,
+        rx: WatchStream<Status>,
+        token: Option<String>,
+    ) -> Result<(), String> {
+        let config = WebSocketConfig {
+            max_message_size: Some(MAX_REMOTE_LINE_LENGTH),
+            max_frame_size: Some(MAX_REMOTE_LINE_LENGTH),
+            ..Default::default()
+        };
+        let websocket = tokio_tungstenite::accept_async_with_config(stream, Some(config))
+            .await
+            .map_err(|e| e.to_string())?;
+        let (writer, reader) = futures::StreamExt::split(websocket);
+        let reader = reader.filter_map(|message| match message {
+            Ok(Message::Text(text)) => Some(Ok(text)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        });
+        let writer = writer.with(|line: String| {
+            futures::future::ok::<_, tokio_tungstenite::tungstenite::Error>(Message::Text(line))
+        });
+        Self::connection_handler(reader, writer, context, rx, token).await
+    }
+
+    /// Serve a client connection that reads lines from `reader` and writes lines to `writer`.
+    async fn connection_handler<R, W, RE, WE>(
+        mut reader: R,
+        mut writer: W,
+        context: Context,
+        mut rx: WatchStream<Status>,
+        token: Option<String>,
+    ) -> Result<(), String>
+    where
+        R: Stream<Item = Result<String, RE>> + Unpin,
+        W: Sink<String, Error = WE> + Unpin,
+        RE: Display,
+        WE: Display,
+    {
+        let mut session = Session::new(token);
+        let mut state_rx = WatchStream::from_changes(context.state.clone());
 
         loop {
             tokio::select! {
-                line = framed_reader.next() => {
+                line = reader.next() => {
                     match line {
+                        Some(Ok(line)) if line.trim_start().starts_with('{') => {
+                            debug!("Received request: \"{line}\"");
+                            let response = Self::handle_request(&context, &mut session, &line).await;
+                            let response_str =
+                                serde_json::to_string(&response).map_err(|e| e.to_string())?;
+                            writer.send(response_str).await.map_err(|e| e.to_string())?;
+                        }
+                        Some(Ok(line)) if !session.authenticated => {
+                            error!("Ignoring line from unauthenticated client: \"{line}\"");
+                        }
                         Some(Ok(line)) => {
                             debug!("Received line: \"{line}\"");
-                            ev.send(Event::IpcInput(line));
+                            context.ev.send(Event::IpcInput(line));
                         }
-                        Some(Err(e)) => error!("Error reading line: {e}"),
+                        // i.e. a line that is too long, which is dropped with the connection
+                        Some(Err(e)) => return Err(format!("Error reading line: {e}")),
                         None => {
                             debug!("Closing IPC connection");
                             return Ok(())
@@ -115,9 +560,32 @@ impl IpcSocket {
                     }
                 }
                 Some(status) = rx.next() => {
+                    if !session.authenticated {
+                        continue;
+                    }
                     debug!("IPC Status update: {status:?}");
                     let status_str = serde_json::to_string(&status).map_err(|e| e.to_string())?;
-                    framed_writer.send(status_str).await.map_err(|e| e.to_string())?;
+                    writer.send(status_str).await.map_err(|e| e.to_string())?;
+                }
+                Some(state) = state_rx.next() => {
+                    for (category, last) in session.subscriptions.iter_mut() {
+                        let Some(current) = state.get(category) else {
+                            continue;
+                        };
+                        let changes = diff(last, current);
+                        if changes.is_empty() {
+                            continue;
+                        }
+                        *last = current.clone();
+                        let notification = Notification {
+                            version: PROTOCOL_VERSION,
+                            event: *category,
+                            changes,
+                        };
+                        let notification_str =
+                            serde_json::to_string(&notification).map_err(|e| e.to_string())?;
+                        writer.send(notification_str).await.map_err(|e| e.to_string())?;
+                    }
                 }
                 else => {
                     error!("All streams are closed");
@@ -126,4 +594,257 @@ impl IpcSocket {
             }
         }
     }
+
+    /// Parse a single request line, or return the response to send back if it isn't a valid
+    /// request.
+    fn parse_request(line: &str) -> Result<Request, Response> {
+        let request: Request = serde_json::from_str(line).map_err(|e| Response {
+            version: PROTOCOL_VERSION,
+            id: Value::Null,
+            outcome: Outcome::Error(format!("Invalid request: {e}")),
+        })?;
+        if request.version != PROTOCOL_VERSION {
+            return Err(Response {
+                version: PROTOCOL_VERSION,
+                id: request.id,
+                outcome: Outcome::Error(format!(
+                    "Unsupported protocol version {}, expected {PROTOCOL_VERSION}",
+                    request.version
+                )),
+            });
+        }
+        Ok(request)
+    }
+
+    /// Parse a single request line and compute the response that should be sent back.
+    async fn handle_request(context: &Context, session: &mut Session, line: &str) -> Response {
+        let request = match Self::parse_request(line) {
+            Ok(request) => request,
+            Err(response) => return response,
+        };
+
+        let outcome = if let Method::Authenticate { token } = request.method {
+            session.authenticate(&token)
+        } else if !session.authenticated {
+            Outcome::Error(String::from("Not authenticated"))
+        } else {
+            match Self::call(context, &mut session.subscriptions, request.method).await {
+                Ok(value) => Outcome::Result(value),
+                Err(e) => Outcome::Error(e),
+            }
+        };
+
+        Response {
+            version: PROTOCOL_VERSION,
+            id: request.id,
+            outcome,
+        }
+    }
+
+    async fn call(
+        context: &Context,
+        subscriptions: &mut HashMap<Category, Value>,
+        method: Method,
+    ) -> Result<Value, String> {
+        let spotify = context.queue.get_spotify();
+        let value = match method {
+            Method::Authenticate { .. } => Ok(Value::Null),
+            Method::Command { command } => {
+                // Commands need access to the UI, so they are executed by the main event loop,
+                // which reports the result back.
+                let (tx, rx) = oneshot::channel();
+                context.ev.send(Event::IpcCommand(command, tx));
+                let output = rx
+                    .await
+                    .map_err(|_| String::from("Command was not executed"))??;
+                serde_json::to_value(output)
+            }
+            Method::QueueAdd { uri } => {
+                let url = SpotifyUrl::from_uri(&uri)
+                    .ok_or_else(|| format!("Invalid Spotify URI \"{uri}\""))?;
+                let queue = context.queue.clone();
+                let library = context.library.clone();
+                // Resolving the item uses the blocking Web API client.
+                let added = tokio::task::spawn_blocking(move || {
+                    let mut item = url.resolve(&queue.get_spotify().api)?;
+                    item.queue(&queue);
+                    Some(item.display_left(&library))
+                })
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("Could not find \"{uri}\""))?;
+                context.ev.trigger();
+                serde_json::to_value(added)
+            }
+            Method::Subscribe { categories } => {
+                let state = context.state.borrow().clone();
+                let mut result = State::new();
+                for category in categories {
+                    let current = state.get(&category).cloned().unwrap_or(Value::Null);
+                    subscriptions.insert(category, current.clone());
+                    result.insert(category, current);
+                }
+                serde_json::to_value(result)
+            }
+            Method::Unsubscribe { categories } => {
+                for category in categories {
+                    subscriptions.remove(&category);
+                }
+                Ok(Value::Null)
+            }
+            Method::GetStatus => serde_json::to_value(Status {
+                mode: spotify.get_current_status(),
+                playable: context.queue.get_current(),
+            }),
+            Method::GetQueue => serde_json::to_value(QueueInfo {
+                current: context.queue.get_current_index(),
+                items: context.queue.queue.read().unwrap().clone(),
+            }),
+            Method::GetLibrary => serde_json::to_value(context.library_info()),
+            Method::GetVolume => serde_json::to_value(context.volume()),
+            Method::GetShuffle => serde_json::to_value(context.queue.get_shuffle()),
+            Method::GetRepeat => serde_json::to_value(context.queue.get_repeat()),
+            Method::GetSleepTimer => {
+                serde_json::to_value(spotify.sleep_timer.status(SystemTime::now()))
+            }
+        };
+        value.map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(response: Response) -> String {
+        match response.outcome {
+            Outcome::Error(e) => e,
+            Outcome::Result(value) => panic!("expected an error, got {value}"),
+        }
+    }
+
+    #[test]
+    fn parses_requests() {
+        let request = IpcSocket::parse_request(
+            r#"{"version":1,"id":3,"method":"command","params":{"command":"next"}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.id, 3);
+        assert!(matches!(request.method, Method::Command { command } if command == "next"));
+
+        let request =
+            IpcSocket::parse_request(r#"{"version":1,"id":"a","method":"get_volume"}"#).unwrap();
+        assert_eq!(request.id, "a");
+        assert!(matches!(request.method, Method::GetVolume));
+
+        let request = IpcSocket::parse_request(
+            r#"{"version":1,"method":"subscribe","params":{"categories":["player","queue"]}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.id, Value::Null);
+        assert!(matches!(
+            request.method,
+            Method::Subscribe { categories } if categories == [Category::Player, Category::Queue]
+        ));
+    }
+
+    #[test]
+    fn serializes_responses() {
+        let response = Response {
+            version: PROTOCOL_VERSION,
+            id: Value::from(3),
+            outcome: Outcome::Result(Value::from(45)),
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({"version": 1, "id": 3, "result": 45})
+        );
+
+        let response = Response {
+            version: PROTOCOL_VERSION,
+            id: Value::from("a"),
+            outcome: Outcome::Error(String::from("Not authenticated")),
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({"version": 1, "id": "a", "error": "Not authenticated"})
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        let response =
+            IpcSocket::parse_request(r#"{"version":1,"id":3,"method":"reboot"}"#).unwrap_err();
+        assert_eq!(response.id, Value::Null);
+        assert!(error(response).starts_with("Invalid request: unknown variant `reboot`"));
+
+        let response = IpcSocket::parse_request(r#"{"version":1,"method":"#).unwrap_err();
+        assert!(error(response).starts_with("Invalid request: EOF"));
+
+        let response =
+            IpcSocket::parse_request(r#"{"version":1,"method":"command","params":{"cmd":"next"}}"#)
+                .unwrap_err();
+        assert!(error(response).contains("missing field `command`"));
+
+        let response =
+            IpcSocket::parse_request(r#"{"version":2,"id":7,"method":"get_volume"}"#).unwrap_err();
+        assert_eq!(response.id, 7);
+        assert_eq!(
+            error(response),
+            "Unsupported protocol version 2, expected 1"
+        );
+    }
+
+    #[test]
+    fn authenticates_with_the_configured_token() {
+        let mut session = Session::new(Some(String::from("secret")));
+        assert!(!session.authenticated);
+        assert!(matches!(
+            session.authenticate("secret"),
+            Outcome::Result(Value::Null)
+        ));
+        assert!(session.authenticated);
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        let mut session = Session::new(Some(String::from("secret")));
+        for token in ["secreT", "secret2", "secre", ""] {
+            assert!(matches!(
+                session.authenticate(token),
+                Outcome::Error(e) if e == "Invalid token"
+            ));
+            assert!(!session.authenticated);
+        }
+
+        let response = IpcSocket::parse_request(r#"{"version":1,"method":"authenticate"}"#)
+            .map(|_| ())
+            .unwrap_err();
+        assert!(error(response).starts_with("Invalid request: missing field `params`"));
+        let response =
+            IpcSocket::parse_request(r#"{"version":1,"method":"authenticate","params":{}}"#)
+                .map(|_| ())
+                .unwrap_err();
+        assert!(error(response).starts_with("Invalid request: missing field `token`"));
+    }
+
+    #[test]
+    fn needs_no_token_without_a_configured_one() {
+        let mut session = Session::new(None);
+        assert!(session.authenticated);
+        assert!(matches!(
+            session.authenticate("anything"),
+            Outcome::Result(Value::Null)
+        ));
+    }
+
+    #[test]
+    fn compares_tokens() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(tokens_match("", ""));
+        assert!(!tokens_match("secret", "secreT"));
+        assert!(!tokens_match("secret", "secret2"));
+        assert!(!tokens_match("secret", "secre"));
+        assert!(!tokens_match("secret", ""));
+    }
 }
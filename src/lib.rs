@@ -1,4 +1,5 @@
 use clap::builder::PathBufValueParser;
+use clap::ArgAction;
 use librespot_playback::audio_backend;
 
 pub const AUTHOR: &str = "Henrik Friedrichsen <henrik@affekt.org> and contributors";
@@ -13,7 +14,7 @@ pub fn program_arguments() -> clap::Command {
         format!("Audio backends: {}", backends.join(", "))
     };
 
-    clap::Command::new("ncspot")
+    let command = clap::Command::new("ncspot")
         .version(env!("CARGO_PKG_VERSION"))
         .author(AUTHOR)
         .about("cross-platform ncurses Spotify client")
@@ -42,4 +43,53 @@ pub fn program_arguments() -> clap::Command {
                 .help("Filename of config file in basepath")
                 .default_value("config.toml"),
         )
+        .arg(
+            clap::Arg::new("daemon")
+                .long("daemon")
+                .action(ArgAction::SetTrue)
+                .help("Run without a user interface, controlled only through IPC and MPRIS"),
+        );
+
+    // the subcommands talk to a running instance through its IPC socket, which is unix only
+    #[cfg(unix)]
+    let command = command
+        .subcommand(
+            clap::Command::new("send")
+                .about("Send commands to a running instance")
+                .arg(
+                    clap::Arg::new("command")
+                        .value_name("COMMAND")
+                        .num_args(1..)
+                        .required(true)
+                        .help("The command to execute, i.e. \"next\" or \"seek +10s\""),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("status")
+                .about("Print the playback status of a running instance")
+                .arg(
+                    clap::Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Print the status as JSON"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("queue")
+                .about("Inspect or modify the queue of a running instance")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("add")
+                        .about("Add a track, album, playlist, artist, show or episode")
+                        .arg(
+                            clap::Arg::new("uri")
+                                .value_name("URI")
+                                .required(true)
+                                .help("Spotify URI or URL of the item to add"),
+                        ),
+                )
+                .subcommand(clap::Command::new("list").about("List the items in the queue")),
+        );
+
+    command
 }
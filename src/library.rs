@@ -1,8 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::iter::Iterator;
 use std::ops::Deref;
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, RwLock, RwLockReadGuard};
 use std::thread;
 
 use log::{debug, error, info};
@@ -12,19 +14,28 @@ use serde::Serialize;
 
 use crate::config::Config;
 use crate::config::{self, CACHE_VERSION};
-use crate::events::EventManager;
+use crate::events::{Event, EventManager};
 use crate::model::album::Album;
 use crate::model::artist::Artist;
+use crate::model::local_file::{self, LocalFile};
+use crate::model::play::Play;
 use crate::model::playable::Playable;
 use crate::model::playlist::Playlist;
 use crate::model::show::Show;
 use crate::model::track::Track;
+use crate::search_query::SearchQuery;
 use crate::spotify::Spotify;
+use crate::traits::ListItem;
+use crate::utils;
 
 const CACHE_TRACKS: &str = "tracks.db";
 const CACHE_ALBUMS: &str = "albums.db";
 const CACHE_ARTISTS: &str = "artists.db";
 const CACHE_PLAYLISTS: &str = "playlists.db";
+const HISTORY: &str = "history.jsonl";
+
+/// The number of plays the listening history keeps.
+const MAX_HISTORY: usize = 20_000;
 
 #[derive(Clone)]
 pub struct Library {
@@ -34,6 +45,12 @@ pub struct Library {
     pub playlists: Arc<RwLock<Vec<Playlist>>>,
     pub shows: Arc<RwLock<Vec<Show>>>,
     pub is_done: Arc<RwLock<bool>>,
+    /// The audio files in the configured music directory.
+    pub local_files: Arc<RwLock<Vec<LocalFile>>>,
+    /// The last [MAX_HISTORY] plays, most recent first.
+    pub history: Arc<RwLock<Vec<Play>>>,
+    /// Appends plays to the history file.
+    history_writer: mpsc::Sender<Play>,
     pub user_id: Option<String>,
     pub display_name: Option<String>,
     ev: EventManager,
@@ -47,6 +64,12 @@ impl Library {
         let user_id = current_user.as_ref().map(|u| u.id.id().to_string());
         let display_name = current_user.as_ref().and_then(|u| u.display_name.clone());
 
+        let history_path = config::cache_path(HISTORY);
+        let mut history = read_history(&history_path);
+        let history_writer = spawn_history_writer(history_path, history.len());
+        history.reverse();
+        history.truncate(MAX_HISTORY);
+
         let library = Self {
             tracks: Arc::new(RwLock::new(Vec::new())),
             albums: Arc::new(RwLock::new(Vec::new())),
@@ -54,6 +77,9 @@ impl Library {
             playlists: Arc::new(RwLock::new(Vec::new())),
             shows: Arc::new(RwLock::new(Vec::new())),
             is_done: Arc::new(RwLock::new(false)),
+            local_files: Arc::new(RwLock::new(Vec::new())),
+            history: Arc::new(RwLock::new(history)),
+            history_writer,
             user_id,
             display_name,
             ev,
@@ -62,9 +88,35 @@ impl Library {
         };
 
         library.update_library();
+        library.rescan_local_files();
         library
     }
 
+    /// Whether the audio file of `playable` is in the audio cache, so it can be played offline.
+    pub fn is_cached(&self, playable: &Playable) -> bool {
+        playable
+            .id()
+            .is_some_and(|id| self.spotify.audio_cache.contains(&id))
+    }
+
+    /// Add `play` to the listening history.
+    pub fn add_play(&self, play: Play) {
+        {
+            let mut history = self.history.write().unwrap();
+            history.truncate(MAX_HISTORY - 1);
+            history.insert(0, play.clone());
+        }
+        self.history_writer.send(play).ok();
+        self.ev.trigger();
+    }
+
+    /// Write the listening history to `path`, as JSON or CSV depending on its extension.
+    pub fn export_history(&self, path: &Path) -> Result<(), String> {
+        let contents = export_plays(&self.history.read().unwrap(), path)?;
+        std::fs::write(path, contents)
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
     pub fn playlists(&self) -> RwLockReadGuard<Vec<Playlist>> {
         self.playlists.read().expect("can't readlock playlists")
     }
@@ -233,10 +285,95 @@ impl Library {
             let mut is_done = library.is_done.write().unwrap();
             *is_done = true;
 
-            library.ev.trigger();
+            library.ev.send(Event::StateChanged);
         });
     }
 
+    /// Scan the music directory for local files in the background, which happens on startup and
+    /// on request only, as it can take a while for large collections.
+    pub fn rescan_local_files(&self) {
+        let library = self.clone();
+        thread::spawn(move || library.scan_local_files());
+    }
+
+    fn scan_local_files(&self) {
+        let directory = match self.cfg.values().music_directory.clone() {
+            Some(directory) => directory,
+            None => return,
+        };
+        let directory = utils::expand_home(&directory);
+
+        debug!("scanning local files in {}", directory.display());
+        let mut paths = Vec::new();
+        Self::find_audio_files(&directory, &mut HashSet::new(), &mut paths);
+        paths.sort();
+
+        let files: Vec<LocalFile> = paths
+            .iter()
+            .filter_map(|path| {
+                let file = LocalFile::read(path);
+                if file.is_none() {
+                    error!("could not read local file {}", path.display());
+                }
+                file
+            })
+            .collect();
+        info!("found {} local files", files.len());
+        *self.local_files.write().unwrap() = files;
+    }
+
+    /// Recursively collect the playable audio files in `directory`. Symlinked directories are
+    /// followed, but every directory is only scanned once, so symlink loops end.
+    fn find_audio_files(
+        directory: &Path,
+        visited: &mut HashSet<PathBuf>,
+        paths: &mut Vec<PathBuf>,
+    ) {
+        match directory.canonicalize() {
+            Ok(canonical) => {
+                if !visited.insert(canonical) {
+                    return;
+                }
+            }
+            Err(e) => {
+                error!("could not resolve directory {}: {}", directory.display(), e);
+                return;
+            }
+        }
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("could not read directory {}: {}", directory.display(), e);
+                return;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                Self::find_audio_files(&path, visited, paths);
+            } else if path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| local_file::EXTENSIONS.contains(&e.to_lowercase().as_str()))
+                .unwrap_or(false)
+            {
+                paths.push(path);
+            }
+        }
+    }
+
+    /// The local files that match every term of `query`.
+    pub fn search_local_files(&self, query: &SearchQuery) -> Vec<LocalFile> {
+        self.local_files
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|file| query.matches(*file, None))
+            .cloned()
+            .collect()
+    }
+
     fn fetch_shows(&self) {
         debug!("loading shows");
 
@@ -536,6 +673,7 @@ impl Library {
             }
         }
         self.save_cache(config::cache_path(CACHE_PLAYLISTS), self.playlists.clone());
+        self.ev.send(Event::PlaylistUpdated(updated.id.clone()));
     }
 
     pub fn is_saved_track(&self, track: &Playable) -> bool {
@@ -543,6 +681,11 @@ impl Library {
             return false;
         }
 
+        // Local files have no id and can't be saved.
+        if track.id().is_none() {
+            return false;
+        }
+
         let tracks = self.tracks.read().unwrap();
         tracks.iter().any(|t| t.id == track.id())
     }
@@ -830,3 +973,236 @@ impl Library {
         self.ev.trigger();
     }
 }
+
+/// Read the listening history from `path`, oldest play first. Unlike the caches it can't be
+/// fetched again, so it is read regardless of the cache version.
+fn read_history(path: &Path) -> Vec<Play> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return Vec::new(),
+    };
+    contents
+        .lines()
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(play) => Some(play),
+            Err(e) => {
+                error!("can't parse play from {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Append `play` to the history file at `path`, one play per line.
+fn append_play(path: &Path, play: &Play) -> Result<(), String> {
+    let mut line = serde_json::to_string(play).map_err(|e| e.to_string())?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(|e| e.to_string())
+}
+
+/// Drop all but the last `keep` plays from the history file at `path`. Returns how many are left.
+fn rotate_history(path: &Path, keep: usize) -> Result<usize, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let kept = &lines[lines.len().saturating_sub(keep)..];
+    let contents: String = kept.iter().map(|line| format!("{line}\n")).collect();
+    std::fs::write(path, contents).map_err(|e| e.to_string())?;
+    Ok(kept.len())
+}
+
+/// Append the plays that are sent to the history file at `path` on a separate thread, so the UI
+/// isn't blocked. The file is rotated once it holds twice as many plays as are kept, `len` being
+/// the number of plays in it already.
+fn spawn_history_writer(path: PathBuf, mut len: usize) -> mpsc::Sender<Play> {
+    let (tx, rx) = mpsc::channel::<Play>();
+    thread::spawn(move || {
+        for play in rx {
+            if let Err(e) = append_play(&path, &play) {
+                error!("could not save play to {}: {}", path.display(), e);
+                continue;
+            }
+            len += 1;
+            if len >= 2 * MAX_HISTORY {
+                match rotate_history(&path, MAX_HISTORY) {
+                    Ok(kept) => len = kept,
+                    Err(e) => error!("could not rotate {}: {}", path.display(), e),
+                }
+            }
+        }
+    });
+    tx
+}
+
+/// The contents of an export of `plays` to `path`, as JSON or CSV depending on its extension.
+fn export_plays(plays: &[Play], path: &Path) -> Result<String, String> {
+    let columns = [
+        "played_at",
+        "played_ms",
+        "uri",
+        "title",
+        "artists",
+        "album",
+        "duration_ms",
+    ];
+    let rows: Vec<[String; 7]> = plays
+        .iter()
+        .map(|play| {
+            let playable = &play.playable;
+            let (title, album) = match playable {
+                Playable::Track(track) => (track.title.clone(), track.album.clone()),
+                Playable::Episode(episode) => (episode.name.clone(), None),
+                Playable::LocalFile(file) => (file.title.clone(), file.album.clone()),
+            };
+            let artists = match playable {
+                Playable::LocalFile(file) => file.artists.clone(),
+                _ => playable
+                    .artists()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|artist| artist.name)
+                    .collect(),
+            };
+            [
+                play.played_at.to_rfc3339(),
+                play.played.to_string(),
+                playable.uri(),
+                title,
+                artists.join(", "),
+                album.unwrap_or_default(),
+                playable.duration().to_string(),
+            ]
+        })
+        .collect();
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => {
+            let records: Vec<serde_json::Map<String, serde_json::Value>> = rows
+                .into_iter()
+                .map(|row| {
+                    columns
+                        .iter()
+                        .map(|column| column.to_string())
+                        .zip(row.into_iter().map(serde_json::Value::String))
+                        .collect()
+                })
+                .collect();
+            serde_json::to_string_pretty(&records).map_err(|e| e.to_string())
+        }
+        Some("csv") => Ok(std::iter::once(columns.map(String::from))
+            .chain(rows)
+            .map(|row| {
+                let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+                fields.join(",") + "\n"
+            })
+            .collect()),
+        _ => Err("The history can only be exported to .json or .csv files".into()),
+    }
+}
+
+/// Quote `field` for a CSV file if necessary.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::utils::temp_path;
+
+    fn play(title: &str, played_at: i64) -> Play {
+        let mut file = LocalFile::test(title);
+        file.artists = vec!["Band".into(), "Guest, Jr.".into()];
+        Play {
+            playable: Playable::LocalFile(file),
+            played_at: Utc.timestamp_opt(played_at, 0).unwrap(),
+            played: 90_000,
+        }
+    }
+
+    #[test]
+    fn exports_the_history_as_json() {
+        let json = export_plays(&[play("Song", 1_000_000_000)], Path::new("history.json")).unwrap();
+        let records: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(records[0]["played_at"], "2001-09-09T01:46:40+00:00");
+        assert_eq!(records[0]["played_ms"], "90000");
+        assert_eq!(records[0]["uri"], "file:///music/Song.mp3");
+        assert_eq!(records[0]["title"], "Song");
+        assert_eq!(records[0]["artists"], "Band, Guest, Jr.");
+        assert_eq!(records[0]["duration_ms"], "180000");
+    }
+
+    #[test]
+    fn exports_the_history_as_csv() {
+        let plays = [
+            play("Song \"B\"", 1_000_000_060),
+            play("Song", 1_000_000_000),
+        ];
+        let csv = export_plays(&plays, Path::new("history.csv")).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "played_at,played_ms,uri,title,artists,album,duration_ms",
+                "2001-09-09T01:47:40+00:00,90000,file:///music/Song%20%22B%22.mp3,\"Song \"\"B\"\"\",\"Band, Guest, Jr.\",Album,180000",
+                "2001-09-09T01:46:40+00:00,90000,file:///music/Song.mp3,Song,\"Band, Guest, Jr.\",Album,180000",
+            ]
+        );
+        assert!(export_plays(&plays, Path::new("history.txt")).is_err());
+    }
+
+    #[test]
+    fn appends_to_and_rotates_the_history_file() {
+        let path = temp_path("history");
+        for (i, title) in ["One", "Two", "Three"].iter().enumerate() {
+            append_play(&path, &play(title, i as i64)).unwrap();
+        }
+        let titles = |plays: Vec<Play>| -> Vec<String> {
+            plays.iter().map(|play| play.to_string()).collect()
+        };
+        assert_eq!(
+            titles(read_history(&path)),
+            [
+                "Band, Guest, Jr. - One",
+                "Band, Guest, Jr. - Two",
+                "Band, Guest, Jr. - Three"
+            ]
+        );
+
+        assert_eq!(rotate_history(&path, 2).unwrap(), 2);
+        assert_eq!(
+            titles(read_history(&path)),
+            ["Band, Guest, Jr. - Two", "Band, Guest, Jr. - Three"]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn finds_audio_files_once_despite_symlink_loops() {
+        let directory = temp_path("music");
+        let album = directory.join("album");
+        std::fs::create_dir_all(&album).unwrap();
+        std::fs::write(album.join("song.MP3"), "").unwrap();
+        std::fs::write(album.join("cover.jpg"), "").unwrap();
+        std::os::unix::fs::symlink(&directory, album.join("loop")).unwrap();
+        std::os::unix::fs::symlink(&album, directory.join("linked")).unwrap();
+
+        let mut paths = Vec::new();
+        Library::find_audio_files(&directory, &mut HashSet::new(), &mut paths);
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].file_name().unwrap(), "song.MP3");
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
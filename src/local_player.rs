@@ -0,0 +1,610 @@
+//! Playback of audio files from the local music directory, and of Spotify tracks from the audio
+//! cache while Spotify can't be reached.
+//!
+//! librespot can only play Spotify tracks through a session, so these are decoded with symphonia
+//! instead.
+//! The decoded audio is converted to the format librespot produces and written to a sink of the
+//! same audio backend, with the volume of the same mixer applied, so both kinds of items sound
+//! the same and can be mixed in the queue.
+
+use std::fmt;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::mpsc::{self as std_mpsc, TryRecvError};
+use std::thread;
+
+use librespot_playback::audio_backend::Sink;
+use librespot_playback::config::PlayerConfig;
+use librespot_playback::convert::Converter;
+use librespot_playback::decoder::AudioPacket;
+use librespot_playback::mixer::VolumeGetter;
+use librespot_playback::SAMPLE_RATE;
+use log::{debug, error, info, warn};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
+use tokio::sync::mpsc;
+
+use crate::audio_cache::CachedAudio;
+
+/// How long before the end of a file the next item should be preloaded, the same as librespot.
+const PRELOAD_BEFORE_END_MS: u32 = 30_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LocalPlayerEvent {
+    Playing { position_ms: u32 },
+    Paused { position_ms: u32 },
+    Stopped,
+    EndOfTrack,
+    TimeToPreloadNextTrack,
+}
+
+/// Audio the local player can play.
+pub enum Source {
+    /// A file in the local music directory.
+    File(PathBuf),
+    /// The audio of the Spotify track with the URI from the audio cache.
+    Cached(String, Box<CachedAudio>),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Cached(uri, _) => write!(f, "{uri} from the audio cache"),
+        }
+    }
+}
+
+impl MediaSource for CachedAudio {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.len())
+    }
+}
+
+enum LocalPlayerCommand {
+    Load(Source, bool, u32),
+    Play,
+    Pause,
+    Stop(std_mpsc::Sender<()>),
+    Seek(u32),
+}
+
+/// Plays local files on a dedicated thread, controlled the same way as the librespot `Player`.
+pub struct LocalPlayer {
+    commands: std_mpsc::Sender<LocalPlayerCommand>,
+}
+
+impl LocalPlayer {
+    pub fn new<F>(
+        volume: Box<dyn VolumeGetter + Send>,
+        sink_builder: F,
+    ) -> (LocalPlayer, mpsc::UnboundedReceiver<LocalPlayerEvent>)
+    where
+        F: FnOnce() -> Box<dyn Sink> + Send + 'static,
+    {
+        let (commands_tx, commands_rx) = std_mpsc::channel();
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+
+        thread::Builder::new()
+            .name("local-player".into())
+            .spawn(move || {
+                let mut internal = PlayerInternal {
+                    commands: commands_rx,
+                    events: events_tx,
+                    volume,
+                    sink_builder: Some(Box::new(sink_builder)),
+                    sink: None,
+                    sink_running: false,
+                    converter: Converter::new(PlayerConfig::default().ditherer),
+                    file: None,
+                    playing: false,
+                };
+                internal.run();
+                debug!("local player thread finished");
+            })
+            .expect("could not start local player thread");
+
+        (
+            LocalPlayer {
+                commands: commands_tx,
+            },
+            events_rx,
+        )
+    }
+
+    fn command(&self, command: LocalPlayerCommand) -> bool {
+        let sent = self.commands.send(command).is_ok();
+        if !sent {
+            error!("local player thread is not running");
+        }
+        sent
+    }
+
+    pub fn load(&self, source: Source, start_playing: bool, position_ms: u32) {
+        self.command(LocalPlayerCommand::Load(source, start_playing, position_ms));
+    }
+
+    pub fn play(&self) {
+        self.command(LocalPlayerCommand::Play);
+    }
+
+    pub fn pause(&self) {
+        self.command(LocalPlayerCommand::Pause);
+    }
+
+    /// Stop playback and release the audio sink. Blocks until the sink is released, so it is
+    /// free for librespot afterwards.
+    pub fn stop(&self) {
+        let (done_tx, done_rx) = std_mpsc::channel();
+        if self.command(LocalPlayerCommand::Stop(done_tx)) {
+            done_rx.recv().ok();
+        }
+    }
+
+    pub fn seek(&self, position_ms: u32) {
+        self.command(LocalPlayerCommand::Seek(position_ms));
+    }
+}
+
+struct PlayerInternal {
+    commands: std_mpsc::Receiver<LocalPlayerCommand>,
+    events: mpsc::UnboundedSender<LocalPlayerEvent>,
+    volume: Box<dyn VolumeGetter + Send>,
+    sink_builder: Option<Box<dyn FnOnce() -> Box<dyn Sink> + Send>>,
+    sink: Option<Box<dyn Sink>>,
+    sink_running: bool,
+    converter: Converter,
+    file: Option<DecodedFile>,
+    playing: bool,
+}
+
+impl PlayerInternal {
+    fn run(&mut self) {
+        loop {
+            // Only wait for commands if there is nothing to play, commands are handled between
+            // packets otherwise.
+            let command = if self.playing && self.file.is_some() {
+                match self.commands.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match self.commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => break,
+                }
+            };
+
+            match command {
+                Some(command) => self.handle_command(command),
+                None => self.play_packet(),
+            }
+        }
+
+        self.ensure_sink_stopped();
+    }
+
+    fn send_event(&self, event: LocalPlayerEvent) {
+        self.events.send(event).ok();
+    }
+
+    fn position_ms(&self) -> u32 {
+        self.file.as_ref().map(|f| f.position_ms).unwrap_or(0)
+    }
+
+    fn handle_command(&mut self, command: LocalPlayerCommand) {
+        match command {
+            LocalPlayerCommand::Load(source, start_playing, position_ms) => {
+                info!("local player loading {}", source);
+                let name = source.to_string();
+                // The sink keeps running while switching files, so they are played gaplessly.
+                self.file = match DecodedFile::open(source) {
+                    Ok(mut file) => {
+                        if position_ms > 0 {
+                            if let Err(e) = file.seek(position_ms) {
+                                warn!("could not seek in {}: {}", name, e);
+                            }
+                        }
+                        Some(file)
+                    }
+                    Err(e) => {
+                        error!("could not play {}: {}", name, e);
+                        None
+                    }
+                };
+
+                if self.file.is_none() {
+                    self.playing = false;
+                    self.send_event(LocalPlayerEvent::EndOfTrack);
+                } else if start_playing {
+                    self.handle_command(LocalPlayerCommand::Play);
+                } else {
+                    self.handle_command(LocalPlayerCommand::Pause);
+                }
+            }
+            LocalPlayerCommand::Play => {
+                if self.file.is_some() {
+                    self.playing = true;
+                    self.ensure_sink_running();
+                    self.send_event(LocalPlayerEvent::Playing {
+                        position_ms: self.position_ms(),
+                    });
+                }
+            }
+            LocalPlayerCommand::Pause => {
+                if self.file.is_some() {
+                    self.playing = false;
+                    self.ensure_sink_stopped();
+                    self.send_event(LocalPlayerEvent::Paused {
+                        position_ms: self.position_ms(),
+                    });
+                }
+            }
+            LocalPlayerCommand::Stop(done) => {
+                self.file = None;
+                self.playing = false;
+                self.ensure_sink_stopped();
+                self.send_event(LocalPlayerEvent::Stopped);
+                done.send(()).ok();
+            }
+            LocalPlayerCommand::Seek(position_ms) => {
+                if let Some(file) = self.file.as_mut() {
+                    if let Err(e) = file.seek(position_ms) {
+                        warn!("could not seek: {}", e);
+                    }
+                    let position_ms = file.position_ms;
+                    self.send_event(if self.playing {
+                        LocalPlayerEvent::Playing { position_ms }
+                    } else {
+                        LocalPlayerEvent::Paused { position_ms }
+                    });
+                }
+            }
+        }
+    }
+
+    fn play_packet(&mut self) {
+        let (samples, preload) = match self.file.as_mut() {
+            Some(file) => {
+                let samples = file.next_samples();
+                let preload = !file.preload_requested
+                    && file.duration_ms.saturating_sub(file.position_ms) < PRELOAD_BEFORE_END_MS;
+                file.preload_requested |= preload;
+                (samples, preload)
+            }
+            None => return,
+        };
+        if preload {
+            self.send_event(LocalPlayerEvent::TimeToPreloadNextTrack);
+        }
+
+        match samples {
+            Ok(Some(mut samples)) => {
+                let attenuation = self.volume.attenuation_factor();
+                if attenuation < 1.0 {
+                    samples.iter_mut().for_each(|sample| *sample *= attenuation);
+                }
+
+                if let Some(sink) = self.sink.as_mut() {
+                    if let Err(e) = sink.write(AudioPacket::Samples(samples), &mut self.converter) {
+                        error!("could not write to audio sink: {}", e);
+                        self.file = None;
+                        self.playing = false;
+                        self.ensure_sink_stopped();
+                        self.send_event(LocalPlayerEvent::Stopped);
+                    }
+                }
+            }
+            Ok(None) => {
+                self.file = None;
+                self.playing = false;
+                self.send_event(LocalPlayerEvent::EndOfTrack);
+            }
+            Err(e) => {
+                error!("could not decode local file: {}", e);
+                self.file = None;
+                self.playing = false;
+                self.send_event(LocalPlayerEvent::EndOfTrack);
+            }
+        }
+    }
+
+    fn ensure_sink_running(&mut self) {
+        if self.sink.is_none() {
+            self.sink = self.sink_builder.take().map(|builder| builder());
+        }
+        if let Some(sink) = self.sink.as_mut() {
+            if !self.sink_running {
+                match sink.start() {
+                    Ok(()) => self.sink_running = true,
+                    Err(e) => error!("could not start audio sink: {}", e),
+                }
+            }
+        }
+    }
+
+    fn ensure_sink_stopped(&mut self) {
+        if let Some(sink) = self.sink.as_mut() {
+            if self.sink_running {
+                if let Err(e) = sink.stop() {
+                    error!("could not stop audio sink: {}", e);
+                }
+                self.sink_running = false;
+            }
+        }
+    }
+}
+
+/// A file that is being decoded.
+struct DecodedFile {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: TimeBase,
+    duration_ms: u32,
+    position_ms: u32,
+    resampler: Resampler,
+    preload_requested: bool,
+}
+
+impl DecodedFile {
+    fn open(source: Source) -> Result<Self, String> {
+        let mut hint = Hint::new();
+        let media: Box<dyn MediaSource> = match source {
+            Source::File(path) => {
+                if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+                    hint.with_extension(extension);
+                }
+                Box::new(File::open(path).map_err(|e| e.to_string())?)
+            }
+            Source::Cached(_, audio) => {
+                hint.with_extension("ogg");
+                audio
+            }
+        };
+
+        let stream = MediaSourceStream::new(media, Default::default());
+        let format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions {
+                    enable_gapless: true,
+                    ..Default::default()
+                },
+                &MetadataOptions::default(),
+            )
+            .map_err(|e| e.to_string())?
+            .format;
+
+        let track = format
+            .default_track()
+            .ok_or_else(|| String::from("no audio track"))?;
+        let params = &track.codec_params;
+        let sample_rate = params
+            .sample_rate
+            .ok_or_else(|| String::from("unknown sample rate"))?;
+        let time_base = params
+            .time_base
+            .unwrap_or_else(|| TimeBase::new(1, sample_rate));
+        let duration_ms = params
+            .n_frames
+            .map(|frames| time_to_ms(time_base.calc_time(frames)))
+            .unwrap_or(0);
+        let decoder = symphonia::default::get_codecs()
+            .make(params, &DecoderOptions::default())
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            track_id: track.id,
+            format,
+            decoder,
+            time_base,
+            duration_ms,
+            position_ms: 0,
+            resampler: Resampler::new(sample_rate, SAMPLE_RATE),
+            preload_requested: false,
+        })
+    }
+
+    fn seek(&mut self, position_ms: u32) -> Result<(), String> {
+        let seeked = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: Time::from(position_ms as f64 / 1000.0),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .map_err(|e| e.to_string())?;
+        self.decoder.reset();
+        self.resampler.reset();
+        self.position_ms = time_to_ms(self.time_base.calc_time(seeked.actual_ts));
+        self.preload_requested = false;
+        Ok(())
+    }
+
+    /// Decode the next packet into interleaved stereo samples at librespot's sample rate, or
+    /// `None` at the end of the file.
+    fn next_samples(&mut self) -> Result<Option<Vec<f64>>, String> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.to_string()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphoniaError::DecodeError(e)) => {
+                    warn!("skipping undecodable packet: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e.to_string()),
+            };
+            self.position_ms = time_to_ms(self.time_base.calc_time(packet.ts()));
+
+            let spec = *decoded.spec();
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            buffer.copy_interleaved_ref(decoded);
+
+            let channels = spec.channels.count().max(1);
+            let frames: Vec<[f64; 2]> = buffer
+                .samples()
+                .chunks_exact(channels)
+                .map(|frame| {
+                    let left = frame[0] as f64;
+                    let right = frame.get(1).map(|s| *s as f64).unwrap_or(left);
+                    [left, right]
+                })
+                .collect();
+
+            let mut samples = Vec::with_capacity(frames.len() * 2);
+            self.resampler.process(&frames, &mut samples);
+            return Ok(Some(samples));
+        }
+    }
+}
+
+fn time_to_ms(time: Time) -> u32 {
+    (time.seconds * 1000) as u32 + (time.frac * 1000.0) as u32
+}
+
+/// Converts stereo frames between sample rates by linear interpolation.
+struct Resampler {
+    step: f64,
+    /// The position of the next output frame relative to the first frame of the next input,
+    /// which is between the last frame of the previous input and it when negative.
+    position: f64,
+    last: [f64; 2],
+}
+
+impl Resampler {
+    fn new(from: u32, to: u32) -> Self {
+        Self {
+            step: from as f64 / to as f64,
+            position: 0.0,
+            last: [0.0; 2],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.position = 0.0;
+        self.last = [0.0; 2];
+    }
+
+    fn process(&mut self, input: &[[f64; 2]], output: &mut Vec<f64>) {
+        if self.step == 1.0 {
+            output.extend(input.iter().flatten());
+            return;
+        }
+        let last_index = match input.len().checked_sub(1) {
+            Some(last_index) => last_index,
+            None => return,
+        };
+
+        while self.position < last_index as f64 {
+            let (a, b, fraction) = if self.position < 0.0 {
+                (self.last, input[0], self.position + 1.0)
+            } else {
+                let index = self.position.floor();
+                (
+                    input[index as usize],
+                    input[index as usize + 1],
+                    self.position - index,
+                )
+            };
+            output.extend((0..2).map(|c| a[c] + (b[c] - a[c]) * fraction));
+            self.position += self.step;
+        }
+
+        self.position -= input.len() as f64;
+        self.last = input[last_index];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::local_file::write_wav;
+    use crate::utils::temp_path;
+
+    /// The interleaved stereo samples of `frames`.
+    fn stereo(frames: &[f64]) -> Vec<f64> {
+        frames.iter().flat_map(|&sample| [sample, sample]).collect()
+    }
+
+    fn resample(resampler: &mut Resampler, frames: &[f64]) -> Vec<f64> {
+        let input: Vec<[f64; 2]> = frames.iter().map(|&sample| [sample, sample]).collect();
+        let mut output = Vec::new();
+        resampler.process(&input, &mut output);
+        output
+    }
+
+    #[test]
+    fn passes_samples_through_at_the_same_rate() {
+        let mut resampler = Resampler::new(SAMPLE_RATE, SAMPLE_RATE);
+        assert_eq!(resample(&mut resampler, &[0.1, 0.2]), stereo(&[0.1, 0.2]));
+    }
+
+    #[test]
+    fn downsamples() {
+        let mut resampler = Resampler::new(88200, 44100);
+        let frames = [0.0, 0.1, 0.2, 0.3, 0.4, 0.5];
+        assert_eq!(resample(&mut resampler, &frames), stereo(&[0.0, 0.2, 0.4]));
+    }
+
+    #[test]
+    fn upsamples_across_packets() {
+        let mut resampler = Resampler::new(22050, 44100);
+        assert_eq!(resample(&mut resampler, &[0.0, 0.5]), stereo(&[0.0, 0.25]));
+        // the frames between the packets are interpolated from the last frame of the previous one
+        assert_eq!(resample(&mut resampler, &[1.0]), stereo(&[0.5, 0.75]));
+
+        resampler.reset();
+        assert_eq!(resample(&mut resampler, &[1.0, 0.0]), stereo(&[1.0, 0.5]));
+        assert!(resample(&mut resampler, &[]).is_empty());
+    }
+
+    #[test]
+    fn decodes_files_to_stereo_at_the_sample_rate_of_librespot() {
+        let path = temp_path("decode.wav");
+        write_wav(&path, 22050, &[i16::MAX / 2; 22050], &[]);
+
+        let mut file = DecodedFile::open(Source::File(path.clone())).unwrap();
+        assert_eq!(file.duration_ms, 1000);
+        let mut samples = Vec::new();
+        while let Some(packet) = file.next_samples().unwrap() {
+            samples.extend(packet);
+        }
+        // the frames after the last one of the file would be interpolated with the next packet
+        assert_eq!(samples.len(), 2 * (SAMPLE_RATE as usize - 2));
+        assert!(samples.iter().all(|&sample| (sample - 0.5).abs() < 0.001));
+
+        // playback continues from the start of the packet at the position
+        file.seek(500).unwrap();
+        assert!((400..=500).contains(&file.position_ms));
+        assert!(file.next_samples().unwrap().is_some());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::model::playable::Playable;
+
+/// A line of time-synced lyrics.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LyricLine {
+    /// When the line starts, relative to the start of the item.
+    pub time: Duration,
+    pub text: String,
+}
+
+/// Time-synced lyrics, with the lines in chronological order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Lyrics {
+    pub lines: Vec<LyricLine>,
+}
+
+impl Lyrics {
+    /// Parse lyrics in the LRC format. Lines may have multiple timestamps, metadata tags are
+    /// ignored except for `offset`. Returns `None` if there is no timed line at all.
+    pub fn parse(lrc: &str) -> Option<Self> {
+        let mut offset_ms = 0;
+        let mut lines = Vec::new();
+
+        for line in lrc.lines() {
+            let mut rest = line.trim();
+            let mut times = Vec::new();
+            while let Some((tag, remainder)) = rest
+                .strip_prefix('[')
+                .and_then(|tagged| tagged.split_once(']'))
+            {
+                if let Some(time) = parse_timestamp(tag) {
+                    times.push(time);
+                } else if let Some(offset) = tag.strip_prefix("offset:") {
+                    offset_ms = offset.trim().parse().unwrap_or(0);
+                }
+                rest = remainder;
+            }
+
+            for time in times {
+                lines.push((time, rest.trim().to_string()));
+            }
+        }
+
+        if lines.is_empty() {
+            return None;
+        }
+
+        // a positive offset makes the lyrics appear sooner
+        let mut lines: Vec<LyricLine> = lines
+            .into_iter()
+            .map(|(time, text)| LyricLine {
+                time: Duration::from_millis((time as i64 - offset_ms).max(0) as u64),
+                text,
+            })
+            .collect();
+        lines.sort_by_key(|line| line.time);
+
+        Some(Self { lines })
+    }
+
+    /// The index of the line that is sung at `progress`, if any line started yet.
+    pub fn current_line(&self, progress: Duration) -> Option<usize> {
+        self.lines
+            .partition_point(|line| line.time <= progress)
+            .checked_sub(1)
+    }
+}
+
+/// Parse an LRC timestamp like `01:23.45`, `01:23.456`, `01:23:45` or `01:23` into
+/// milliseconds.
+fn parse_timestamp(tag: &str) -> Option<u64> {
+    let (minutes, rest) = tag.split_once(':')?;
+    let (seconds, fraction) = match rest.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (rest, "0"),
+    };
+
+    let minutes: u64 = minutes.trim().parse().ok()?;
+    let seconds: u64 = seconds.parse().ok()?;
+    if seconds >= 60 || fraction.is_empty() || fraction.len() > 3 {
+        return None;
+    }
+    // the fraction is hundredths in most files, but may have any precision
+    let fraction_ms = fraction.parse::<u64>().ok()? * 10_u64.pow(3 - fraction.len() as u32);
+
+    Some((minutes * 60 + seconds) * 1000 + fraction_ms)
+}
+
+/// A source of lyrics.
+pub trait LyricsProvider: Send + Sync {
+    /// The name of the provider, as shown along with the lyrics.
+    fn name(&self) -> &str;
+    /// Look up the lyrics of `playable`. This may block, so it is never called on the UI thread.
+    fn lyrics(&self, playable: &Playable) -> Option<Lyrics>;
+}
+
+/// Lyrics from `.lrc` files in a local directory, named `<artists> - <title>.lrc`,
+/// `<first artist> - <title>.lrc` or `<title>.lrc`. Local audio files may also have an `.lrc`
+/// file with the same name next to them.
+pub struct LocalLyrics {
+    directory: PathBuf,
+}
+
+impl LocalLyrics {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    /// The files that are checked for the lyrics of `playable`, in order.
+    fn candidates(&self, playable: &Playable) -> Vec<PathBuf> {
+        let mut candidates = Vec::new();
+        if let Playable::LocalFile(file) = playable {
+            candidates.push(file.path.with_extension("lrc"));
+        }
+
+        let (title, artists) = match playable {
+            Playable::Track(track) => (track.title.clone(), track.artists.clone()),
+            Playable::LocalFile(file) => (file.title.clone(), file.artists.clone()),
+            Playable::Episode(episode) => (episode.name.clone(), Vec::new()),
+        };
+
+        let mut names = Vec::new();
+        if !artists.is_empty() {
+            names.push(format!("{} - {}", artists.join(", "), title));
+            names.push(format!("{} - {}", artists[0], title));
+        }
+        names.push(title);
+        names.dedup();
+
+        // path separators can't be part of file names
+        candidates.extend(names.iter().map(|name| {
+            self.directory
+                .join(format!("{}.lrc", name.replace('/', "_")))
+        }));
+        candidates
+    }
+}
+
+impl LyricsProvider for LocalLyrics {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn lyrics(&self, playable: &Playable) -> Option<Lyrics> {
+        self.candidates(playable).iter().find_map(|path| {
+            let lrc = fs::read_to_string(path).ok()?;
+            debug!("reading lyrics from {}", path.display());
+            Lyrics::parse(&lrc)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::track::Track;
+    use crate::utils::temp_path;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn line(time: u64, text: &str) -> LyricLine {
+        LyricLine {
+            time: ms(time),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_lrc() {
+        let lrc = "[ar:Someone]\n\
+                   [ti:Something]\n\
+                   [00:12.00]First\n\
+                   [00:15.5]Second\n\
+                   \n\
+                   [00:10.123][01:02:30] Chorus \n\
+                   not a lyric line\n\
+                   [00:20]\n";
+        assert_eq!(
+            Lyrics::parse(lrc).unwrap().lines,
+            vec![
+                line(10_123, "Chorus"),
+                line(12_000, "First"),
+                line(15_500, "Second"),
+                line(20_000, ""),
+                line(62_300, "Chorus"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_offset() {
+        let lrc = "[offset:+500]\n[00:00.20]Soon\n[00:02.00]Later\n";
+        assert_eq!(
+            Lyrics::parse(lrc).unwrap().lines,
+            vec![line(0, "Soon"), line(1_500, "Later")]
+        );
+    }
+
+    #[test]
+    fn parse_plain_text() {
+        assert_eq!(Lyrics::parse("Just some text\n[ar:Someone]\n"), None);
+    }
+
+    #[test]
+    fn current_line() {
+        let lyrics = Lyrics::parse("[00:01.00]One\n[00:02.00]Two\n[00:03.00]Three\n").unwrap();
+        assert_eq!(lyrics.current_line(ms(500)), None);
+        assert_eq!(lyrics.current_line(ms(1_000)), Some(0));
+        assert_eq!(lyrics.current_line(ms(2_999)), Some(1));
+        assert_eq!(lyrics.current_line(ms(60_000)), Some(2));
+    }
+
+    #[test]
+    fn local_provider() {
+        let directory = temp_path("lyrics");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("First Artist - Some_Title.lrc"),
+            "[00:01.00]Found\n",
+        )
+        .unwrap();
+
+        let playable = Playable::Track(Track {
+            artists: vec!["First Artist".to_string(), "Second Artist".to_string()],
+            ..Track::test("Some/Title")
+        });
+        let provider = LocalLyrics::new(directory.clone());
+        let lyrics = provider.lyrics(&playable);
+        let missing = LocalLyrics::new(directory.join("missing")).lyrics(&playable);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(lyrics.unwrap().lines, vec![line(1_000, "Found")]);
+        assert_eq!(missing, None);
+    }
+}
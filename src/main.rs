@@ -11,34 +11,55 @@ use application::{setup_logging, Application};
 use config::set_configuration_base_path;
 use ncspot::program_arguments;
 
+mod alarm;
 mod application;
+mod audio_cache;
 mod authentication;
 mod command;
 mod commands;
 mod config;
+mod crossfade;
+mod devices;
+mod equalizer;
 mod events;
 mod ext_traits;
 mod library;
+mod local_player;
+mod lyrics;
 mod model;
+mod outputs;
 mod panic;
+mod play_tracker;
 mod queue;
+mod scrobbler;
+mod search_query;
 mod serialization;
 mod sharing;
+mod sleep_timer;
 mod spotify;
 mod spotify_api;
 mod spotify_url;
 mod spotify_worker;
+mod stats;
 mod theme;
+mod time_stretch;
 mod traits;
 mod ui;
 mod utils;
 
+#[cfg(unix)]
+mod client;
+#[cfg(feature = "connect")]
+mod connect;
 #[cfg(unix)]
 mod ipc;
 
 #[cfg(feature = "mpris")]
 mod mpris;
 
+#[cfg(feature = "web_ui")]
+mod web;
+
 fn main() -> Result<(), String> {
     // Set a custom backtrace hook that writes the backtrace to a file instead of stdout, since
     // stdout is most likely in use by Cursive.
@@ -56,8 +77,17 @@ fn main() -> Result<(), String> {
     // path.
     set_configuration_base_path(matches.get_one::<PathBuf>("basepath").cloned());
 
+    // Subcommands control an already running instance instead of starting a new one.
+    #[cfg(unix)]
+    if let Some((name, sub_matches)) = matches.subcommand() {
+        std::process::exit(client::run(name, sub_matches));
+    }
+
     // Create the application.
-    let mut application = Application::new(matches.get_one::<String>("config").cloned())?;
+    let mut application = Application::new(
+        matches.get_one::<String>("config").cloned(),
+        matches.get_flag("daemon"),
+    )?;
 
     // Start the application event loop.
     application.run()
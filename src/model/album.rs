@@ -66,6 +66,22 @@ impl Album {
             self.tracks = Some(collected_tracks);
         }
     }
+
+    /// The album `track` appears on, with the little that is known about it from the track.
+    pub fn from_track(track: &Track) -> Option<Self> {
+        Some(Self {
+            id: Some(track.album_id.clone()?),
+            title: track.album.clone()?,
+            artists: track.album_artists.clone(),
+            artist_ids: Vec::new(),
+            year: String::new(),
+            cover_url: track.cover_url.clone(),
+            url: None,
+            tracks: None,
+            added_at: None,
+            total_tracks: None,
+        })
+    }
 }
 
 impl From<&SimplifiedAlbum> for Album {
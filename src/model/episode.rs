@@ -20,6 +20,9 @@ pub struct Episode {
     pub cover_url: Option<String>,
     pub added_at: Option<DateTime<Utc>>,
     pub list_index: usize,
+    /// The ID of the show the episode belongs to, if known.
+    #[serde(default)]
+    pub show_id: Option<String>,
 }
 
 impl Episode {
@@ -40,6 +43,7 @@ impl From<&SimplifiedEpisode> for Episode {
             cover_url: episode.images.get(0).map(|img| img.url.clone()),
             added_at: None,
             list_index: 0,
+            show_id: None,
         }
     }
 }
@@ -56,6 +60,7 @@ impl From<&FullEpisode> for Episode {
             cover_url: episode.images.get(0).map(|img| img.url.clone()),
             added_at: None,
             list_index: 0,
+            show_id: Some(episode.show.id.id().to_string()),
         }
     }
 }
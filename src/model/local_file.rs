@@ -0,0 +1,352 @@
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use symphonia::core::codecs::CodecParameters;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+use url::Url;
+
+use crate::config;
+use crate::library::Library;
+use crate::model::playable::Playable;
+use crate::queue::Queue;
+use crate::traits::{ListItem, ViewExt};
+use crate::utils::ms_to_hms;
+
+/// The file extensions of the audio files that can be played.
+pub const EXTENSIONS: &[&str] = &["aac", "flac", "m4a", "mp3", "oga", "ogg", "wav"];
+
+/// An audio file from the local music directory.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LocalFile {
+    pub path: PathBuf,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub track_number: u32,
+    pub duration: u32,
+    pub added_at: Option<DateTime<Utc>>,
+    pub list_index: usize,
+}
+
+impl LocalFile {
+    /// Read the tags and the duration of the audio file at `path`. Missing titles fall back to
+    /// the file name.
+    pub fn read(path: &Path) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        let added_at = file
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(DateTime::<Utc>::from);
+
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(extension);
+        }
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .ok()?;
+
+        let duration = duration_ms(&probed.format.default_track()?.codec_params);
+
+        let mut file = Self {
+            path: path.to_path_buf(),
+            title: String::new(),
+            artists: Vec::new(),
+            album: None,
+            track_number: 0,
+            duration,
+            added_at,
+            list_index: 0,
+        };
+
+        // Tags can precede the container (i.e. ID3v2) or be part of it, the latter take
+        // precedence.
+        if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+            file.apply_tags(revision);
+        }
+        if let Some(revision) = probed.format.metadata().current() {
+            file.apply_tags(revision);
+        }
+
+        if file.title.is_empty() {
+            file.title = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+        }
+        Some(file)
+    }
+
+    fn apply_tags(&mut self, revision: &MetadataRevision) {
+        let mut artists = Vec::new();
+        for tag in revision.tags() {
+            let value = tag.value.to_string();
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => self.title = value,
+                Some(StandardTagKey::Artist) => artists.push(value),
+                Some(StandardTagKey::Album) => self.album = Some(value),
+                Some(StandardTagKey::TrackNumber) => {
+                    // Track numbers are sometimes stored as "3/12".
+                    let number = value.split('/').next().unwrap_or_default();
+                    self.track_number = number.trim().parse().unwrap_or_default();
+                }
+                _ => {}
+            }
+        }
+        if !artists.is_empty() {
+            self.artists = artists;
+        }
+    }
+
+    pub fn uri(&self) -> String {
+        Url::from_file_path(&self.path)
+            .map(String::from)
+            .unwrap_or_else(|_| self.path.to_string_lossy().into_owned())
+    }
+
+    pub fn duration_str(&self) -> String {
+        ms_to_hms(self.duration)
+    }
+
+    /// Whether any of the title, artists, album or file name contains `query`, ignoring case.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        let file_name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        std::iter::once(self.title.as_str())
+            .chain(self.artists.iter().map(String::as_str))
+            .chain(self.album.as_deref())
+            .chain(std::iter::once(file_name.as_ref()))
+            .any(|field| field.to_lowercase().contains(&query))
+    }
+
+    fn format(&self, format: Option<String>, default: Option<String>, library: &Library) -> String {
+        let format = format.or(default).unwrap_or_default();
+        Playable::format(&Playable::LocalFile(self.clone()), &format, library)
+    }
+}
+
+/// The duration of a track in milliseconds, 0 if it is unknown or the header is bogus.
+fn duration_ms(params: &CodecParameters) -> u32 {
+    let duration = match (params.n_frames, params.time_base, params.sample_rate) {
+        (Some(frames), Some(time_base), _) => {
+            let time = time_base.calc_time(frames);
+            time.seconds
+                .checked_mul(1000)
+                .and_then(|ms| ms.checked_add((time.frac * 1000.0) as u64))
+        }
+        (Some(frames), None, Some(rate)) => frames
+            .checked_mul(1000)
+            .and_then(|ms| ms.checked_div(rate as u64)),
+        _ => None,
+    };
+    duration
+        .and_then(|duration| u32::try_from(duration).ok())
+        .unwrap_or(0)
+}
+
+impl fmt::Display for LocalFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.artists.is_empty() {
+            write!(f, "{}", self.title)
+        } else {
+            write!(f, "{} - {}", self.artists.join(", "), self.title)
+        }
+    }
+}
+
+impl ListItem for LocalFile {
+    fn is_playing(&self, queue: &Queue) -> bool {
+        let current = queue.get_current();
+        current.map(|t| t.uri() == self.uri()).unwrap_or(false)
+    }
+
+    fn display_left(&self, library: &Library) -> String {
+        let formatting = library.cfg.values().track_format.clone();
+        self.format(
+            formatting.and_then(|f| f.left),
+            config::TrackFormat::default().left,
+            library,
+        )
+    }
+
+    fn display_center(&self, library: &Library) -> String {
+        let formatting = library.cfg.values().track_format.clone();
+        self.format(
+            formatting.and_then(|f| f.center),
+            config::TrackFormat::default().center,
+            library,
+        )
+    }
+
+    fn display_right(&self, library: &Library) -> String {
+        let formatting = library.cfg.values().track_format.clone();
+        self.format(
+            formatting.and_then(|f| f.right),
+            config::TrackFormat::default().right,
+            library,
+        )
+    }
+
+    fn play(&mut self, queue: &Queue) {
+        let index = queue.append_next(&vec![Playable::LocalFile(self.clone())]);
+        queue.play(index, true, false);
+    }
+
+    fn play_next(&mut self, queue: &Queue) {
+        queue.insert_after_current(Playable::LocalFile(self.clone()));
+    }
+
+    fn queue(&mut self, queue: &Queue) {
+        queue.append(Playable::LocalFile(self.clone()));
+    }
+
+    fn toggle_saved(&mut self, _library: &Library) {}
+
+    fn save(&mut self, _library: &Library) {}
+
+    fn unsave(&mut self, _library: &Library) {}
+
+    fn open(&self, _queue: Arc<Queue>, _library: Arc<Library>) -> Option<Box<dyn ViewExt>> {
+        None
+    }
+
+    fn share_url(&self) -> Option<String> {
+        None
+    }
+
+    #[inline]
+    fn is_playable(&self) -> bool {
+        true
+    }
+
+    fn as_listitem(&self) -> Box<dyn ListItem> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+impl LocalFile {
+    /// A file called `title` in `/music` that takes 3 minutes, without tags apart from the album.
+    pub fn test(title: &str) -> Self {
+        Self {
+            path: PathBuf::from(format!("/music/{title}.mp3")),
+            title: title.to_string(),
+            artists: Vec::new(),
+            album: Some("Album".to_string()),
+            track_number: 0,
+            duration: 180_000,
+            added_at: None,
+            list_index: 0,
+        }
+    }
+}
+
+/// Write a 16 bit mono WAV file with `samples` at `sample_rate` and the RIFF INFO `tags` to `path`.
+#[cfg(test)]
+pub fn write_wav(path: &Path, sample_rate: u32, samples: &[i16], tags: &[(&[u8; 4], &str)]) {
+    fn chunk(id: &[u8], contents: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(contents);
+        if contents.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    let mut format = Vec::new();
+    format.extend_from_slice(&1u16.to_le_bytes());
+    format.extend_from_slice(&1u16.to_le_bytes());
+    format.extend_from_slice(&sample_rate.to_le_bytes());
+    format.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    format.extend_from_slice(&2u16.to_le_bytes());
+    format.extend_from_slice(&16u16.to_le_bytes());
+
+    let mut info = b"INFO".to_vec();
+    for (id, value) in tags {
+        info.extend(chunk(*id, value.as_bytes()));
+    }
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+
+    let mut wave = b"WAVE".to_vec();
+    wave.extend(chunk(b"fmt ", &format));
+    if !tags.is_empty() {
+        wave.extend(chunk(b"LIST", &info));
+    }
+    wave.extend(chunk(b"data", &data));
+    std::fs::write(path, chunk(b"RIFF", &wave)).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_path;
+
+    #[test]
+    fn reads_tags_and_duration() {
+        let path = temp_path("tagged.wav");
+        let tags: &[(&[u8; 4], &str)] = &[
+            (b"INAM", "Song"),
+            (b"IART", "Band"),
+            (b"IPRD", "Record"),
+            (b"IPRT", "3/12"),
+        ];
+        write_wav(&path, 8000, &[0; 12000], tags);
+
+        let file = LocalFile::read(&path).unwrap();
+        assert_eq!(file.title, "Song");
+        assert_eq!(file.artists, ["Band"]);
+        assert_eq!(file.album.as_deref(), Some("Record"));
+        assert_eq!(file.track_number, 3);
+        assert_eq!(file.duration, 1500);
+        assert_eq!(file.to_string(), "Band - Song");
+        assert!(file.matches("RECO"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_the_file_name_without_tags() {
+        let path = temp_path("untitled song.wav");
+        write_wav(&path, 8000, &[0; 800], &[]);
+
+        let file = LocalFile::read(&path).unwrap();
+        let title = format!("ncspot-{}-untitled song", std::process::id());
+        assert_eq!(file.title, title);
+        assert!(file.artists.is_empty());
+        assert_eq!(file.track_number, 0);
+        assert_eq!(file.duration, 100);
+
+        std::fs::write(&path, "not audio").unwrap();
+        assert!(LocalFile::read(&path).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn treats_bogus_durations_as_unknown() {
+        let mut params = CodecParameters::new();
+        params.with_n_frames(44100).with_sample_rate(0);
+        assert_eq!(duration_ms(&params), 0);
+        params.with_n_frames(u64::MAX).with_sample_rate(44100);
+        assert_eq!(duration_ms(&params), 0);
+        params.with_n_frames(66150);
+        assert_eq!(duration_ms(&params), 1500);
+    }
+}
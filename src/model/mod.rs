@@ -2,7 +2,10 @@ pub mod album;
 pub mod artist;
 pub mod category;
 pub mod episode;
+pub mod local_file;
+pub mod play;
 pub mod playable;
 pub mod playlist;
+pub mod ranked;
 pub mod show;
 pub mod track;
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::{DateTime, Local, Utc};
+
+use crate::library::Library;
+use crate::model::album::Album;
+use crate::model::artist::Artist;
+use crate::model::playable::Playable;
+use crate::model::track::Track;
+use crate::queue::Queue;
+use crate::traits::{ListItem, ViewExt};
+
+/// A record of an item that was played, as shown in the listening history.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Play {
+    pub playable: Playable,
+    /// When playback started.
+    pub played_at: DateTime<Utc>,
+    /// How long the item was played for in milliseconds, excluding pauses.
+    pub played: u32,
+}
+
+impl fmt::Display for Play {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.playable.fmt(f)
+    }
+}
+
+impl ListItem for Play {
+    fn is_playing(&self, queue: &Queue) -> bool {
+        self.playable.is_playing(queue)
+    }
+
+    fn display_left(&self, library: &Library) -> String {
+        self.playable.display_left(library)
+    }
+
+    fn display_center(&self, library: &Library) -> String {
+        self.playable.display_center(library)
+    }
+
+    fn display_right(&self, _library: &Library) -> String {
+        let played_at = self.played_at.with_timezone(&Local);
+        format!(
+            "{} {}",
+            played_at.format("%Y-%m-%d %H:%M"),
+            self.playable.duration_str()
+        )
+    }
+
+    fn play(&mut self, queue: &Queue) {
+        self.playable.play(queue)
+    }
+
+    fn play_next(&mut self, queue: &Queue) {
+        self.playable.play_next(queue)
+    }
+
+    fn queue(&mut self, queue: &Queue) {
+        self.playable.queue(queue)
+    }
+
+    fn toggle_saved(&mut self, library: &Library) {
+        self.playable.toggle_saved(library)
+    }
+
+    fn save(&mut self, library: &Library) {
+        self.playable.save(library)
+    }
+
+    fn unsave(&mut self, library: &Library) {
+        self.playable.unsave(library)
+    }
+
+    fn open(&self, queue: Arc<Queue>, library: Arc<Library>) -> Option<Box<dyn ViewExt>> {
+        self.playable.open(queue, library)
+    }
+
+    fn share_url(&self) -> Option<String> {
+        self.playable.share_url()
+    }
+
+    fn album(&self, queue: &Queue) -> Option<Album> {
+        self.playable.album(queue)
+    }
+
+    fn artists(&self) -> Option<Vec<Artist>> {
+        self.playable.artists()
+    }
+
+    fn track(&self) -> Option<Track> {
+        self.playable.track()
+    }
+
+    #[inline]
+    fn is_playable(&self) -> bool {
+        true
+    }
+
+    /// Actions on a play, i.e. in the context menu, apply to the played item.
+    fn as_listitem(&self) -> Box<dyn ListItem> {
+        self.playable.as_listitem()
+    }
+}
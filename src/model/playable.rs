@@ -5,6 +5,7 @@ use crate::library::Library;
 use crate::model::album::Album;
 use crate::model::artist::Artist;
 use crate::model::episode::Episode;
+use crate::model::local_file::LocalFile;
 use crate::model::track::Track;
 use crate::queue::Queue;
 use crate::traits::{ListItem, ViewExt};
@@ -17,6 +18,7 @@ use std::sync::Arc;
 pub enum Playable {
     Track(Track),
     Episode(Episode),
+    LocalFile(LocalFile),
 }
 
 impl Playable {
@@ -24,7 +26,9 @@ impl Playable {
         formatting
             .replace(
                 "%artists",
-                if let Some(artists) = playable.artists() {
+                if let Playable::LocalFile(file) = playable {
+                    file.artists.join(", ")
+                } else if let Some(artists) = playable.artists() {
                     artists
                         .iter()
                         .map(|artist| artist.clone().name)
@@ -40,6 +44,7 @@ impl Playable {
                 match playable.clone() {
                     Playable::Episode(episode) => episode.name,
                     Playable::Track(track) => track.title,
+                    Playable::LocalFile(file) => file.title,
                 }
                 .as_str(),
             )
@@ -47,16 +52,14 @@ impl Playable {
                 "%album",
                 match playable.clone() {
                     Playable::Track(track) => track.album.unwrap_or_default(),
+                    Playable::LocalFile(file) => file.album.unwrap_or_default(),
                     _ => String::new(),
                 }
                 .as_str(),
             )
             .replace(
                 "%saved",
-                if library.is_saved_track(&match playable.clone() {
-                    Playable::Episode(episode) => Playable::Episode(episode),
-                    Playable::Track(track) => Playable::Track(track),
-                }) {
+                if library.is_saved_track(playable) {
                     if library.cfg.values().use_nerdfont.unwrap_or_default() {
                         "\u{f012c}"
                     } else {
@@ -66,13 +69,26 @@ impl Playable {
                     ""
                 },
             )
+            .replace("%cached", playable.cached_indicator(library))
             .replace("%duration", playable.duration_str().as_str())
     }
 
+    /// The badge of items in the audio cache, empty for other items.
+    pub fn cached_indicator(&self, library: &Library) -> &'static str {
+        if !library.is_cached(self) {
+            ""
+        } else if library.cfg.values().use_nerdfont.unwrap_or_default() {
+            "\u{f01da}"
+        } else {
+            "↓"
+        }
+    }
+
     pub fn id(&self) -> Option<String> {
         match self {
             Playable::Track(track) => track.id.clone(),
             Playable::Episode(episode) => Some(episode.id.clone()),
+            Playable::LocalFile(_) => None,
         }
     }
 
@@ -80,6 +96,7 @@ impl Playable {
         match self {
             Playable::Track(track) => track.uri.clone(),
             Playable::Episode(episode) => episode.uri.clone(),
+            Playable::LocalFile(file) => file.uri(),
         }
     }
 
@@ -87,6 +104,7 @@ impl Playable {
         match self {
             Playable::Track(track) => track.cover_url.clone(),
             Playable::Episode(episode) => episode.cover_url.clone(),
+            Playable::LocalFile(_) => None,
         }
     }
 
@@ -94,6 +112,7 @@ impl Playable {
         match self {
             Playable::Track(track) => track.duration,
             Playable::Episode(episode) => episode.duration,
+            Playable::LocalFile(file) => file.duration,
         }
     }
 
@@ -101,6 +120,7 @@ impl Playable {
         match self {
             Playable::Track(track) => track.list_index,
             Playable::Episode(episode) => episode.list_index,
+            Playable::LocalFile(file) => file.list_index,
         }
     }
 
@@ -108,6 +128,7 @@ impl Playable {
         match self {
             Playable::Track(track) => track.list_index = index,
             Playable::Episode(episode) => episode.list_index = index,
+            Playable::LocalFile(file) => file.list_index = index,
         }
     }
 
@@ -115,6 +136,7 @@ impl Playable {
         match self {
             Playable::Track(track) => track.added_at = added_at,
             Playable::Episode(episode) => episode.added_at = added_at,
+            Playable::LocalFile(file) => file.added_at = added_at,
         }
     }
 
@@ -126,6 +148,7 @@ impl Playable {
         match self {
             Playable::Track(track) => track.as_listitem(),
             Playable::Episode(episode) => episode.as_listitem(),
+            Playable::LocalFile(file) => file.as_listitem(),
         }
     }
 }
@@ -150,6 +173,7 @@ impl From<&Playable> for Option<rspotify::prelude::PlayableId<'_>> {
             Playable::Episode(e) => rspotify::model::EpisodeId::from_id(e.id.clone())
                 .map(rspotify::prelude::PlayableId::Episode)
                 .ok(),
+            Playable::LocalFile(_) => None,
         }
     }
 }
@@ -159,6 +183,7 @@ impl fmt::Display for Playable {
         match self {
             Playable::Track(track) => track.fmt(f),
             Playable::Episode(episode) => episode.fmt(f),
+            Playable::LocalFile(file) => file.fmt(f),
         }
     }
 }
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::library::Library;
+use crate::model::album::Album;
+use crate::model::artist::Artist;
+use crate::model::track::Track;
+use crate::queue::Queue;
+use crate::traits::{ListItem, ViewExt};
+use crate::utils::format_duration;
+
+/// An item together with how often and how long it was listened to, as shown in the listening
+/// statistics.
+#[derive(Clone)]
+pub struct Ranked<I: ListItem + Clone> {
+    pub item: I,
+    pub plays: usize,
+    pub played: Duration,
+}
+
+impl<I: ListItem + Clone> ListItem for Ranked<I> {
+    fn is_playing(&self, queue: &Queue) -> bool {
+        self.item.is_playing(queue)
+    }
+
+    fn display_left(&self, library: &Library) -> String {
+        self.item.display_left(library)
+    }
+
+    fn display_center(&self, library: &Library) -> String {
+        self.item.display_center(library)
+    }
+
+    fn display_right(&self, _library: &Library) -> String {
+        let plays = if self.plays == 1 { "play" } else { "plays" };
+        format!(
+            "{} {}, {}",
+            self.plays,
+            plays,
+            format_duration(&Duration::from_secs(self.played.as_secs()))
+        )
+    }
+
+    fn play(&mut self, queue: &Queue) {
+        self.item.play(queue)
+    }
+
+    fn play_next(&mut self, queue: &Queue) {
+        self.item.play_next(queue)
+    }
+
+    fn queue(&mut self, queue: &Queue) {
+        self.item.queue(queue)
+    }
+
+    fn toggle_saved(&mut self, library: &Library) {
+        self.item.toggle_saved(library)
+    }
+
+    fn save(&mut self, library: &Library) {
+        self.item.save(library)
+    }
+
+    fn unsave(&mut self, library: &Library) {
+        self.item.unsave(library)
+    }
+
+    fn open(&self, queue: Arc<Queue>, library: Arc<Library>) -> Option<Box<dyn ViewExt>> {
+        self.item.open(queue, library)
+    }
+
+    fn share_url(&self) -> Option<String> {
+        self.item.share_url()
+    }
+
+    fn album(&self, queue: &Queue) -> Option<Album> {
+        self.item.album(queue)
+    }
+
+    fn artists(&self) -> Option<Vec<Artist>> {
+        self.item.artists()
+    }
+
+    fn track(&self) -> Option<Track> {
+        self.item.track()
+    }
+
+    fn is_saved(&self, library: &Library) -> Option<bool> {
+        self.item.is_saved(library)
+    }
+
+    fn is_playable(&self) -> bool {
+        self.item.is_playable()
+    }
+
+    fn as_listitem(&self) -> Box<dyn ListItem> {
+        self.item.as_listitem()
+    }
+}
@@ -37,6 +37,30 @@ pub struct Track {
 }
 
 impl Track {
+    /// A track called `title` that takes 3 minutes, without an id, artists or album. Tests set the
+    /// fields they need with struct update syntax.
+    #[cfg(test)]
+    pub fn test(title: &str) -> Self {
+        Self {
+            id: None,
+            uri: format!("spotify:track:{title}"),
+            title: title.to_string(),
+            track_number: 1,
+            disc_number: 1,
+            duration: 180_000,
+            artists: Vec::new(),
+            artist_ids: Vec::new(),
+            album: None,
+            album_id: None,
+            album_artists: Vec::new(),
+            cover_url: None,
+            url: String::new(),
+            added_at: None,
+            list_index: 0,
+            is_local: false,
+        }
+    }
+
     pub fn from_simplified_track(track: &SimplifiedTrack, album: &FullAlbum) -> Track {
         let artists = track
             .artists
@@ -226,7 +250,8 @@ impl ListItem for Track {
             } else {
                 ""
             };
-            format!("{} {}", saved, self.duration_str())
+            let cached = Playable::Track(self.clone()).cached_indicator(library);
+            format!("{}{} {}", cached, saved, self.duration_str())
         }
     }
 
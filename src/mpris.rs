@@ -1,14 +1,18 @@
 use std::collections::HashMap;
 use std::error::Error;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_stream::StreamExt;
 use zbus::zvariant::{ObjectPath, Value};
-use zbus::{dbus_interface, ConnectionBuilder};
+use zbus::{dbus_interface, ConnectionBuilder, SignalContext};
 
 use crate::application::ASYNC_RUNTIME;
+use crate::command::SeekDirection;
+use crate::config::Config;
+use crate::devices::{self, Transport};
+use crate::events::Event;
 use crate::library::Library;
 use crate::model::album::Album;
 use crate::model::episode::Episode;
@@ -19,6 +23,7 @@ use crate::model::track::Track;
 use crate::queue::RepeatSetting;
 use crate::spotify::UriType;
 use crate::spotify_url::SpotifyUrl;
+use crate::time_stretch::{MAX_SPEED, MIN_SPEED};
 use crate::traits::ListItem;
 use crate::{
     events::EventManager,
@@ -26,18 +31,32 @@ use crate::{
     spotify::{PlayerEvent, Spotify, VOLUME_PERCENT},
 };
 
-struct MprisRoot {}
+struct MprisRoot {
+    event: EventManager,
+    configuration: Arc<Config>,
+}
+
+impl MprisRoot {
+    /// The command that brings the terminal running ncspot to the front, if configured.
+    fn raise_cmd(&self) -> Option<String> {
+        self.configuration
+            .values()
+            .raise_cmd
+            .clone()
+            .filter(|cmd| !cmd.is_empty())
+    }
+}
 
 #[dbus_interface(name = "org.mpris.MediaPlayer2")]
 impl MprisRoot {
     #[dbus_interface(property)]
     fn can_quit(&self) -> bool {
-        false
+        true
     }
 
     #[dbus_interface(property)]
     fn can_raise(&self) -> bool {
-        false
+        self.raise_cmd().is_some()
     }
 
     #[dbus_interface(property)]
@@ -60,9 +79,26 @@ impl MprisRoot {
         Vec::new()
     }
 
-    fn raise(&self) {}
+    fn raise(&self) {
+        if let Some(cmd) = self.raise_cmd() {
+            log::info!("Raising ncspot: {cmd}");
+            match std::process::Command::new("sh").args(["-c", &cmd]).spawn() {
+                // wait for the command in the background, so that it doesn't linger as a zombie
+                Ok(mut child) => {
+                    std::thread::spawn(move || {
+                        if let Err(e) = child.wait() {
+                            log::error!("Could not wait for raise command \"{cmd}\": {e}");
+                        }
+                    });
+                }
+                Err(e) => log::error!("Could not execute raise command \"{cmd}\": {e}"),
+            }
+        }
+    }
 
-    fn quit(&self) {}
+    fn quit(&self) {
+        self.event.send(Event::Quit);
+    }
 }
 
 struct MprisPlayer {
@@ -105,23 +141,32 @@ impl MprisPlayer {
 
     #[dbus_interface(property)]
     fn rate(&self) -> f64 {
-        1.0
+        self.spotify.speed()
+    }
+
+    #[dbus_interface(property)]
+    fn set_rate(&self, rate: f64) {
+        // A rate of 0 should act like pausing, rates out of range are ignored.
+        if rate == 0.0 {
+            self.spotify.pause();
+        } else if (MIN_SPEED..=MAX_SPEED).contains(&rate) {
+            self.queue.set_speed(rate);
+        }
+        self.event.trigger();
     }
 
     #[dbus_interface(property)]
     fn minimum_rate(&self) -> f64 {
-        1.0
+        MIN_SPEED
     }
 
     #[dbus_interface(property)]
     fn maximum_rate(&self) -> f64 {
-        1.0
+        MAX_SPEED
     }
 
     #[dbus_interface(property)]
     fn metadata(&self) -> HashMap<String, Value> {
-        let mut hm = HashMap::new();
-
         let playable = self.queue.get_current();
 
         // Fetch full track details in case this playable is based on a SimplifiedTrack
@@ -140,116 +185,17 @@ impl MprisPlayer {
                 }
             }
             Playable::Episode(episode) => Some(Playable::Episode(episode)),
+            Playable::LocalFile(file) => Some(Playable::LocalFile(file)),
         });
         let playable = playable_full.as_ref();
+        let track_id = self
+            .queue
+            .get_current_index()
+            .filter(|_| playable.is_some())
+            .map(track_id)
+            .unwrap_or_else(no_track);
 
-        hm.insert(
-            "mpris:trackid".to_string(),
-            Value::ObjectPath(ObjectPath::from_string_unchecked(format!(
-                "/org/ncspot/{}",
-                playable
-                    .filter(|t| t.id().is_some())
-                    .map(|t| t.uri().replace(':', "/"))
-                    .unwrap_or_else(|| String::from("0"))
-            ))),
-        );
-        hm.insert(
-            "mpris:length".to_string(),
-            Value::I64(playable.map(|t| t.duration() as i64 * 1_000).unwrap_or(0)),
-        );
-        hm.insert(
-            "mpris:artUrl".to_string(),
-            Value::Str(
-                playable
-                    .map(|t| t.cover_url().unwrap_or_default())
-                    .unwrap_or_default()
-                    .into(),
-            ),
-        );
-
-        hm.insert(
-            "xesam:album".to_string(),
-            Value::Str(
-                playable
-                    .and_then(|p| p.track())
-                    .map(|t| t.album.unwrap_or_default())
-                    .unwrap_or_default()
-                    .into(),
-            ),
-        );
-        hm.insert(
-            "xesam:albumArtist".to_string(),
-            Value::Array(
-                playable
-                    .and_then(|p| p.track())
-                    .map(|t| t.album_artists)
-                    .unwrap_or_default()
-                    .into(),
-            ),
-        );
-        hm.insert(
-            "xesam:artist".to_string(),
-            Value::Array(
-                playable
-                    .and_then(|p| p.track())
-                    .map(|t| t.artists)
-                    .unwrap_or_default()
-                    .into(),
-            ),
-        );
-        hm.insert(
-            "xesam:discNumber".to_string(),
-            Value::I32(
-                playable
-                    .and_then(|p| p.track())
-                    .map(|t| t.disc_number)
-                    .unwrap_or(0),
-            ),
-        );
-        hm.insert(
-            "xesam:title".to_string(),
-            Value::Str(
-                playable
-                    .map(|t| match t {
-                        Playable::Track(t) => t.title.clone(),
-                        Playable::Episode(ep) => ep.name.clone(),
-                    })
-                    .unwrap_or_default()
-                    .into(),
-            ),
-        );
-        hm.insert(
-            "xesam:trackNumber".to_string(),
-            Value::I32(
-                playable
-                    .and_then(|p| p.track())
-                    .map(|t| t.track_number)
-                    .unwrap_or(0) as i32,
-            ),
-        );
-        hm.insert(
-            "xesam:url".to_string(),
-            Value::Str(
-                playable
-                    .map(|t| t.share_url().unwrap_or_default())
-                    .unwrap_or_default()
-                    .into(),
-            ),
-        );
-        hm.insert(
-            "xesam:userRating".to_string(),
-            Value::F64(
-                playable
-                    .and_then(|p| p.track())
-                    .map(|t| match self.library.is_saved_track(&Playable::Track(t)) {
-                        true => 1.0,
-                        false => 0.0,
-                    })
-                    .unwrap_or(0.0),
-            ),
-        );
-
-        hm
+        playable_metadata(playable, track_id, &self.library)
     }
 
     #[dbus_interface(property)]
@@ -272,6 +218,9 @@ impl MprisPlayer {
     fn set_volume(&self, volume: f64) {
         log::info!("set volume: {volume}");
         if (0.0..=1.0).contains(&volume) {
+            if devices::send(&self.spotify, Transport::SetVolume((volume * 100.0) as u8)) {
+                return;
+            }
             let vol = (VOLUME_PERCENT as f64) * volume * 100.0;
             self.spotify.set_volume(vol as u16);
             self.event.trigger();
@@ -314,10 +263,15 @@ impl MprisPlayer {
     }
 
     fn next(&self) {
-        self.queue.next(true)
+        if !devices::send(&self.spotify, Transport::Next) {
+            self.queue.next(true)
+        }
     }
 
     fn previous(&self) {
+        if devices::send(&self.spotify, Transport::Previous) {
+            return;
+        }
         if self.spotify.get_current_progress() < Duration::from_secs(5) {
             self.queue.previous();
         } else {
@@ -326,22 +280,34 @@ impl MprisPlayer {
     }
 
     fn pause(&self) {
-        self.spotify.pause()
+        if !devices::send(&self.spotify, Transport::Pause) {
+            self.spotify.pause()
+        }
     }
 
     fn play_pause(&self) {
-        self.queue.toggleplayback()
+        if !devices::send(&self.spotify, Transport::TogglePlay) {
+            self.queue.toggleplayback()
+        }
     }
 
     fn stop(&self) {
-        self.queue.stop()
+        if !devices::send(&self.spotify, Transport::Pause) {
+            self.queue.stop()
+        }
     }
 
     fn play(&self) {
-        self.spotify.play()
+        if !devices::send(&self.spotify, Transport::Play) {
+            self.spotify.play()
+        }
     }
 
     fn seek(&self, offset: i64) {
+        let seek = Transport::Seek(SeekDirection::Relative((offset / 1000) as i32));
+        if devices::send(&self.spotify, seek) {
+            return;
+        }
         if let Some(current_track) = self.queue.get_current() {
             let progress = self.spotify.get_current_progress();
             let new_position = (progress.as_secs() * 1000) as i32
@@ -359,6 +325,10 @@ impl MprisPlayer {
     }
 
     fn set_position(&self, _track: ObjectPath, position: i64) {
+        let seek = Transport::Seek(SeekDirection::Absolute((position / 1000) as u32));
+        if devices::send(&self.spotify, seek) {
+            return;
+        }
         if let Some(current_track) = self.queue.get_current() {
             let position = (position / 1000) as u32;
             let duration = current_track.duration();
@@ -463,8 +433,365 @@ impl MprisPlayer {
     }
 }
 
+struct MprisTrackList {
+    event: EventManager,
+    queue: Arc<Queue>,
+    library: Arc<Library>,
+    spotify: Spotify,
+}
+
+#[dbus_interface(name = "org.mpris.MediaPlayer2.TrackList")]
+impl MprisTrackList {
+    #[dbus_interface(property)]
+    fn tracks(&self) -> Vec<ObjectPath<'static>> {
+        (0..self.queue.len()).map(track_id).collect()
+    }
+
+    #[dbus_interface(property)]
+    fn can_edit_tracks(&self) -> bool {
+        true
+    }
+
+    fn get_tracks_metadata(
+        &self,
+        track_ids: Vec<ObjectPath>,
+    ) -> Vec<HashMap<String, Value<'static>>> {
+        let queue = self.queue.queue.read().unwrap();
+        track_ids
+            .iter()
+            .filter_map(|id| {
+                let index = track_index(id)?;
+                let playable = queue.get(index)?;
+                Some(playable_metadata(
+                    Some(playable),
+                    track_id(index),
+                    &self.library,
+                ))
+            })
+            .collect()
+    }
+
+    fn add_track(&self, uri: &str, after_track: ObjectPath, set_as_current: bool) {
+        let playable = match SpotifyUrl::from_uri(uri) {
+            Some(SpotifyUrl {
+                id,
+                uri_type: UriType::Track,
+            }) => self
+                .spotify
+                .api
+                .track(&id)
+                .map(|track| Playable::Track(Track::from(&track))),
+            Some(SpotifyUrl {
+                id,
+                uri_type: UriType::Episode,
+            }) => self
+                .spotify
+                .api
+                .episode(&id)
+                .map(|episode| Playable::Episode(Episode::from(&episode))),
+            _ => None,
+        };
+
+        let Some(playable) = playable else {
+            log::warn!("Could not add track to the queue: {uri}");
+            return;
+        };
+
+        let index = track_index(&after_track)
+            .map(|index| index + 1)
+            .unwrap_or(0);
+        self.queue.insert(index, playable);
+        if set_as_current {
+            self.queue.play(index, false, false);
+        }
+        self.event.trigger();
+    }
+
+    fn remove_track(&self, track_id: ObjectPath) {
+        if let Some(index) = track_index(&track_id).filter(|&index| index < self.queue.len()) {
+            self.queue.remove(index);
+            self.event.trigger();
+        }
+    }
+
+    fn go_to(&self, track_id: ObjectPath) {
+        if let Some(index) = track_index(&track_id).filter(|&index| index < self.queue.len()) {
+            self.queue.play(index, false, false);
+            self.event.trigger();
+        }
+    }
+
+    #[dbus_interface(signal)]
+    async fn track_list_replaced(
+        ctxt: &SignalContext<'_>,
+        tracks: Vec<ObjectPath<'_>>,
+        current_track: ObjectPath<'_>,
+    ) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn track_added(
+        ctxt: &SignalContext<'_>,
+        metadata: HashMap<String, Value<'_>>,
+        after_track: ObjectPath<'_>,
+    ) -> zbus::Result<()>;
+}
+
+/// A playlist as represented by the MPRIS Playlists interface: its id, name and icon.
+type MprisPlaylist = (ObjectPath<'static>, String, String);
+
+struct MprisPlaylists {
+    event: EventManager,
+    queue: Arc<Queue>,
+    library: Arc<Library>,
+    /// The id of the playlist that was activated last.
+    active: Mutex<Option<String>>,
+}
+
+#[dbus_interface(name = "org.mpris.MediaPlayer2.Playlists")]
+impl MprisPlaylists {
+    #[dbus_interface(property)]
+    fn playlist_count(&self) -> u32 {
+        self.library.playlists().len() as u32
+    }
+
+    #[dbus_interface(property)]
+    fn orderings(&self) -> Vec<String> {
+        vec!["Alphabetical".to_string(), "UserDefined".to_string()]
+    }
+
+    #[dbus_interface(property)]
+    fn active_playlist(&self) -> (bool, MprisPlaylist) {
+        let active = self.active.lock().unwrap();
+        let playlists = self.library.playlists();
+        match playlists
+            .iter()
+            .find(|playlist| Some(&playlist.id) == active.as_ref())
+        {
+            Some(playlist) => (true, mpris_playlist(playlist)),
+            None => (
+                false,
+                ("/".try_into().unwrap(), String::new(), String::new()),
+            ),
+        }
+    }
+
+    fn activate_playlist(&self, playlist_id: ObjectPath) {
+        let playlist = playlist_id_from_path(&playlist_id).and_then(|id| {
+            self.library
+                .playlists()
+                .iter()
+                .find(|playlist| playlist.id == id)
+                .cloned()
+        });
+        let Some(mut playlist) = playlist else {
+            log::warn!("Could not activate unknown playlist: {playlist_id}");
+            return;
+        };
+
+        // Play the tracks in the same order as they are shown in the playlist view.
+        playlist.load_tracks(self.queue.get_spotify());
+        if let Some(order) = self.library.cfg.state().playlist_orders.get(&playlist.id) {
+            playlist.sort(&order.key, &order.direction);
+        }
+        playlist.play(&self.queue);
+
+        *self.active.lock().unwrap() = Some(playlist.id);
+        self.event.trigger();
+    }
+
+    fn get_playlists(
+        &self,
+        index: u32,
+        max_count: u32,
+        order: &str,
+        reverse_order: bool,
+    ) -> Vec<MprisPlaylist> {
+        let mut playlists: Vec<&Playlist> = Vec::new();
+        let library_playlists = self.library.playlists();
+        playlists.extend(library_playlists.iter());
+
+        if order == "Alphabetical" {
+            playlists.sort_by_key(|playlist| playlist.name.to_lowercase());
+        }
+        if reverse_order {
+            playlists.reverse();
+        }
+
+        playlists
+            .into_iter()
+            .skip(index as usize)
+            .take(max_count as usize)
+            .map(mpris_playlist)
+            .collect()
+    }
+
+    #[dbus_interface(signal)]
+    async fn playlist_changed(
+        ctxt: &SignalContext<'_>,
+        playlist: MprisPlaylist,
+    ) -> zbus::Result<()>;
+}
+
+/// The MPRIS representation of `playlist`.
+fn mpris_playlist(playlist: &Playlist) -> MprisPlaylist {
+    (
+        ObjectPath::from_string_unchecked(format!("/org/ncspot/playlist/{}", playlist.id)),
+        playlist.name.clone(),
+        String::new(),
+    )
+}
+
+/// The Spotify id of the playlist with the MPRIS object path `path`.
+fn playlist_id_from_path<'a>(path: &'a ObjectPath) -> Option<&'a str> {
+    path.as_str().strip_prefix("/org/ncspot/playlist/")
+}
+
+/// The object path of the item at `index` in the queue, used as its MPRIS track id.
+fn track_id(index: usize) -> ObjectPath<'static> {
+    ObjectPath::from_string_unchecked(format!("/org/ncspot/queue/{index}"))
+}
+
+/// The track id that signifies the absence of a track.
+fn no_track() -> ObjectPath<'static> {
+    ObjectPath::from_static_str_unchecked("/org/mpris/MediaPlayer2/TrackList/NoTrack")
+}
+
+/// The index in the queue of the item with the MPRIS track id `track_id`.
+fn track_index(track_id: &ObjectPath) -> Option<usize> {
+    track_id
+        .as_str()
+        .strip_prefix("/org/ncspot/queue/")?
+        .parse()
+        .ok()
+}
+
+/// The MPRIS metadata of `playable`, identified by `track_id`.
+fn playable_metadata(
+    playable: Option<&Playable>,
+    track_id: ObjectPath<'static>,
+    library: &Library,
+) -> HashMap<String, Value<'static>> {
+    let mut hm = HashMap::new();
+
+    hm.insert("mpris:trackid".to_string(), Value::ObjectPath(track_id));
+
+    hm.insert(
+        "mpris:length".to_string(),
+        Value::I64(playable.map(|t| t.duration() as i64 * 1_000).unwrap_or(0)),
+    );
+    hm.insert(
+        "mpris:artUrl".to_string(),
+        Value::Str(
+            playable
+                .map(|t| t.cover_url().unwrap_or_default())
+                .unwrap_or_default()
+                .into(),
+        ),
+    );
+
+    hm.insert(
+        "xesam:album".to_string(),
+        Value::Str(
+            playable
+                .and_then(|p| match p {
+                    Playable::LocalFile(file) => file.album.clone(),
+                    p => p.track().and_then(|t| t.album),
+                })
+                .unwrap_or_default()
+                .into(),
+        ),
+    );
+    hm.insert(
+        "xesam:albumArtist".to_string(),
+        Value::Array(
+            playable
+                .and_then(|p| p.track())
+                .map(|t| t.album_artists)
+                .unwrap_or_default()
+                .into(),
+        ),
+    );
+    hm.insert(
+        "xesam:artist".to_string(),
+        Value::Array(
+            playable
+                .and_then(|p| match p {
+                    Playable::LocalFile(file) => Some(file.artists.clone()),
+                    p => p.track().map(|t| t.artists),
+                })
+                .unwrap_or_default()
+                .into(),
+        ),
+    );
+    hm.insert(
+        "xesam:discNumber".to_string(),
+        Value::I32(
+            playable
+                .and_then(|p| p.track())
+                .map(|t| t.disc_number)
+                .unwrap_or(0),
+        ),
+    );
+    hm.insert(
+        "xesam:title".to_string(),
+        Value::Str(
+            playable
+                .map(|t| match t {
+                    Playable::Track(t) => t.title.clone(),
+                    Playable::Episode(ep) => ep.name.clone(),
+                    Playable::LocalFile(file) => file.title.clone(),
+                })
+                .unwrap_or_default()
+                .into(),
+        ),
+    );
+    hm.insert(
+        "xesam:trackNumber".to_string(),
+        Value::I32(
+            playable
+                .and_then(|p| p.track())
+                .map(|t| t.track_number)
+                .unwrap_or(0) as i32,
+        ),
+    );
+    hm.insert(
+        "xesam:url".to_string(),
+        Value::Str(
+            playable
+                .map(|t| match t {
+                    Playable::LocalFile(file) => file.uri(),
+                    t => t.share_url().unwrap_or_default(),
+                })
+                .unwrap_or_default()
+                .into(),
+        ),
+    );
+    hm.insert(
+        "xesam:userRating".to_string(),
+        Value::F64(
+            playable
+                .and_then(|p| p.track())
+                .map(|t| match library.is_saved_track(&Playable::Track(t)) {
+                    true => 1.0,
+                    false => 0.0,
+                })
+                .unwrap_or(0.0),
+        ),
+    );
+
+    hm
+}
+
+/// The kinds of state changes the MPRIS interfaces are notified about.
+enum MprisUpdate {
+    Player,
+    TrackList,
+    /// The playlist with the given id changed.
+    Playlist(String),
+}
+
 pub struct MprisManager {
-    tx: mpsc::UnboundedSender<()>,
+    tx: mpsc::UnboundedSender<MprisUpdate>,
 }
 
 impl MprisManager {
@@ -473,8 +800,24 @@ impl MprisManager {
         queue: Arc<Queue>,
         library: Arc<Library>,
         spotify: Spotify,
+        configuration: Arc<Config>,
     ) -> Self {
-        let root = MprisRoot {};
+        let root = MprisRoot {
+            event: event.clone(),
+            configuration,
+        };
+        let playlists = MprisPlaylists {
+            event: event.clone(),
+            queue: queue.clone(),
+            library: library.clone(),
+            active: Mutex::new(None),
+        };
+        let tracklist = MprisTrackList {
+            event: event.clone(),
+            queue: queue.clone(),
+            library: library.clone(),
+            spotify: spotify.clone(),
+        };
         let player = MprisPlayer {
             event,
             queue,
@@ -482,10 +825,17 @@ impl MprisManager {
             spotify,
         };
 
-        let (tx, rx) = mpsc::unbounded_channel::<()>();
+        let (tx, rx) = mpsc::unbounded_channel::<MprisUpdate>();
 
         ASYNC_RUNTIME.spawn(async {
-            let result = Self::serve(UnboundedReceiverStream::new(rx), root, player).await;
+            let result = Self::serve(
+                UnboundedReceiverStream::new(rx),
+                root,
+                player,
+                tracklist,
+                playlists,
+            )
+            .await;
             if let Err(e) = result {
                 log::error!("MPRIS error: {e}");
             }
@@ -495,14 +845,21 @@ impl MprisManager {
     }
 
     async fn serve(
-        mut rx: UnboundedReceiverStream<()>,
+        mut rx: UnboundedReceiverStream<MprisUpdate>,
         root: MprisRoot,
         player: MprisPlayer,
+        tracklist: MprisTrackList,
+        playlists: MprisPlaylists,
     ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let queue = tracklist.queue.clone();
+        let library = tracklist.library.clone();
+
         let conn = ConnectionBuilder::session()?
             .name("org.mpris.MediaPlayer2.ncspot")?
             .serve_at("/org/mpris/MediaPlayer2", root)?
             .serve_at("/org/mpris/MediaPlayer2", player)?
+            .serve_at("/org/mpris/MediaPlayer2", tracklist)?
+            .serve_at("/org/mpris/MediaPlayer2", playlists)?
             .build()
             .await?;
 
@@ -511,21 +868,104 @@ impl MprisManager {
             .interface::<_, MprisPlayer>("/org/mpris/MediaPlayer2")
             .await?;
         let player_iface = player_iface_ref.get().await;
+        let tracklist_iface_ref = object_server
+            .interface::<_, MprisTrackList>("/org/mpris/MediaPlayer2")
+            .await?;
+        let playlists_iface_ref = object_server
+            .interface::<_, MprisPlaylists>("/org/mpris/MediaPlayer2")
+            .await?;
+
+        // The URIs of the items in the queue as they were last announced to clients.
+        let mut announced: Vec<String> = queue
+            .queue
+            .read()
+            .unwrap()
+            .iter()
+            .map(|p| p.uri())
+            .collect();
 
         loop {
             tokio::select! {
-                Some(()) = rx.next() => {
-                    let ctx = player_iface_ref.signal_context();
-                    player_iface.playback_status_changed(ctx).await?;
-                    player_iface.metadata_changed(ctx).await?;
+                Some(update) = rx.next() => match update {
+                    MprisUpdate::Player => {
+                        let ctx = player_iface_ref.signal_context();
+                        player_iface.playback_status_changed(ctx).await?;
+                        player_iface.metadata_changed(ctx).await?;
+                        player_iface.rate_changed(ctx).await?;
+                    }
+                    MprisUpdate::TrackList => {
+                        let (current, added) = {
+                            let items = queue.queue.read().unwrap();
+                            let current: Vec<String> = items.iter().map(|p| p.uri()).collect();
+                            // Appending a single item is announced as such, every other change
+                            // shifts the track ids, so the whole list is announced again.
+                            let added = (current.len() == announced.len() + 1
+                                && current.starts_with(&announced))
+                                .then(|| {
+                                    let index = current.len() - 1;
+                                    playable_metadata(items.get(index), track_id(index), &library)
+                                });
+                            (current, added)
+                        };
+                        if current == announced {
+                            continue;
+                        }
+
+                        let ctx = tracklist_iface_ref.signal_context();
+                        match added {
+                            Some(metadata) => {
+                                let after_track = announced
+                                    .len()
+                                    .checked_sub(1)
+                                    .map(track_id)
+                                    .unwrap_or_else(no_track);
+                                MprisTrackList::track_added(ctx, metadata, after_track).await?;
+                            }
+                            None => {
+                                let current_track = queue
+                                    .get_current_index()
+                                    .map(track_id)
+                                    .unwrap_or_else(no_track);
+                                let tracks = (0..current.len()).map(track_id).collect();
+                                MprisTrackList::track_list_replaced(ctx, tracks, current_track)
+                                    .await?;
+                            }
+                        }
+                        announced = current;
+                    }
+                    MprisUpdate::Playlist(id) => {
+                        let playlist = library
+                            .playlists()
+                            .iter()
+                            .find(|playlist| playlist.id == id)
+                            .map(mpris_playlist);
+                        if let Some(playlist) = playlist {
+                            let ctx = playlists_iface_ref.signal_context();
+                            MprisPlaylists::playlist_changed(ctx, playlist).await?;
+                        }
+                    }
                 }
             }
         }
     }
 
     pub fn update(&self) {
-        if let Err(e) = self.tx.send(()) {
+        if let Err(e) = self.tx.send(MprisUpdate::Player) {
             log::warn!("Could not update MPRIS state: {e}");
         }
     }
+
+    /// Notify clients of the Playlists interface that the playlist with `id` changed.
+    pub fn playlist_changed(&self, id: String) {
+        if let Err(e) = self.tx.send(MprisUpdate::Playlist(id)) {
+            log::warn!("Could not update MPRIS playlist: {e}");
+        }
+    }
+
+    /// Notify clients of the TrackList interface that the queue changed.
+    pub fn update_tracklist(&self) {
+        if let Err(e) = self.tx.send(MprisUpdate::TrackList) {
+            log::warn!("Could not update MPRIS track list: {e}");
+        }
+    }
 }
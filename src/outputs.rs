@@ -0,0 +1,153 @@
+//! The output devices of the audio backends.
+//!
+//! librespot only lists devices by printing them when the device is set to `?`, so they are
+//! queried here for the backends that can switch between devices.
+
+use librespot_playback::audio_backend;
+#[cfg(feature = "pulseaudio_backend")]
+use std::time::Duration;
+
+/// How long the PulseAudio server gets to list its sinks.
+#[cfg(feature = "pulseaudio_backend")]
+const PULSEAUDIO_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An output device, with the name the backend knows it by.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Device {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+impl Device {
+    /// The description of the device if there is one, and its name otherwise.
+    pub fn label(&self) -> &str {
+        self.description.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// The output devices of the audio backend `backend`, or the default backend for `None`.
+pub fn devices(backend: Option<&str>) -> Result<Vec<Device>, String> {
+    let backend = match backend {
+        Some(backend) => backend,
+        None => audio_backend::BACKENDS
+            .first()
+            .map(|backend| backend.0)
+            .ok_or("No audio backend available")?,
+    };
+
+    match backend {
+        #[cfg(feature = "pulseaudio_backend")]
+        "pulseaudio" => pulseaudio_devices(),
+        #[cfg(feature = "alsa_backend")]
+        "alsa" => alsa_devices(),
+        #[cfg(feature = "rodio_backend")]
+        "rodio" => rodio_devices(),
+        backend => Err(format!(
+            "The devices of the {backend} backend can't be listed"
+        )),
+    }
+}
+
+/// The sinks of the PulseAudio server, if it lists them within [PULSEAUDIO_TIMEOUT].
+#[cfg(feature = "pulseaudio_backend")]
+fn pulseaudio_devices() -> Result<Vec<Device>, String> {
+    // the main loop blocks while the server doesn't answer, so it runs on its own thread
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || tx.send(pulseaudio_sinks()));
+    rx.recv_timeout(PULSEAUDIO_TIMEOUT)
+        .map_err(|_| "PulseAudio didn't list its devices in time".to_string())?
+}
+
+#[cfg(feature = "pulseaudio_backend")]
+fn pulseaudio_sinks() -> Result<Vec<Device>, String> {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use libpulse_binding::callbacks::ListResult;
+    use libpulse_binding::context::{Context, FlagSet, State};
+    use libpulse_binding::mainloop::standard::{IterateResult, Mainloop};
+    use libpulse_binding::operation::State as OperationState;
+
+    let mut mainloop = Mainloop::new().ok_or("Can't create a PulseAudio main loop")?;
+    let mut context =
+        Context::new(&mainloop, "ncspot").ok_or("Can't create a PulseAudio context")?;
+    context
+        .connect(None, FlagSet::NOFLAGS, None)
+        .map_err(|e| format!("Can't connect to PulseAudio: {e}"))?;
+
+    let iterate = |mainloop: &mut Mainloop| match mainloop.iterate(true) {
+        IterateResult::Success(_) => Ok(()),
+        IterateResult::Quit(_) | IterateResult::Err(_) => {
+            Err("The PulseAudio main loop stopped".to_string())
+        }
+    };
+
+    loop {
+        iterate(&mut mainloop)?;
+        match context.get_state() {
+            State::Ready => break,
+            State::Failed | State::Terminated => {
+                return Err("Can't connect to PulseAudio".to_string())
+            }
+            _ => {}
+        }
+    }
+
+    let devices = Rc::new(RefCell::new(Vec::new()));
+    let operation = context.introspect().get_sink_info_list({
+        let devices = devices.clone();
+        move |result| {
+            if let ListResult::Item(sink) = result {
+                if let Some(name) = &sink.name {
+                    devices.borrow_mut().push(Device {
+                        name: name.to_string(),
+                        description: sink.description.as_ref().map(|d| d.to_string()),
+                    });
+                }
+            }
+        }
+    });
+    while operation.get_state() == OperationState::Running {
+        iterate(&mut mainloop)?;
+    }
+    context.disconnect();
+
+    let devices = devices.borrow().clone();
+    Ok(devices)
+}
+
+/// The PCM devices of ALSA that can play audio.
+#[cfg(feature = "alsa_backend")]
+fn alsa_devices() -> Result<Vec<Device>, String> {
+    use alsa::device_name::HintIter;
+    use alsa::Direction;
+
+    let hints = HintIter::new_str(None, "pcm").map_err(|e| e.to_string())?;
+    Ok(hints
+        // devices without a direction can both play and record
+        .filter(|hint| hint.direction != Some(Direction::Capture))
+        .filter_map(|hint| {
+            Some(Device {
+                name: hint.name?,
+                description: hint.desc.map(|desc| desc.replace('\n', ", ")),
+            })
+        })
+        .collect())
+}
+
+/// The output devices of the default host of cpal, which rodio plays on.
+#[cfg(feature = "rodio_backend")]
+fn rodio_devices() -> Result<Vec<Device>, String> {
+    use cpal::traits::{DeviceTrait, HostTrait};
+
+    let devices = cpal::default_host()
+        .output_devices()
+        .map_err(|e| e.to_string())?;
+    Ok(devices
+        .filter_map(|device| device.name().ok())
+        .map(|name| Device {
+            name,
+            description: None,
+        })
+        .collect())
+}
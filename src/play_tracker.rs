@@ -0,0 +1,135 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::model::play::Play;
+use crate::model::playable::Playable;
+use crate::spotify::PlayerEvent;
+
+/// The item that is currently being played.
+struct CurrentPlay {
+    playable: Playable,
+    uri: String,
+    played_at: Option<DateTime<Utc>>,
+    played: Duration,
+    playing_since: Option<Instant>,
+}
+
+impl CurrentPlay {
+    fn pause(&mut self) {
+        if let Some(since) = self.playing_since.take() {
+            self.played += since.elapsed();
+        }
+    }
+}
+
+/// Follows the playback state to determine how long each item was played for.
+#[derive(Default)]
+pub struct PlayTracker {
+    current: Option<CurrentPlay>,
+}
+
+impl PlayTracker {
+    /// Update the playback state after `event`, while `playable` is the current item. Returns
+    /// the play of the previous item once it is over.
+    pub fn update(&mut self, event: &PlayerEvent, playable: Option<&Playable>) -> Option<Play> {
+        let mut finished = None;
+
+        let uri = playable.map(Playable::uri);
+        if self.current.as_ref().map(|current| &current.uri) != uri.as_ref() {
+            finished = self.finish();
+            self.current = playable.zip(uri).map(|(playable, uri)| CurrentPlay {
+                playable: playable.clone(),
+                uri,
+                played_at: None,
+                played: Duration::ZERO,
+                playing_since: None,
+            });
+        }
+
+        match event {
+            PlayerEvent::Playing(_) => {
+                if let Some(current) = self.current.as_mut() {
+                    current.played_at.get_or_insert_with(Utc::now);
+                    current.playing_since.get_or_insert_with(Instant::now);
+                }
+            }
+            PlayerEvent::Paused(_) => {
+                if let Some(current) = self.current.as_mut() {
+                    current.pause();
+                }
+            }
+            PlayerEvent::Stopped | PlayerEvent::FinishedTrack => {
+                finished = finished.or_else(|| self.finish());
+            }
+        }
+
+        finished
+    }
+
+    /// Stop following the current item, returning its play if it was played at all.
+    pub fn finish(&mut self) -> Option<Play> {
+        let mut current = self.current.take()?;
+        current.pause();
+        match current.played_at {
+            Some(played_at) if !current.played.is_zero() => Some(Play {
+                playable: current.playable,
+                played_at,
+                played: current.played.as_millis() as u32,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::model::local_file::LocalFile;
+
+    fn playable(title: &str) -> Playable {
+        Playable::LocalFile(LocalFile::test(title))
+    }
+
+    fn playing() -> PlayerEvent {
+        PlayerEvent::Playing(SystemTime::now())
+    }
+
+    #[test]
+    fn counts_the_time_played_without_pauses() {
+        let (song, next) = (playable("Song"), playable("Next"));
+        let mut tracker = PlayTracker::default();
+
+        assert!(tracker.update(&playing(), Some(&song)).is_none());
+        sleep(Duration::from_millis(50));
+        assert!(tracker
+            .update(&PlayerEvent::Paused(Duration::ZERO), Some(&song))
+            .is_none());
+        sleep(Duration::from_millis(300));
+        tracker.update(&playing(), Some(&song));
+        sleep(Duration::from_millis(50));
+
+        // the play is over once the next item starts
+        let play = tracker.update(&playing(), Some(&next)).unwrap();
+        assert_eq!(play.playable.uri(), song.uri());
+        assert!((100..300).contains(&play.played), "{}", play.played);
+
+        let play = tracker.update(&PlayerEvent::FinishedTrack, Some(&next));
+        assert_eq!(play.unwrap().playable.uri(), next.uri());
+        assert!(tracker.finish().is_none());
+    }
+
+    #[test]
+    fn ignores_items_that_were_never_played() {
+        let mut tracker = PlayTracker::default();
+        let song = playable("Song");
+        let paused = PlayerEvent::Paused(Duration::ZERO);
+        assert!(tracker.update(&paused, Some(&song)).is_none());
+        assert!(tracker.update(&PlayerEvent::Stopped, Some(&song)).is_none());
+        assert!(tracker.update(&paused, Some(&playable("Next"))).is_none());
+        assert!(tracker.update(&PlayerEvent::Stopped, None).is_none());
+    }
+}
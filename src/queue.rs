@@ -8,11 +8,18 @@ use notify_rust::Notification;
 use rand::prelude::*;
 use strum_macros::Display;
 
-use crate::config::{Config, PlaybackState};
+#[cfg(feature = "connect")]
+use crate::config::QueueState;
+use crate::config::{Config, PlaybackState, VolnormMode};
+use crate::crossfade;
+use crate::events::{Event, EventManager};
 use crate::library::Library;
+use crate::model::episode::Episode;
 use crate::model::playable::Playable;
+use crate::model::track::Track;
 use crate::spotify::PlayerEvent;
 use crate::spotify::Spotify;
+use crate::time_stretch;
 
 /// Repeat behavior for the [Queue].
 #[derive(Display, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -46,10 +53,22 @@ pub struct Queue {
     spotify: Spotify,
     cfg: Arc<Config>,
     library: Arc<Library>,
+    ev: EventManager,
+    /// The local queue, which is kept aside while a Spotify Connect controller plays on ncspot.
+    #[cfg(feature = "connect")]
+    local: RwLock<Option<QueueState>>,
+    /// The name of the Spotify Connect controller that plays on ncspot.
+    #[cfg(feature = "connect")]
+    controller: RwLock<Option<String>>,
 }
 
 impl Queue {
-    pub fn new(spotify: Spotify, cfg: Arc<Config>, library: Arc<Library>) -> Queue {
+    pub fn new(
+        ev: EventManager,
+        spotify: Spotify,
+        cfg: Arc<Config>,
+        library: Arc<Library>,
+    ) -> Queue {
         let queue_state = cfg.state().queuestate.clone();
         let playback_state = cfg.state().playback_state.clone();
         let queue = Queue {
@@ -59,9 +78,18 @@ impl Queue {
             random_order: RwLock::new(queue_state.random_order),
             cfg,
             library,
+            ev,
+            #[cfg(feature = "connect")]
+            local: RwLock::new(None),
+            #[cfg(feature = "connect")]
+            controller: RwLock::new(None),
         };
 
         if let Some(playable) = queue.get_current() {
+            if let Some(index) = queue.get_current_index() {
+                queue.update_album_gain(index);
+            }
+            spotify.set_speed(queue.speed_for(&playable));
             spotify.load(
                 &playable,
                 playback_state == PlaybackState::Playing,
@@ -82,53 +110,48 @@ impl Queue {
     }
 
     /// The index of the next item in `self.queue` that should be played. None
-    /// if at the end of the queue.
+    /// if at the end of the queue. In offline mode, items that aren't cached
+    /// are skipped.
     pub fn next_index(&self) -> Option<usize> {
-        match *self.current_track.read().unwrap() {
-            Some(mut index) => {
-                let random_order = self.random_order.read().unwrap();
-                if let Some(order) = random_order.as_ref() {
-                    index = order.iter().position(|&i| i == index).unwrap();
-                }
+        let index = (*self.current_track.read().unwrap())?;
+        let random_order = self.random_order.read().unwrap();
+        let q = self.queue.read().unwrap();
 
-                let mut next_index = index + 1;
-                if next_index < self.queue.read().unwrap().len() {
-                    if let Some(order) = random_order.as_ref() {
-                        next_index = order[next_index];
-                    }
+        let position = match random_order.as_ref() {
+            Some(order) => order.iter().position(|&i| i == index).unwrap(),
+            None => index,
+        };
 
-                    Some(next_index)
-                } else {
-                    None
-                }
-            }
-            None => None,
-        }
+        (position + 1..q.len())
+            .map(|position| random_order.as_ref().map_or(position, |o| o[position]))
+            .find(|&i| self.is_available(&q[i]))
     }
 
     /// The index of the previous item in `self.queue` that should be played.
-    /// None if at the start of the queue.
+    /// None if at the start of the queue. In offline mode, items that aren't
+    /// cached are skipped.
     pub fn previous_index(&self) -> Option<usize> {
-        match *self.current_track.read().unwrap() {
-            Some(mut index) => {
-                let random_order = self.random_order.read().unwrap();
-                if let Some(order) = random_order.as_ref() {
-                    index = order.iter().position(|&i| i == index).unwrap();
-                }
+        let index = (*self.current_track.read().unwrap())?;
+        let random_order = self.random_order.read().unwrap();
+        let q = self.queue.read().unwrap();
 
-                if index > 0 {
-                    let mut next_index = index - 1;
-                    if let Some(order) = random_order.as_ref() {
-                        next_index = order[next_index];
-                    }
+        let position = match random_order.as_ref() {
+            Some(order) => order.iter().position(|&i| i == index).unwrap(),
+            None => index,
+        };
 
-                    Some(next_index)
-                } else {
-                    None
-                }
-            }
-            None => None,
-        }
+        (0..position)
+            .rev()
+            .map(|position| random_order.as_ref().map_or(position, |o| o[position]))
+            .find(|&i| self.is_available(&q[i]))
+    }
+
+    /// Whether `playable` can be played. In offline mode, this is only the
+    /// case for local files and items that are present in the audio cache.
+    pub fn is_available(&self, playable: &Playable) -> bool {
+        !self.get_offline()
+            || matches!(playable, Playable::LocalFile(_))
+            || self.library.is_cached(playable)
     }
 
     /// The currently playing item from `self.queue`.
@@ -160,6 +183,7 @@ impl Queue {
             }
             let mut q = self.queue.write().unwrap();
             q.insert(index + 1, track);
+            self.ev.send(Event::QueueChanged);
         } else {
             self.append(track);
         }
@@ -175,6 +199,33 @@ impl Queue {
 
         let mut q = self.queue.write().unwrap();
         q.push(track);
+        self.ev.send(Event::QueueChanged);
+    }
+
+    /// Insert `track` at `index` in `self.queue`, moving the following items
+    /// back by one.
+    pub fn insert(&self, index: usize, track: Playable) {
+        let mut random_order = self.random_order.write().unwrap();
+        let mut q = self.queue.write().unwrap();
+        let index = index.min(q.len());
+        q.insert(index, track);
+
+        if let Some(order) = random_order.as_mut() {
+            for item in order.iter_mut() {
+                if *item >= index {
+                    *item += 1;
+                }
+            }
+            order.push(index);
+        }
+
+        let mut current = self.current_track.write().unwrap();
+        if let Some(current_index) = *current {
+            if current_index >= index {
+                current.replace(current_index + 1);
+            }
+        }
+        self.ev.send(Event::QueueChanged);
     }
 
     /// Append `tracks` after the currently playing item, taking into account
@@ -199,6 +250,7 @@ impl Queue {
             q.insert(i, track.clone());
             i += 1;
         }
+        self.ev.send(Event::QueueChanged);
 
         first
     }
@@ -214,6 +266,7 @@ impl Queue {
             }
             q.remove(index);
         }
+        self.ev.send(Event::QueueChanged);
 
         // if the queue is empty stop playback
         let len = self.queue.read().unwrap().len();
@@ -265,6 +318,7 @@ impl Queue {
         if let Some(o) = random_order.as_mut() {
             o.clear()
         }
+        self.ev.send(Event::QueueChanged);
     }
 
     /// The amount of items in `self.queue`.
@@ -290,6 +344,7 @@ impl Queue {
                 current.replace(to - 1);
             }
         }
+        self.ev.send(Event::QueueChanged);
     }
 
     /// Play the item at `index` in `self.queue`.
@@ -304,8 +359,15 @@ impl Queue {
             let mut rng = rand::thread_rng();
             index = rng.gen_range(0..queue_length);
         }
+        self.update_album_gain(index);
 
         if let Some(track) = &self.queue.read().unwrap().get(index) {
+            if !self.is_available(track) {
+                info!("Not playing {track} in offline mode, it isn't cached");
+                return;
+            }
+
+            self.spotify.set_speed(self.speed_for(track));
             self.spotify.load(track, true, 0);
             let mut current = self.current_track.write().unwrap();
             current.replace(index);
@@ -383,11 +445,13 @@ impl Queue {
             }
         } else if repeat == RepeatSetting::RepeatPlaylist && q.len() > 0 {
             let random_order = self.random_order.read().unwrap();
-            self.play(
-                random_order.as_ref().map(|o| o[0]).unwrap_or(0),
-                false,
-                false,
-            );
+            let first = (0..q.len())
+                .map(|position| random_order.as_ref().map_or(position, |o| o[position]))
+                .find(|&i| self.is_available(&q[i]));
+            match first {
+                Some(index) => self.play(index, false, false),
+                None => self.spotify.stop(),
+            }
         } else {
             self.spotify.stop();
         }
@@ -417,6 +481,88 @@ impl Queue {
         }
     }
 
+    /// Replace the queue with the `items` of the Spotify Connect controller `controller`, and play
+    /// the one at `index` from `position_ms`, or load it paused if `playing` isn't set. The local
+    /// queue is kept aside until [Queue::resume_local] is called.
+    #[cfg(feature = "connect")]
+    pub fn play_remote(
+        &self,
+        controller: &str,
+        items: Vec<Playable>,
+        index: usize,
+        position_ms: u32,
+        playing: bool,
+    ) {
+        {
+            let mut local = self.local.write().unwrap();
+            if local.is_none() {
+                *local = Some(QueueState {
+                    current_track: self.get_current_index(),
+                    random_order: self.get_random_order(),
+                    track_progress: self.spotify.get_current_progress(),
+                    queue: self.queue.read().unwrap().clone(),
+                });
+            }
+        }
+        info!("Playing the queue of {controller}");
+        *self.controller.write().unwrap() = Some(controller.to_string());
+        *self.queue.write().unwrap() = items;
+        self.ev.send(Event::QueueChanged);
+        // the controller decides about the order
+        *self.random_order.write().unwrap() = None;
+        self.load_index(Some(index), position_ms, playing);
+    }
+
+    /// Bring back the local queue that was kept aside by [Queue::play_remote], paused where it was
+    /// left.
+    #[cfg(feature = "connect")]
+    pub fn resume_local(&self) {
+        let Some(local) = self.local.write().unwrap().take() else {
+            return;
+        };
+        info!("Resuming the local queue");
+        *self.controller.write().unwrap() = None;
+        *self.queue.write().unwrap() = local.queue;
+        self.ev.send(Event::QueueChanged);
+        *self.random_order.write().unwrap() = local.random_order;
+        self.load_index(
+            local.current_track,
+            local.track_progress.as_millis() as u32,
+            false,
+        );
+    }
+
+    /// The name of the Spotify Connect controller whose queue is played.
+    #[cfg(feature = "connect")]
+    pub fn controller(&self) -> Option<String> {
+        self.controller.read().unwrap().clone()
+    }
+
+    /// The local queue while the queue of a Spotify Connect controller is played.
+    #[cfg(feature = "connect")]
+    pub fn local_state(&self) -> Option<QueueState> {
+        self.local.read().unwrap().clone()
+    }
+
+    /// Load the item at `index` at `position_ms`, or stop if there is none.
+    #[cfg(feature = "connect")]
+    fn load_index(&self, index: Option<usize>, position_ms: u32, playing: bool) {
+        let playable = index.and_then(|index| self.queue.read().unwrap().get(index).cloned());
+        match (index, playable) {
+            (Some(index), Some(playable)) => {
+                self.update_album_gain(index);
+                self.spotify.set_speed(self.speed_for(&playable));
+                self.spotify.load(&playable, playing, position_ms);
+                *self.current_track.write().unwrap() = Some(index);
+                self.spotify.update_track();
+            }
+            _ => {
+                *self.current_track.write().unwrap() = None;
+                self.spotify.stop();
+            }
+        }
+    }
+
     /// Get the current repeat behavior.
     pub fn get_repeat(&self) -> RepeatSetting {
         self.cfg.state().repeat
@@ -432,6 +578,69 @@ impl Queue {
         self.cfg.state().shuffle
     }
 
+    /// Whether playback is limited to items present in the audio cache.
+    pub fn get_offline(&self) -> bool {
+        self.cfg.state().offline
+    }
+
+    /// Limit playback to items present in the audio cache and save the
+    /// setting to the configuration.
+    pub fn set_offline(&self, offline: bool) {
+        self.cfg.with_state_mut(|mut s| s.offline = offline);
+    }
+
+    /// The playback speed of `playable`, which is remembered for the show of episodes and the
+    /// normal speed for everything else.
+    fn speed_for(&self, playable: &Playable) -> f64 {
+        match playable {
+            Playable::Episode(Episode {
+                show_id: Some(show_id),
+                ..
+            }) => self
+                .cfg
+                .state()
+                .show_speeds
+                .get(show_id)
+                .copied()
+                .unwrap_or(1.0),
+            _ => 1.0,
+        }
+    }
+
+    /// Change the playback speed of the current item and remember it for its show if it is an
+    /// episode.
+    pub fn set_speed(&self, speed: f64) {
+        self.spotify.set_speed(speed);
+        let speed = self.spotify.speed();
+        if let Some(Playable::Episode(Episode {
+            show_id: Some(show_id),
+            ..
+        })) = self.get_current()
+        {
+            self.cfg.with_state_mut(|mut s| {
+                if time_stretch::is_normal(speed) {
+                    s.show_speeds.remove(&show_id);
+                } else {
+                    s.show_speeds.insert(show_id.clone(), speed);
+                }
+            });
+        }
+    }
+
+    /// Tell the player whether to normalise the item at `index` by the loudness of its album, which
+    /// only matters with the `auto` volume normalisation mode.
+    fn update_album_gain(&self, index: usize) {
+        if self.cfg.values().volnorm_mode != Some(VolnormMode::Auto) {
+            return;
+        }
+        let album_gain = plays_album(
+            &self.queue.read().unwrap(),
+            self.random_order.read().unwrap().as_deref(),
+            index,
+        );
+        self.spotify.set_album_gain(album_gain);
+    }
+
     /// Get the current order that is used to shuffle.
     pub fn get_random_order(&self) -> Option<Vec<usize>> {
         self.random_order.read().unwrap().clone()
@@ -474,7 +683,17 @@ impl Queue {
                 if let Some(next_index) = self.next_index() {
                     let track = self.queue.read().unwrap()[next_index].clone();
                     debug!("Preloading track {} as requested by librespot", track);
-                    self.spotify.preload(&track);
+                    let crossfade = self.get_current().and_then(|current| {
+                        crossfade::duration(
+                            self.cfg.values().crossfade.unwrap_or(0),
+                            self.cfg.values().crossfade_albums.unwrap_or(true),
+                            self.get_repeat(),
+                            &current,
+                            &track,
+                        )
+                    });
+                    self.update_album_gain(next_index);
+                    self.spotify.preload(&track, crossfade);
                 }
             }
         }
@@ -486,6 +705,32 @@ impl Queue {
     }
 }
 
+/// Whether the item at `index` of `queue` is a track that is played right before or after another
+/// track of the same album, in the shuffled `order` if there is one.
+fn plays_album(queue: &[Playable], order: Option<&[usize]>, index: usize) -> bool {
+    let Some(Playable::Track(Track {
+        album_id: Some(album_id),
+        ..
+    })) = queue.get(index)
+    else {
+        return false;
+    };
+    let position = match order {
+        Some(order) => order.iter().position(|&i| i == index),
+        None => Some(index),
+    };
+    let same_album = |position: usize| {
+        let index = order.map_or(Some(position), |order| order.get(position).copied());
+        matches!(
+            index.and_then(|index| queue.get(index)),
+            Some(Playable::Track(track)) if track.album_id.as_ref() == Some(album_id)
+        )
+    };
+    position.is_some_and(|position| {
+        position.checked_sub(1).is_some_and(same_album) || same_album(position + 1)
+    })
+}
+
 /// Send a notification using the desktops default notification method.
 ///
 /// `summary_txt`: A short title for the notification.
@@ -524,3 +769,41 @@ pub fn send_notification(summary_txt: &str, body_txt: &str, cover_url: Option<St
         Err(e) => log::error!("Failed to send notification cover: {}", e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(album: &str) -> Playable {
+        Playable::Track(Track {
+            album_id: Some(album.to_string()),
+            ..Track::test(album)
+        })
+    }
+
+    #[test]
+    fn album_gain_for_consecutive_tracks() {
+        let queue = [track("a"), track("a"), track("b"), track("c"), track("b")];
+        assert!(plays_album(&queue, None, 0));
+        assert!(plays_album(&queue, None, 1));
+        assert!(!plays_album(&queue, None, 2));
+        assert!(!plays_album(&queue, None, 4));
+        assert!(!plays_album(&queue, None, 5));
+
+        // the shuffled order is what is played
+        let order = [2, 4, 0, 3, 1];
+        assert!(plays_album(&queue, Some(&order), 2));
+        assert!(plays_album(&queue, Some(&order), 4));
+        assert!(!plays_album(&queue, Some(&order), 0));
+    }
+
+    #[test]
+    fn track_gain_without_album() {
+        let mut single = track("a");
+        if let Playable::Track(track) = &mut single {
+            track.album_id = None;
+        }
+        let queue = [single.clone(), single];
+        assert!(!plays_album(&queue, None, 0));
+    }
+}
@@ -0,0 +1,650 @@
+//! Submission of listens to ListenBrainz and Last.fm compatible services.
+//!
+//! The [Scrobbler] submits a play once the item was played for half its duration or 4 minutes,
+//! whichever comes first. Submissions happen on a separate thread.
+//! Listens that could not be submitted are kept in a cache file and retried later.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use serde_json::{json, Value};
+
+use crate::config;
+use crate::model::play::Play;
+use crate::model::playable::Playable;
+use crate::traits::ListItem;
+
+/// The file that listens which still have to be submitted are saved to.
+const CACHE_PENDING_LISTENS: &str = "scrobbles.json";
+
+/// How often submitting pending listens is retried.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Items shorter than this are never submitted.
+const MIN_DURATION: Duration = Duration::from_secs(30);
+
+/// Items are submitted after being played for this long, even if it is less than half of them.
+const MAX_LISTEN_DURATION: Duration = Duration::from_secs(4 * 60);
+
+const DEFAULT_LISTENBRAINZ_URL: &str = "https://api.listenbrainz.org";
+const DEFAULT_LASTFM_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+
+/// Whether an item that is `duration` long was listened to, after being played for `played`.
+pub fn is_listen(duration: Duration, played: Duration) -> bool {
+    duration >= MIN_DURATION && played >= std::cmp::min(duration / 2, MAX_LISTEN_DURATION)
+}
+
+/// A listen of an item.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Listen {
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    pub duration_ms: u32,
+    /// When playback started, as a Unix timestamp.
+    pub listened_at: i64,
+    pub url: Option<String>,
+}
+
+impl Listen {
+    /// The listen of `play`, if it can be submitted. Podcast episodes are never submitted.
+    fn new(play: &Play) -> Option<Self> {
+        let playable = &play.playable;
+        let (artists, title, album) = match playable {
+            Playable::Track(track) => (&track.artists, &track.title, &track.album),
+            Playable::LocalFile(file) => (&file.artists, &file.title, &file.album),
+            Playable::Episode(_) => return None,
+        };
+        if artists.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            artist: artists.join(", "),
+            title: title.clone(),
+            album: album.clone(),
+            duration_ms: playable.duration(),
+            listened_at: play.played_at.timestamp(),
+            url: playable.share_url(),
+        })
+    }
+}
+
+/// Why a submission failed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SubmitError {
+    /// The listens may be accepted when they are submitted again later.
+    Temporary(String),
+    /// The listens were rejected and must not be submitted again.
+    Rejected(String),
+}
+
+/// A service listens can be submitted to.
+pub trait Service: Send {
+    /// A unique name, used to keep track of the listens that still have to be submitted.
+    fn name(&self) -> &'static str;
+    /// The maximum number of listens that can be submitted at once.
+    fn batch_size(&self) -> usize;
+    fn submit(&self, listens: &[Listen]) -> Result<(), SubmitError>;
+}
+
+fn http_client() -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("could not create HTTP client")
+}
+
+/// Submits listens to [ListenBrainz](https://listenbrainz.org).
+pub struct ListenBrainz {
+    url: String,
+    token: String,
+    client: reqwest::blocking::Client,
+}
+
+impl ListenBrainz {
+    pub fn new(config: &config::ListenBrainz) -> Self {
+        Self {
+            url: config
+                .url
+                .clone()
+                .unwrap_or_else(|| DEFAULT_LISTENBRAINZ_URL.to_string()),
+            token: config.token.clone(),
+            client: http_client(),
+        }
+    }
+
+    fn payload(listens: &[Listen]) -> Value {
+        let listen_type = if listens.len() == 1 {
+            "single"
+        } else {
+            "import"
+        };
+        let payload: Vec<Value> = listens
+            .iter()
+            .map(|listen| {
+                let mut track_metadata = json!({
+                    "artist_name": listen.artist,
+                    "track_name": listen.title,
+                    "additional_info": {
+                        "duration_ms": listen.duration_ms,
+                        "media_player": "ncspot",
+                        "submission_client": "ncspot",
+                        "submission_client_version": env!("CARGO_PKG_VERSION"),
+                    },
+                });
+                if let Some(album) = &listen.album {
+                    track_metadata["release_name"] = json!(album);
+                }
+                if let Some(url) = &listen.url {
+                    track_metadata["additional_info"]["spotify_id"] = json!(url);
+                    track_metadata["additional_info"]["music_service"] = json!("spotify.com");
+                }
+                json!({ "listened_at": listen.listened_at, "track_metadata": track_metadata })
+            })
+            .collect();
+        json!({ "listen_type": listen_type, "payload": payload })
+    }
+}
+
+impl Service for ListenBrainz {
+    fn name(&self) -> &'static str {
+        "listenbrainz"
+    }
+
+    fn batch_size(&self) -> usize {
+        100
+    }
+
+    fn submit(&self, listens: &[Listen]) -> Result<(), SubmitError> {
+        let response = self
+            .client
+            .post(format!(
+                "{}/1/submit-listens",
+                self.url.trim_end_matches('/')
+            ))
+            .header("Authorization", format!("Token {}", self.token))
+            .json(&Self::payload(listens))
+            .send()
+            .map_err(|e| SubmitError::Temporary(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            let message = format!("{}: {}", status, response.text().unwrap_or_default());
+            if status == reqwest::StatusCode::BAD_REQUEST {
+                Err(SubmitError::Rejected(message))
+            } else {
+                Err(SubmitError::Temporary(message))
+            }
+        }
+    }
+}
+
+/// Submits listens to [Last.fm](https://www.last.fm) or another service with a compatible API.
+pub struct LastFm {
+    url: String,
+    api_key: String,
+    api_secret: String,
+    session_key: String,
+    client: reqwest::blocking::Client,
+}
+
+impl LastFm {
+    /// The error code for invalid parameters, all other errors are caused by the configuration or
+    /// the service being unavailable.
+    const INVALID_PARAMETERS: i64 = 6;
+
+    pub fn new(config: &config::LastFm) -> Self {
+        Self {
+            url: config
+                .url
+                .clone()
+                .unwrap_or_else(|| DEFAULT_LASTFM_URL.to_string()),
+            api_key: config.api_key.clone(),
+            api_secret: config.api_secret.clone(),
+            session_key: config.session_key.clone(),
+            client: http_client(),
+        }
+    }
+
+    /// The parameters of a `track.scrobble` call, including the signature.
+    fn parameters(&self, listens: &[Listen]) -> BTreeMap<String, String> {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("method".to_string(), "track.scrobble".to_string());
+        parameters.insert("api_key".to_string(), self.api_key.clone());
+        parameters.insert("sk".to_string(), self.session_key.clone());
+        for (index, listen) in listens.iter().enumerate() {
+            parameters.insert(format!("artist[{index}]"), listen.artist.clone());
+            parameters.insert(format!("track[{index}]"), listen.title.clone());
+            parameters.insert(
+                format!("timestamp[{index}]"),
+                listen.listened_at.to_string(),
+            );
+            parameters.insert(
+                format!("duration[{index}]"),
+                (listen.duration_ms / 1000).to_string(),
+            );
+            if let Some(album) = &listen.album {
+                parameters.insert(format!("album[{index}]"), album.clone());
+            }
+        }
+
+        let signature: String = parameters
+            .iter()
+            .flat_map(|(key, value)| [key.as_str(), value.as_str()])
+            .chain(std::iter::once(self.api_secret.as_str()))
+            .collect();
+        parameters.insert(
+            "api_sig".to_string(),
+            format!("{:x}", md5::compute(signature)),
+        );
+        parameters.insert("format".to_string(), "json".to_string());
+        parameters
+    }
+}
+
+impl Service for LastFm {
+    fn name(&self) -> &'static str {
+        "lastfm"
+    }
+
+    fn batch_size(&self) -> usize {
+        50
+    }
+
+    fn submit(&self, listens: &[Listen]) -> Result<(), SubmitError> {
+        let response = self
+            .client
+            .post(&self.url)
+            .form(&self.parameters(listens))
+            .send()
+            .map_err(|e| SubmitError::Temporary(e.to_string()))?;
+
+        let status = response.status();
+        let body: Value = response.json().unwrap_or_default();
+        match body["error"].as_i64() {
+            Some(code) => {
+                let message = format!("error {}: {}", code, body["message"]);
+                if code == Self::INVALID_PARAMETERS {
+                    Err(SubmitError::Rejected(message))
+                } else {
+                    Err(SubmitError::Temporary(message))
+                }
+            }
+            None if status.is_success() => Ok(()),
+            None => Err(SubmitError::Temporary(status.to_string())),
+        }
+    }
+}
+
+/// The listens that still have to be submitted to each service, saved to a file so they
+/// survive restarts.
+struct PendingListens {
+    path: PathBuf,
+    listens: BTreeMap<String, Vec<Listen>>,
+}
+
+impl PendingListens {
+    fn load(path: &Path) -> Self {
+        let listens = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                error!("could not parse {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            path: path.to_path_buf(),
+            listens,
+        }
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string(&self.listens)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| std::fs::write(&self.path, contents).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("could not save {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.listens.values().all(Vec::is_empty)
+    }
+
+    fn add(&mut self, services: &[Box<dyn Service>], listen: Listen) {
+        for service in services {
+            self.listens
+                .entry(service.name().to_string())
+                .or_default()
+                .push(listen.clone());
+        }
+        self.save();
+    }
+
+    /// Submit the pending listens of all `services`, until a submission fails temporarily.
+    fn submit(&mut self, services: &[Box<dyn Service>]) {
+        let mut changed = false;
+        for service in services {
+            let listens = match self.listens.get_mut(service.name()) {
+                Some(listens) => listens,
+                None => continue,
+            };
+
+            while !listens.is_empty() {
+                let batch = std::cmp::min(service.batch_size(), listens.len());
+                let submitted = submit_batch(service.as_ref(), &listens[..batch]);
+                if submitted > 0 {
+                    listens.drain(..submitted);
+                    changed = true;
+                }
+                if submitted < batch {
+                    break;
+                }
+            }
+        }
+
+        if changed {
+            self.save();
+        }
+    }
+}
+
+/// Submit `listens` to `service`, bisecting rejected batches so that only the listens that are
+/// rejected on their own are dropped. Returns how many of the first listens were submitted or
+/// dropped, which are all of them unless a submission failed temporarily.
+fn submit_batch(service: &dyn Service, listens: &[Listen]) -> usize {
+    match service.submit(listens) {
+        Ok(()) => {
+            debug!("submitted {} listens to {}", listens.len(), service.name());
+            listens.len()
+        }
+        Err(SubmitError::Rejected(e)) if listens.len() > 1 => {
+            debug!(
+                "{} rejected {} listens: {}",
+                service.name(),
+                listens.len(),
+                e
+            );
+            let (first, second) = listens.split_at(listens.len() / 2);
+            let submitted = submit_batch(service, first);
+            if submitted < first.len() {
+                submitted
+            } else {
+                submitted + submit_batch(service, second)
+            }
+        }
+        Err(SubmitError::Rejected(e)) => {
+            let listen = &listens[0];
+            warn!(
+                "{} rejected {} - {}: {}",
+                service.name(),
+                listen.artist,
+                listen.title,
+                e
+            );
+            listens.len()
+        }
+        Err(SubmitError::Temporary(e)) => {
+            warn!("could not submit listens to {}: {}", service.name(), e);
+            0
+        }
+    }
+}
+
+/// Submits listens to the configured services.
+pub struct Scrobbler {
+    listens: Option<mpsc::Sender<Listen>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Scrobbler {
+    /// Start submitting to the services in `config`, if there are any.
+    pub fn new(config: &config::Scrobbling) -> Option<Self> {
+        let mut services: Vec<Box<dyn Service>> = Vec::new();
+        if let Some(listenbrainz) = &config.listenbrainz {
+            services.push(Box::new(ListenBrainz::new(listenbrainz)));
+        }
+        if let Some(lastfm) = &config.lastfm {
+            services.push(Box::new(LastFm::new(lastfm)));
+        }
+        if services.is_empty() {
+            return None;
+        }
+
+        Some(Self::with_services(
+            services,
+            config::cache_path(CACHE_PENDING_LISTENS),
+        ))
+    }
+
+    fn with_services(services: Vec<Box<dyn Service>>, cache_path: PathBuf) -> Self {
+        let (tx, rx) = mpsc::channel::<Listen>();
+        let worker = thread::spawn(move || {
+            let mut pending = PendingListens::load(&cache_path);
+            loop {
+                pending.submit(&services);
+
+                let listen = if pending.is_empty() {
+                    rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+                } else {
+                    rx.recv_timeout(RETRY_INTERVAL)
+                };
+                match listen {
+                    Ok(listen) => {
+                        info!("submitting listen: {} - {}", listen.artist, listen.title);
+                        pending.add(&services, listen);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+
+        Self {
+            listens: Some(tx),
+            worker: Some(worker),
+        }
+    }
+
+    /// Submit `play` if it counts as a listen.
+    pub fn submit(&self, play: &Play) {
+        let duration = Duration::from_millis(play.playable.duration() as u64);
+        if !is_listen(duration, Duration::from_millis(play.played as u64)) {
+            return;
+        }
+        if let (Some(listens), Some(listen)) = (&self.listens, Listen::new(play)) {
+            listens.send(listen).ok();
+        }
+    }
+}
+
+impl Drop for Scrobbler {
+    fn drop(&mut self) {
+        // The worker saves the last listen before it exits.
+        self.listens.take();
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                error!("scrobbler thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify_api::stub_server;
+    use crate::utils::temp_path;
+
+    fn listen(title: &str) -> Listen {
+        Listen {
+            artist: "Hideki Naganuma".to_string(),
+            title: title.to_string(),
+            album: Some("Jet Set Radio".to_string()),
+            duration_ms: 200_000,
+            listened_at: 1_000_000_000,
+            url: None,
+        }
+    }
+
+    /// Answer one submission per status in `statuses`, returning the address and the received
+    /// request bodies.
+    fn mock_server(statuses: Vec<&'static str>) -> (String, JoinHandle<Vec<String>>) {
+        let (address, server) = stub_server(statuses.into_iter().map(|s| (s, "{}")).collect());
+        let bodies = thread::spawn(move || {
+            let requests = server.join().unwrap();
+            requests.into_iter().map(|(_, body)| body).collect()
+        });
+        (address, bodies)
+    }
+
+    fn listenbrainz(url: String) -> Box<dyn Service> {
+        Box::new(ListenBrainz::new(&config::ListenBrainz {
+            token: "token".to_string(),
+            url: Some(url),
+        }))
+    }
+
+    #[test]
+    fn listen_rule() {
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        assert!(!is_listen(Duration::from_secs(20), Duration::from_secs(20)));
+        assert!(!is_listen(minutes(3), Duration::from_secs(89)));
+        assert!(is_listen(minutes(3), Duration::from_secs(90)));
+        assert!(!is_listen(minutes(20), minutes(3)));
+        assert!(is_listen(minutes(20), minutes(4)));
+    }
+
+    #[test]
+    fn submits_to_listenbrainz() {
+        let (url, server) = mock_server(vec!["200 OK"]);
+        let services = vec![listenbrainz(url)];
+        let mut pending = PendingListens::load(&temp_path("submit"));
+
+        pending.add(&services, listen("Let Mom Sleep"));
+        pending.submit(&services);
+
+        assert!(pending.is_empty());
+        std::fs::remove_file(pending.path).ok();
+        let body: Value = serde_json::from_str(&server.join().unwrap()[0]).unwrap();
+        assert_eq!(body["listen_type"], "single");
+        assert_eq!(body["payload"][0]["listened_at"], 1_000_000_000);
+        assert_eq!(
+            body["payload"][0]["track_metadata"]["track_name"],
+            "Let Mom Sleep"
+        );
+        assert_eq!(
+            body["payload"][0]["track_metadata"]["release_name"],
+            "Jet Set Radio"
+        );
+    }
+
+    #[test]
+    fn keeps_failed_listens() {
+        let path = temp_path("retry");
+        let (url, server) = mock_server(vec!["503 Service Unavailable", "200 OK"]);
+        let services = vec![listenbrainz(url)];
+
+        let mut pending = PendingListens::load(&path);
+        pending.add(&services, listen("Humming the Bassline"));
+        pending.add(&services, listen("Funky Radio"));
+        pending.submit(&services);
+
+        // The listens survive a restart and are submitted at once.
+        let mut pending = PendingListens::load(&path);
+        assert_eq!(pending.listens["listenbrainz"].len(), 2);
+        pending.submit(&services);
+        assert!(PendingListens::load(&path).is_empty());
+
+        let bodies = server.join().unwrap();
+        let body: Value = serde_json::from_str(&bodies[1]).unwrap();
+        assert_eq!(body["listen_type"], "import");
+        assert_eq!(body["payload"].as_array().unwrap().len(), 2);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn drops_rejected_listens() {
+        let (url, server) = mock_server(vec!["400 Bad Request"]);
+        let services = vec![listenbrainz(url)];
+        let mut pending = PendingListens::load(&temp_path("rejected"));
+
+        pending.add(&services, listen("Sneakman"));
+        pending.submit(&services);
+
+        assert!(pending.is_empty());
+        std::fs::remove_file(pending.path).ok();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn drops_only_the_rejected_listens_of_a_batch() {
+        let statuses = vec![
+            "400 Bad Request",
+            "200 OK",
+            "400 Bad Request",
+            "400 Bad Request",
+        ];
+        let (url, server) = mock_server([statuses, vec!["200 OK"]].concat());
+        let services = vec![listenbrainz(url)];
+        let mut pending = PendingListens::load(&temp_path("bisect"));
+
+        pending.add(&services, listen("Let Mom Sleep"));
+        pending.add(&services, listen("Sneakman"));
+        pending.add(&services, listen("Funky Radio"));
+        pending.submit(&services);
+
+        assert!(pending.is_empty());
+        std::fs::remove_file(pending.path).ok();
+        let payloads: Vec<Vec<String>> = server
+            .join()
+            .unwrap()
+            .iter()
+            .map(|body| {
+                let body: Value = serde_json::from_str(body).unwrap();
+                body["payload"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|listen| listen["track_metadata"]["track_name"].to_string())
+                    .collect()
+            })
+            .collect();
+        // the batch is halved until the rejected listen is submitted on its own
+        assert_eq!(
+            payloads.iter().map(Vec::len).collect::<Vec<_>>(),
+            [3, 1, 2, 1, 1]
+        );
+        assert_eq!(payloads[3], ["\"Sneakman\""]);
+        assert_eq!(payloads[4], ["\"Funky Radio\""]);
+    }
+
+    #[test]
+    fn signs_lastfm_requests() {
+        let lastfm = LastFm::new(&config::LastFm {
+            api_key: "key".to_string(),
+            api_secret: "secret".to_string(),
+            session_key: "session".to_string(),
+            url: None,
+        });
+        let parameters = lastfm.parameters(&[listen("Rock It On")]);
+
+        assert_eq!(parameters["artist[0]"], "Hideki Naganuma");
+        assert_eq!(parameters["duration[0]"], "200");
+        let signature = format!(
+            "{:x}",
+            md5::compute(
+                "album[0]Jet Set Radioapi_keykeyartist[0]Hideki Naganumaduration[0]200\
+                 methodtrack.scrobblesksessiontimestamp[0]1000000000track[0]Rock It Onsecret"
+            )
+        );
+        assert_eq!(parameters["api_sig"], signature);
+    }
+}
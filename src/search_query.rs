@@ -0,0 +1,551 @@
+//! The query language of searches, i.e. `artist:"Boards of Canada" year:1998-2002 -live`.
+//!
+//! Queries are parsed locally and sent to Spotify with its field filters. Terms that Spotify can't
+//! filter a type of results by, like excluded terms, are checked on the results instead.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::ops::RangeInclusive;
+use std::str::{Chars, FromStr};
+
+use rspotify::model::SearchType;
+
+use crate::model::album::Album;
+use crate::model::artist::Artist;
+use crate::model::episode::Episode;
+use crate::model::local_file::LocalFile;
+use crate::model::playlist::Playlist;
+use crate::model::show::Show;
+use crate::model::track::Track;
+
+/// A field results can be filtered by, named like the field filters of Spotify.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Artist,
+    Album,
+    Track,
+    Genre,
+    Year,
+}
+
+impl Field {
+    const ALL: [Field; 5] = [
+        Field::Artist,
+        Field::Album,
+        Field::Track,
+        Field::Genre,
+        Field::Year,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Artist => "artist",
+            Field::Album => "album",
+            Field::Track => "track",
+            Field::Genre => "genre",
+            Field::Year => "year",
+        }
+    }
+
+    /// Whether Spotify can filter results of `kind` by the field.
+    fn filtered_by_spotify(self, kind: SearchType) -> bool {
+        match kind {
+            SearchType::Track => true,
+            SearchType::Album => matches!(self, Field::Artist | Field::Album | Field::Year),
+            SearchType::Artist => matches!(self, Field::Artist | Field::Genre | Field::Year),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The types of results `type:` accepts.
+const TYPES: &[(&str, SearchType)] = &[
+    ("track", SearchType::Track),
+    ("album", SearchType::Album),
+    ("artist", SearchType::Artist),
+    ("playlist", SearchType::Playlist),
+    ("show", SearchType::Show),
+    ("podcast", SearchType::Show),
+    ("episode", SearchType::Episode),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Term {
+    /// Words that are searched for in every field.
+    Text(String),
+    Field(Field, String),
+    /// The release year is within the range.
+    Year(RangeInclusive<u16>),
+}
+
+impl Term {
+    fn field(&self) -> Option<Field> {
+        match self {
+            Term::Text(_) => None,
+            Term::Field(field, _) => Some(*field),
+            Term::Year(_) => Some(Field::Year),
+        }
+    }
+
+    fn matches(&self, item: &dyn Searchable) -> bool {
+        match self {
+            Term::Text(text) => item.contains(&text.to_lowercase()),
+            Term::Field(field, value) => {
+                let value = value.to_lowercase();
+                item.values(*field)
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|v| v.to_lowercase().contains(&value))
+            }
+            Term::Year(years) => item
+                .values(Field::Year)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|date| date.get(..4)?.parse::<u16>().ok())
+                .any(|year| years.contains(&year)),
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quote = |text: &str| match text.contains(char::is_whitespace) {
+            true => format!("\"{text}\""),
+            false => text.to_string(),
+        };
+        match self {
+            Term::Text(text) => write!(f, "{}", quote(text)),
+            Term::Field(field, value) => write!(f, "{field}:{}", quote(value)),
+            Term::Year(years) if years.start() == years.end() => {
+                write!(f, "year:{}", years.start())
+            }
+            Term::Year(years) => write!(f, "year:{}-{}", years.start(), years.end()),
+        }
+    }
+}
+
+/// A result of a search that terms can be checked on.
+pub trait Searchable {
+    /// The name of the result, i.e. the title of a track or an album.
+    fn name(&self) -> &str;
+
+    /// The values of `field`, None if the result has no such field.
+    fn values(&self, _field: Field) -> Option<Vec<&str>> {
+        None
+    }
+
+    /// Whether the name, artists or album of the result contain `text`, which is lowercase.
+    fn contains(&self, text: &str) -> bool {
+        std::iter::once(self.name())
+            .chain(self.values(Field::Artist).unwrap_or_default())
+            .chain(self.values(Field::Album).unwrap_or_default())
+            .any(|value| value.to_lowercase().contains(text))
+    }
+}
+
+impl Searchable for Track {
+    fn name(&self) -> &str {
+        &self.title
+    }
+
+    fn values(&self, field: Field) -> Option<Vec<&str>> {
+        match field {
+            Field::Artist => Some(self.artists.iter().map(String::as_str).collect()),
+            Field::Album => Some(self.album.as_deref().into_iter().collect()),
+            Field::Track => Some(vec![&self.title]),
+            _ => None,
+        }
+    }
+}
+
+impl Searchable for Album {
+    fn name(&self) -> &str {
+        &self.title
+    }
+
+    fn values(&self, field: Field) -> Option<Vec<&str>> {
+        match field {
+            Field::Artist => Some(self.artists.iter().map(String::as_str).collect()),
+            Field::Album => Some(vec![&self.title]),
+            Field::Year => Some(vec![&self.year]),
+            _ => None,
+        }
+    }
+}
+
+impl Searchable for Artist {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn values(&self, field: Field) -> Option<Vec<&str>> {
+        match field {
+            Field::Artist => Some(vec![&self.name]),
+            _ => None,
+        }
+    }
+}
+
+impl Searchable for Playlist {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Searchable for Show {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Searchable for Episode {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn values(&self, field: Field) -> Option<Vec<&str>> {
+        match field {
+            Field::Year => Some(vec![&self.release_date]),
+            _ => None,
+        }
+    }
+}
+
+impl Searchable for LocalFile {
+    fn name(&self) -> &str {
+        &self.title
+    }
+
+    fn values(&self, field: Field) -> Option<Vec<&str>> {
+        match field {
+            Field::Artist => Some(self.artists.iter().map(String::as_str).collect()),
+            Field::Album => Some(self.album.as_deref().into_iter().collect()),
+            Field::Track => Some(vec![&self.title]),
+            _ => None,
+        }
+    }
+
+    fn contains(&self, text: &str) -> bool {
+        self.matches(text)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SearchQueryError {
+    UnterminatedQuote,
+    NoTerms,
+    MissingValue {
+        field: String,
+    },
+    BadValue {
+        field: String,
+        value: String,
+        err: String,
+    },
+}
+
+impl fmt::Display for SearchQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SearchQueryError::*;
+        let formatted = match self {
+            UnterminatedQuote => "Missing closing quote in search".to_string(),
+            NoTerms => "Search requires a term that isn't excluded".to_string(),
+            MissingValue { field } => format!("\"{field}:\" requires a value"),
+            BadValue { field, value, err } => format!("Error with \"{field}:{value}\": {err}"),
+        };
+        write!(f, "{formatted}")
+    }
+}
+
+/// A parsed search.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// The terms results have to match.
+    pub include: Vec<Term>,
+    /// The terms results must not match, which are only checked locally.
+    pub exclude: Vec<Term>,
+    /// The types of results to search for, every type if empty.
+    pub types: Vec<SearchType>,
+}
+
+impl SearchQuery {
+    /// Whether results of `kind` are searched for.
+    pub fn includes(&self, kind: SearchType) -> bool {
+        self.types.is_empty() || self.types.contains(&kind)
+    }
+
+    /// The query to send to Spotify to search for results of `kind`, or None if they aren't
+    /// searched for or can't be filtered by every term.
+    pub fn spotify_query(&self, kind: SearchType) -> Option<String> {
+        if !self.includes(kind) {
+            return None;
+        }
+        let mut terms = Vec::new();
+        for term in &self.include {
+            match term.field() {
+                Some(field) if field.filtered_by_spotify(kind) => terms.push(term.to_string()),
+                // checked on the results
+                Some(Field::Year) if kind == SearchType::Episode => {}
+                Some(_) => return None,
+                None => terms.push(term.to_string()),
+            }
+        }
+        (!terms.is_empty()).then(|| terms.join(" "))
+    }
+
+    /// Whether the result `item` of `kind` matches the terms Spotify can't filter by. Every term
+    /// is checked for results that don't come from Spotify, i.e. local files.
+    pub fn matches(&self, item: &dyn Searchable, kind: Option<SearchType>) -> bool {
+        let checked_locally = |term: &&Term| match (kind, term.field()) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(kind), Some(field)) => !field.filtered_by_spotify(kind),
+        };
+        self.include
+            .iter()
+            .filter(checked_locally)
+            .all(|term| term.matches(item))
+            && !self.exclude.iter().any(|term| term.matches(item))
+    }
+}
+
+impl FromStr for SearchQuery {
+    type Err = SearchQueryError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut query = SearchQuery::default();
+        let mut chars = input.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+            // a lone "-" is searched for as it is
+            let mut lookahead = chars.clone();
+            let excluded = lookahead.next() == Some('-')
+                && lookahead.next().is_some_and(|c| !c.is_whitespace());
+            if excluded {
+                chars.next();
+            }
+
+            let term = parse_term(&mut chars)?;
+            match (term, excluded) {
+                (ParsedTerm::Type(value), excluded) => {
+                    let kind = TYPES
+                        .iter()
+                        .find(|(name, _)| value.eq_ignore_ascii_case(name))
+                        .map(|(_, kind)| *kind)
+                        .ok_or_else(|| SearchQueryError::BadValue {
+                            field: "type".to_string(),
+                            value: value.clone(),
+                            err: format!(
+                                "should be one of {}",
+                                TYPES
+                                    .iter()
+                                    .map(|(name, _)| *name)
+                                    .collect::<Vec<_>>()
+                                    .join("|")
+                            ),
+                        })?;
+                    if excluded {
+                        return Err(SearchQueryError::BadValue {
+                            field: "type".to_string(),
+                            value,
+                            err: "types can't be excluded".to_string(),
+                        });
+                    }
+                    query.types.push(kind);
+                }
+                (ParsedTerm::Term(term), true) => query.exclude.push(term),
+                (ParsedTerm::Term(term), false) => query.include.push(term),
+            }
+        }
+
+        if query.include.is_empty() {
+            return Err(SearchQueryError::NoTerms);
+        }
+        Ok(query)
+    }
+}
+
+enum ParsedTerm {
+    Term(Term),
+    Type(String),
+}
+
+/// Parse the term at the start of `chars`, up to the next whitespace outside of quotes.
+fn parse_term(chars: &mut Peekable<Chars>) -> Result<ParsedTerm, SearchQueryError> {
+    if chars.next_if_eq(&'"').is_some() {
+        return Ok(ParsedTerm::Term(Term::Text(parse_quoted(chars)?)));
+    }
+
+    let mut word = String::new();
+    while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+        if c != ':' {
+            word.push(c);
+            continue;
+        }
+        let name = word.to_lowercase();
+        let field = Field::ALL.into_iter().find(|field| field.name() == name);
+        if field.is_none() && name != "type" {
+            // i.e. a Spotify URI, or a title like "Re:Stacks"
+            word.push(c);
+            continue;
+        }
+
+        let value = match chars.next_if_eq(&'"') {
+            Some(_) => parse_quoted(chars)?,
+            None => std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace())).collect(),
+        };
+        if value.trim().is_empty() {
+            return Err(SearchQueryError::MissingValue { field: name });
+        }
+        return match field {
+            Some(Field::Year) => {
+                parse_years(&value).map(|years| ParsedTerm::Term(Term::Year(years)))
+            }
+            Some(field) => Ok(ParsedTerm::Term(Term::Field(field, value))),
+            None => Ok(ParsedTerm::Type(value)),
+        };
+    }
+    Ok(ParsedTerm::Term(Term::Text(word)))
+}
+
+/// Parse the rest of a quoted phrase, whose opening quote was consumed already.
+fn parse_quoted(chars: &mut Peekable<Chars>) -> Result<String, SearchQueryError> {
+    let mut phrase = String::new();
+    for c in chars.by_ref() {
+        if c == '"' {
+            return Ok(phrase);
+        }
+        phrase.push(c);
+    }
+    Err(SearchQueryError::UnterminatedQuote)
+}
+
+/// Parse a year like `1998`, or a range of years like `1998-2002`.
+fn parse_years(value: &str) -> Result<RangeInclusive<u16>, SearchQueryError> {
+    let err = |err: &str| SearchQueryError::BadValue {
+        field: "year".to_string(),
+        value: value.to_string(),
+        err: err.to_string(),
+    };
+    let year = |year: &str| {
+        year.parse::<u16>()
+            .map_err(|_| err("expected a year like 1998"))
+    };
+    let (from, to) = match value.split_once('-') {
+        Some((from, to)) => (year(from)?, year(to)?),
+        None => (year(value)?, year(value)?),
+    };
+    if from > to {
+        return Err(err("the range ends before it starts"));
+    }
+    Ok(from..=to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(title: &str, artist: &str, album: &str) -> Track {
+        Track {
+            artists: vec![artist.to_string()],
+            album: Some(album.to_string()),
+            ..Track::test(title)
+        }
+    }
+
+    #[test]
+    fn parses_field_filters() {
+        let query: SearchQuery =
+            r#"artist:"Boards of Canada" year:1998-2002 album:geogaddi -live type:track"#
+                .parse()
+                .unwrap();
+        assert_eq!(
+            query.include,
+            [
+                Term::Field(Field::Artist, "Boards of Canada".to_string()),
+                Term::Year(1998..=2002),
+                Term::Field(Field::Album, "geogaddi".to_string()),
+            ]
+        );
+        assert_eq!(query.exclude, [Term::Text("live".to_string())]);
+        assert_eq!(query.types, [SearchType::Track]);
+    }
+
+    #[test]
+    fn keeps_plain_text() {
+        let query: SearchQuery = "spotify:track:4uLU6hMCjMI75M1A2tKUQC - \"Re: Stacks\""
+            .parse()
+            .unwrap();
+        assert_eq!(
+            query.include,
+            [
+                Term::Text("spotify:track:4uLU6hMCjMI75M1A2tKUQC".to_string()),
+                Term::Text("-".to_string()),
+                Term::Text("Re: Stacks".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_to_spotify_filters() {
+        let query: SearchQuery = r#"roygbiv artist:"Boards of Canada" year:1998 album:music"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            query.spotify_query(SearchType::Track).unwrap(),
+            r#"roygbiv artist:"Boards of Canada" year:1998 album:music"#
+        );
+        assert_eq!(
+            query.spotify_query(SearchType::Album).unwrap(),
+            r#"roygbiv artist:"Boards of Canada" year:1998 album:music"#
+        );
+        // artists and playlists can't be filtered by albums
+        assert_eq!(query.spotify_query(SearchType::Artist), None);
+        assert_eq!(query.spotify_query(SearchType::Playlist), None);
+
+        let query: SearchQuery = "ambient year:2020 type:episode".parse().unwrap();
+        assert_eq!(query.spotify_query(SearchType::Episode).unwrap(), "ambient");
+        assert_eq!(query.spotify_query(SearchType::Track), None);
+    }
+
+    #[test]
+    fn filters_results_locally() {
+        let query: SearchQuery = "artist:boards -live -album:peel".parse().unwrap();
+        let kind = Some(SearchType::Track);
+        assert!(query.matches(&track("Roygbiv", "Boards of Canada", "Music"), kind));
+        assert!(!query.matches(&track("Aquarius (Live)", "Boards of Canada", "Music"), kind));
+        assert!(!query.matches(&track("Aquarius", "Boards of Canada", "Peel Session"), kind));
+        // the artist is filtered by Spotify, but not for local files
+        assert!(query.matches(&track("Roygbiv", "Someone", "Music"), kind));
+        assert!(!query.matches(&track("Roygbiv", "Someone", "Music"), None));
+    }
+
+    #[test]
+    fn reports_errors() {
+        let error = |input: &str| input.parse::<SearchQuery>().unwrap_err().to_string();
+        assert_eq!(error("artist:"), "\"artist:\" requires a value");
+        assert_eq!(error("artist:\"Boards"), "Missing closing quote in search");
+        assert_eq!(
+            error("year:2002-1998"),
+            "Error with \"year:2002-1998\": the range ends before it starts"
+        );
+        assert_eq!(
+            error("type:song"),
+            "Error with \"type:song\": should be one of \
+             track|album|artist|playlist|show|podcast|episode"
+        );
+        assert_eq!(
+            error("-live type:track"),
+            "Search requires a term that isn't excluded"
+        );
+    }
+}
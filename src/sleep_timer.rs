@@ -0,0 +1,261 @@
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::command::SleepMode;
+use crate::utils::format_duration;
+
+/// How long the volume is ramped down for before playback stops.
+pub const FADE_DURATION: Duration = Duration::from_secs(10);
+
+/// The number of steps the volume is ramped down in.
+const FADE_STEPS: u64 = 100;
+
+/// What the sleep timer waits for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Trigger {
+    /// Stop at a point in time.
+    At(SystemTime),
+    /// Stop once this many tracks finished, including the current one.
+    Tracks(u32),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Timer {
+    trigger: Trigger,
+    /// The volume before fading out started.
+    volume: Option<u16>,
+}
+
+/// What has to be done to the player for the sleep timer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SleepAction {
+    /// Set the volume while fading out.
+    Fade(u16),
+    /// Stop playback, then restore the volume from before fading out if it was changed.
+    Stop(Option<u16>),
+}
+
+/// The state of a running sleep timer, as reported to IPC clients.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SleepStatus {
+    /// When playback stops in milliseconds since the UNIX epoch, for timers with a duration.
+    pub stops_at: Option<u64>,
+    /// How many tracks are played until playback stops, including the current one.
+    pub tracks: Option<u32>,
+    /// The time until playback stops, for timers with a duration.
+    #[serde(skip)]
+    pub remaining: Option<Duration>,
+}
+
+impl fmt::Display for SleepStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.remaining, self.tracks) {
+            (Some(remaining), _) => write!(
+                f,
+                "Playback stops in {}",
+                format_duration(&Duration::from_secs(remaining.as_secs()))
+            ),
+            (None, Some(1)) => write!(f, "Playback stops after the current track"),
+            (None, Some(tracks)) => write!(f, "Playback stops after {tracks} tracks"),
+            (None, None) => write!(f, "Playback stops now"),
+        }
+    }
+}
+
+/// Stops playback after some time or a number of tracks, fading out the volume before.
+#[derive(Clone, Default)]
+pub struct SleepTimer {
+    timer: Arc<RwLock<Option<Timer>>>,
+}
+
+impl SleepTimer {
+    /// Start the timer according to `mode` or cancel it for [SleepMode::Off], replacing a running
+    /// timer. Returns the volume to restore if the replaced timer was fading out already, or an
+    /// error if the duration is too long to tell when it runs out.
+    pub fn set(&self, mode: &SleepMode, now: SystemTime) -> Result<Option<u16>, String> {
+        let trigger = match mode {
+            SleepMode::Off => None,
+            SleepMode::Duration(duration) => Some(Trigger::At(
+                now.checked_add(*duration)
+                    .ok_or("The sleep duration is too long")?,
+            )),
+            SleepMode::EndOfTrack => Some(Trigger::Tracks(1)),
+            SleepMode::Tracks(tracks) => Some(Trigger::Tracks((*tracks).max(1))),
+        };
+        let timer = trigger.map(|trigger| Timer {
+            trigger,
+            volume: None,
+        });
+        Ok(std::mem::replace(&mut *self.timer.write().unwrap(), timer).and_then(|old| old.volume))
+    }
+
+    /// The state of the timer at `now`, if it is running.
+    pub fn status(&self, now: SystemTime) -> Option<SleepStatus> {
+        let timer = (*self.timer.read().unwrap())?;
+        Some(match timer.trigger {
+            Trigger::At(at) => SleepStatus {
+                stops_at: at
+                    .duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|since| since.as_millis() as u64),
+                tracks: None,
+                remaining: Some(at.duration_since(now).unwrap_or_default()),
+            },
+            Trigger::Tracks(tracks) => SleepStatus {
+                stops_at: None,
+                tracks: Some(tracks),
+                remaining: None,
+            },
+        })
+    }
+
+    /// Advance the timer to `now`, while the player is at `volume` and `track_remaining` is left
+    /// of the current track, if any.
+    pub fn update(
+        &self,
+        now: SystemTime,
+        volume: u16,
+        track_remaining: Option<Duration>,
+    ) -> Option<SleepAction> {
+        let mut guard = self.timer.write().unwrap();
+        let timer = guard.as_mut()?;
+
+        let left = match timer.trigger {
+            Trigger::At(at) => at.duration_since(now).unwrap_or_default(),
+            // the last track is over when it finished, which is reported separately
+            Trigger::Tracks(1) => track_remaining?.max(Duration::from_millis(1)),
+            Trigger::Tracks(_) => return None,
+        };
+
+        if left.is_zero() {
+            let volume = timer.volume;
+            *guard = None;
+            return Some(SleepAction::Stop(volume));
+        }
+
+        if left >= FADE_DURATION {
+            return None;
+        }
+        let original = *timer.volume.get_or_insert(volume);
+        let target = fade_volume(original, left);
+        (target != volume).then_some(SleepAction::Fade(target))
+    }
+
+    /// Count a finished track. Returns [SleepAction::Stop] instead of playing the next track if
+    /// that was the last one.
+    pub fn track_finished(&self) -> Option<SleepAction> {
+        let mut guard = self.timer.write().unwrap();
+        let timer = guard.as_mut()?;
+        match &mut timer.trigger {
+            Trigger::Tracks(1) => {
+                let volume = timer.volume;
+                *guard = None;
+                Some(SleepAction::Stop(volume))
+            }
+            Trigger::Tracks(tracks) => {
+                *tracks -= 1;
+                None
+            }
+            Trigger::At(_) => None,
+        }
+    }
+}
+
+/// The volume while fading out from `volume` with `left` to go, decreasing in steps.
+fn fade_volume(volume: u16, left: Duration) -> u16 {
+    let left = left.min(FADE_DURATION).as_millis() as u64;
+    let steps = (left * FADE_STEPS).div_ceil(FADE_DURATION.as_millis() as u64);
+    (volume as u64 * steps / FADE_STEPS) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOLUME: u16 = 50_000;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn fades_out_and_stops() {
+        let start = UNIX_EPOCH + secs(1_000_000);
+        let timer = SleepTimer::default();
+        timer.set(&SleepMode::Duration(secs(60)), start).unwrap();
+        assert_eq!(timer.status(start).unwrap().remaining, Some(secs(60)));
+        assert_eq!(timer.status(start).unwrap().stops_at, Some(1_000_060_000));
+
+        assert_eq!(timer.update(start + secs(45), VOLUME, None), None);
+        assert_eq!(
+            timer.update(start + secs(55), VOLUME, None),
+            Some(SleepAction::Fade(VOLUME / 2))
+        );
+        // the fade continues from the volume before fading out
+        assert_eq!(
+            timer.update(start + secs(59), VOLUME / 2, None),
+            Some(SleepAction::Fade(VOLUME / 10))
+        );
+        assert_eq!(
+            timer.update(start + secs(61), 0, None),
+            Some(SleepAction::Stop(Some(VOLUME)))
+        );
+        assert_eq!(timer.status(start + secs(61)), None);
+    }
+
+    #[test]
+    fn cancel_restores_volume() {
+        let start = UNIX_EPOCH;
+        let timer = SleepTimer::default();
+        timer.set(&SleepMode::Duration(secs(5)), start).unwrap();
+        assert_eq!(timer.set(&SleepMode::Off, start), Ok(None));
+
+        timer.set(&SleepMode::Duration(secs(5)), start).unwrap();
+        timer.update(start, VOLUME, None);
+        assert_eq!(timer.set(&SleepMode::Off, start), Ok(Some(VOLUME)));
+        assert_eq!(timer.update(start + secs(10), VOLUME, None), None);
+    }
+
+    #[test]
+    fn rejects_durations_that_never_run_out() {
+        let timer = SleepTimer::default();
+        let mode = SleepMode::Duration(Duration::MAX);
+        assert!(timer.set(&mode, UNIX_EPOCH).is_err());
+        assert_eq!(timer.status(UNIX_EPOCH), None);
+    }
+
+    #[test]
+    fn counts_tracks() {
+        let now = UNIX_EPOCH;
+        let timer = SleepTimer::default();
+        timer.set(&SleepMode::Tracks(2), now).unwrap();
+
+        // only the last track fades out
+        assert_eq!(timer.update(now, VOLUME, Some(secs(1))), None);
+        assert_eq!(timer.track_finished(), None);
+        assert_eq!(timer.status(now).unwrap().tracks, Some(1));
+        assert_eq!(timer.update(now, VOLUME, Some(secs(20))), None);
+        assert_eq!(
+            timer.update(now, VOLUME, Some(secs(5))),
+            Some(SleepAction::Fade(VOLUME / 2))
+        );
+        // the end of the track doesn't stop playback, the track has to finish
+        assert_eq!(
+            timer.update(now, VOLUME / 2, Some(Duration::ZERO)),
+            Some(SleepAction::Fade(VOLUME / 100))
+        );
+        assert_eq!(
+            timer.track_finished(),
+            Some(SleepAction::Stop(Some(VOLUME)))
+        );
+        assert_eq!(timer.track_finished(), None);
+    }
+
+    #[test]
+    fn end_of_track_without_fade() {
+        let timer = SleepTimer::default();
+        timer.set(&SleepMode::EndOfTrack, UNIX_EPOCH).unwrap();
+        assert_eq!(timer.track_finished(), Some(SleepAction::Stop(None)));
+    }
+}
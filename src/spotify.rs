@@ -3,10 +3,12 @@ use librespot_core::cache::Cache;
 use librespot_core::config::SessionConfig;
 use librespot_core::session::Session;
 use librespot_core::session::SessionError;
-use librespot_playback::audio_backend::SinkBuilder;
-use librespot_playback::config::PlayerConfig;
+use librespot_playback::audio_backend::{Sink, SinkBuilder};
+use librespot_playback::config::{
+    AudioFormat, NormalisationMethod, NormalisationType, PlayerConfig,
+};
 use librespot_playback::mixer::softmixer::SoftMixer;
-use librespot_playback::mixer::MixerConfig;
+use librespot_playback::mixer::{Mixer, MixerConfig};
 use log::{debug, error, info};
 
 use librespot_playback::audio_backend;
@@ -24,11 +26,21 @@ use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime};
 
 use crate::application::ASYNC_RUNTIME;
-use crate::config;
+use crate::audio_cache::AudioCache;
+use crate::config::{self, VolnormMode};
+use crate::crossfade::{FadeVolume, Gain};
+use crate::devices::RemoteDevice;
+use crate::equalizer::{EqSink, Equalizer};
 use crate::events::{Event, EventManager};
+use crate::local_player::LocalPlayer;
 use crate::model::playable::Playable;
+use crate::outputs::{self, Device};
+use crate::sleep_timer::SleepTimer;
 use crate::spotify_api::WebApi;
-use crate::spotify_worker::{Worker, WorkerCommand};
+use crate::spotify_worker::{
+    Deck, DeckFactory, OfflineWorker, PlayerFactory, Players, Worker, WorkerCommand,
+};
+use crate::time_stretch::{Speed, StretchSink};
 
 pub const VOLUME_PERCENT: u16 = ((u16::max_value() as f64) * 1.0 / 100.0) as u16;
 
@@ -49,6 +61,18 @@ pub struct Spotify {
     cfg: Arc<config::Config>,
     status: Arc<RwLock<PlayerEvent>>,
     pub api: WebApi,
+    /// Stops playback after a while, shared by every clone.
+    pub sleep_timer: SleepTimer,
+    /// Filters the audio of every player, shared by every clone.
+    pub equalizer: Equalizer,
+    /// The playback speed, shared by every clone.
+    speed: Speed,
+    /// The other device that is controlled instead of playing locally, shared by every clone.
+    pub remote_device: RemoteDevice,
+    /// The tracks that can be played without a connection, shared by every clone.
+    pub audio_cache: AudioCache,
+    /// Whether volume normalisation is on, which can differ from the configuration.
+    volnorm: Arc<RwLock<bool>>,
     elapsed: Arc<RwLock<Option<Duration>>>,
     since: Arc<RwLock<Option<SystemTime>>>,
     channel: Arc<RwLock<Option<mpsc::UnboundedSender<WorkerCommand>>>>,
@@ -67,6 +91,12 @@ impl Spotify {
             cfg: cfg.clone(),
             status: Arc::new(RwLock::new(PlayerEvent::Stopped)),
             api: WebApi::new(),
+            sleep_timer: SleepTimer::default(),
+            equalizer: Equalizer::default(),
+            speed: Speed::default(),
+            remote_device: RemoteDevice::default(),
+            audio_cache: AudioCache::new(&cfg),
+            volnorm: Arc::new(RwLock::new(cfg.values().volnorm.unwrap_or(false))),
             elapsed: Arc::new(RwLock::new(None)),
             since: Arc::new(RwLock::new(None)),
             channel: Arc::new(RwLock::new(None)),
@@ -78,6 +108,9 @@ impl Spotify {
         spotify.user = ASYNC_RUNTIME.block_on(user_rx).ok();
         let volume = cfg.state().volume;
         spotify.set_volume(volume);
+        if let Err(e) = spotify.set_eq_preset(cfg.state().eq_preset.as_deref()) {
+            error!("{e}");
+        }
 
         spotify.api.set_worker_channel(spotify.channel.clone());
         spotify.api.update_token();
@@ -98,7 +131,19 @@ impl Spotify {
             let cfg = self.cfg.clone();
             let events = self.events.clone();
             let volume = self.volume();
+            let volnorm = self.volnorm();
+            let device = self.output_device();
             let credentials = self.credentials.clone();
+            let (equalizer, speed) = (self.equalizer.clone(), self.speed.clone());
+            let audio_cache = self.audio_cache.clone();
+            // the audio of every player is filtered before it is written to the sink
+            let filters = {
+                let speed = speed.clone();
+                move |sink| -> Box<dyn Sink> {
+                    let sink = StretchSink::new(sink, speed.clone());
+                    Box::new(EqSink::new(Box::new(sink), equalizer.clone()))
+                }
+            };
             ASYNC_RUNTIME.spawn(Self::worker(
                 worker_channel,
                 events,
@@ -107,6 +152,11 @@ impl Spotify {
                 credentials,
                 user_tx,
                 volume,
+                volnorm,
+                device,
+                filters,
+                audio_cache,
+                speed,
             ));
         }
     }
@@ -176,6 +226,25 @@ impl Spotify {
         Some(backend.1)
     }
 
+    /// Creates decks that play through `session` on sinks of `backend`.
+    fn deck_factory(
+        session: Session,
+        backend: impl Fn(Option<String>, AudioFormat) -> Box<dyn Sink> + Clone + Send + 'static,
+    ) -> DeckFactory {
+        Box::new(move |player_config, device, mixer: &dyn Mixer| {
+            let gain = Gain::default();
+            let backend = backend.clone();
+            let (player, player_events) = Player::new(
+                player_config,
+                session.clone(),
+                Box::new(FadeVolume::new(mixer.get_soft_volume(), gain.clone())),
+                move || (backend)(device, AudioFormat::default()),
+            );
+            Deck::new(player, player_events, gain)
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn worker(
         worker_channel: Arc<RwLock<Option<mpsc::UnboundedSender<WorkerCommand>>>>,
         events: EventManager,
@@ -184,6 +253,11 @@ impl Spotify {
         credentials: Credentials,
         user_tx: Option<oneshot::Sender<String>>,
         volume: u16,
+        volnorm: bool,
+        device: Option<String>,
+        filters: impl Fn(Box<dyn Sink>) -> Box<dyn Sink> + Clone + Send + 'static,
+        audio_cache: AudioCache,
+        speed: Speed,
     ) {
         let bitrate_str = cfg.values().bitrate.unwrap_or(320).to_string();
         let bitrate = Bitrate::from_str(&bitrate_str);
@@ -194,16 +268,21 @@ impl Spotify {
         let player_config = PlayerConfig {
             gapless: cfg.values().gapless.unwrap_or(true),
             bitrate: bitrate.unwrap_or(Bitrate::Bitrate320),
-            normalisation: cfg.values().volnorm.unwrap_or(false),
+            normalisation: volnorm,
+            normalisation_type: match cfg.values().volnorm_mode.unwrap_or_default() {
+                VolnormMode::Track => NormalisationType::Track,
+                VolnormMode::Album => NormalisationType::Album,
+                VolnormMode::Auto => NormalisationType::Auto,
+            },
+            // the limiter only turns down peaks, instead of the whole track if it would clip
+            normalisation_method: match cfg.values().volnorm_limiter.unwrap_or(true) {
+                true => NormalisationMethod::Dynamic,
+                false => NormalisationMethod::Basic,
+            },
             normalisation_pregain_db: cfg.values().volnorm_pregain.unwrap_or(0.0),
             ..Default::default()
         };
 
-        let session = Self::create_session(&cfg, credentials)
-            .await
-            .expect("Could not create session");
-        user_tx.map(|tx| tx.send(session.username()));
-
         let create_mixer = librespot_playback::mixer::find(Some(SoftMixer::NAME))
             .expect("could not create softvol mixer");
         let mixer = create_mixer(MixerConfig::default());
@@ -212,21 +291,58 @@ impl Spotify {
         let backend_name = cfg.values().backend.clone();
         let backend =
             Self::init_backend(backend_name).expect("Could not find an audio playback backend");
-        let audio_format: librespot_playback::config::AudioFormat = Default::default();
-        let (player, player_events) = Player::new(
-            player_config,
-            session.clone(),
-            mixer.get_soft_volume(),
-            move || (backend)(cfg.values().backend_device.clone(), audio_format),
-        );
+        let audio_format: AudioFormat = Default::default();
+        let backend =
+            move |device: Option<String>, format: AudioFormat| filters((backend)(device, format));
+
+        let username = credentials.username.clone();
+        let session = match Self::create_session(&cfg, credentials).await {
+            Ok(session) => session,
+            Err(e) => {
+                // without a session, local files and tracks in the audio cache can still be played
+                error!("could not create session, playing offline: {}", e);
+                user_tx.map(|tx| tx.send(username));
+                let local_player = LocalPlayer::new(mixer.get_soft_volume(), move || {
+                    (backend)(device, audio_format)
+                });
+                let mut worker =
+                    OfflineWorker::new(events.clone(), commands, local_player, mixer, audio_cache);
+                worker.run_loop().await;
+
+                *worker_channel
+                    .write()
+                    .expect("can't writelock worker channel") = None;
+                events.send(Event::SessionDied);
+                return;
+            }
+        };
+        user_tx.map(|tx| tx.send(session.username()));
+
+        let create_deck = Self::deck_factory(session.clone(), backend.clone());
+        // the second deck is only created to crossfade into the next track
+        let create_next_deck = Self::deck_factory(session.clone(), backend.clone());
+        let create_players: PlayerFactory =
+            Box::new(move |player_config, device, mixer: &dyn Mixer| {
+                let local_backend = backend.clone();
+                Players {
+                    deck: create_deck(player_config, device.clone(), mixer),
+                    local_player: LocalPlayer::new(mixer.get_soft_volume(), move || {
+                        (local_backend)(device, audio_format)
+                    }),
+                }
+            });
 
         let mut worker = Worker::new(
             events.clone(),
-            player_events,
             commands,
             session,
-            player,
+            player_config,
+            device,
+            create_players,
+            create_next_deck,
             mixer,
+            audio_cache,
+            speed,
         );
         debug!("worker thread ready.");
         worker.run_loop().await;
@@ -246,11 +362,13 @@ impl Spotify {
         (*status).clone()
     }
 
+    /// The position in the current item. While playing, the time since playback started is
+    /// scaled by the playback speed.
     pub fn get_current_progress(&self) -> Duration {
         self.get_elapsed().unwrap_or_else(|| Duration::from_secs(0))
             + self
                 .get_since()
-                .map(|t| t.elapsed().unwrap())
+                .map(|t| t.elapsed().unwrap_or_default().mul_f64(self.speed()))
                 .unwrap_or_else(|| Duration::from_secs(0))
     }
 
@@ -302,8 +420,10 @@ impl Spotify {
                 self.set_since(None);
             }
             PlayerEvent::Playing(playback_start) => {
-                self.set_since(Some(playback_start));
-                self.set_elapsed(None);
+                // the start is reported for the normal speed, so count from the current position
+                let now = SystemTime::now();
+                self.set_elapsed(Some(now.duration_since(playback_start).unwrap_or_default()));
+                self.set_since(Some(now));
             }
             PlayerEvent::Stopped | PlayerEvent::FinishedTrack => {
                 self.set_elapsed(None);
@@ -372,10 +492,91 @@ impl Spotify {
         info!("setting volume to {}", volume);
         self.cfg.with_state_mut(|mut s| s.volume = volume);
         self.send_worker(WorkerCommand::SetVolume(volume));
+        self.events.send(Event::StateChanged);
+    }
+
+    /// Switch the equalizer to the preset `name` from the configuration, or turn it off for
+    /// `None`. The preset is saved, so it is used again after restarting.
+    pub fn set_eq_preset(&self, name: Option<&str>) -> Result<(), String> {
+        let preset = match name {
+            Some(name) => {
+                let presets = self.cfg.values().eq_presets.clone().unwrap_or_default();
+                let preset = presets
+                    .get(name)
+                    .ok_or_else(|| format!("Unknown equalizer preset \"{name}\""))?;
+                Some((name.to_string(), preset.clone()))
+            }
+            None => None,
+        };
+        self.equalizer.set(preset);
+        self.cfg
+            .with_state_mut(|mut s| s.eq_preset = name.map(str::to_string));
+        Ok(())
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed.get()
+    }
+
+    /// Change the playback speed, keeping the current position.
+    pub fn set_speed(&self, speed: f64) {
+        if self.get_since().is_some() {
+            self.set_elapsed(Some(self.get_current_progress()));
+            self.set_since(Some(SystemTime::now()));
+        }
+        self.speed.set(speed);
+        self.events.send(Event::StateChanged);
+    }
+
+    pub fn volnorm(&self) -> bool {
+        *self
+            .volnorm
+            .read()
+            .expect("can't readlock volume normalisation")
+    }
+
+    /// Turn volume normalisation on or off until the next start. The current item continues at
+    /// the same position.
+    pub fn set_volnorm(&self, enabled: bool) {
+        *self
+            .volnorm
+            .write()
+            .expect("can't writelock volume normalisation") = enabled;
+        let position_ms = self.get_current_progress().as_millis() as u32;
+        self.send_worker(WorkerCommand::SetNormalisation(enabled, position_ms));
+    }
+
+    /// Normalise the next track by the loudness of its album, if `volnorm_mode` is `auto`.
+    pub fn set_album_gain(&self, album_gain: bool) {
+        self.send_worker(WorkerCommand::SetAlbumGain(album_gain));
+    }
+
+    /// The output devices of the audio backend.
+    pub fn output_devices(&self) -> Result<Vec<Device>, String> {
+        outputs::devices(self.cfg.values().backend.as_deref())
+    }
+
+    /// The output device that was switched to last, or the one from the configuration.
+    pub fn output_device(&self) -> Option<String> {
+        self.cfg
+            .state()
+            .output_device
+            .clone()
+            .or_else(|| self.cfg.values().backend_device.clone())
+    }
+
+    /// Switch to the output `device`, or back to the one from the configuration for `None`. The
+    /// device is remembered, and the current item continues at the same position.
+    pub fn set_output_device(&self, device: Option<String>) {
+        self.cfg
+            .with_state_mut(|mut s| s.output_device = device.clone());
+        let position_ms = self.get_current_progress().as_millis() as u32;
+        self.send_worker(WorkerCommand::SetDevice(self.output_device(), position_ms));
     }
 
-    pub fn preload(&self, track: &Playable) {
-        self.send_worker(WorkerCommand::Preload(track.clone()));
+    /// Prepare playing `track` next, crossfading into it for `crossfade` if set.
+    pub fn preload(&self, track: &Playable, crossfade: Option<Duration>) {
+        self.send_worker(WorkerCommand::Preload(track.clone(), crossfade));
     }
 
     pub fn shutdown(&self) {
@@ -14,10 +14,10 @@ use log::{debug, error, info};
 
 use rspotify::http::HttpError;
 use rspotify::model::{
-    AlbumId, AlbumType, ArtistId, CursorBasedPage, EpisodeId, FullAlbum, FullArtist, FullEpisode,
-    FullPlaylist, FullShow, FullTrack, ItemPositions, Market, Page, PlayableId, PlaylistId,
-    PrivateUser, Recommendations, SavedAlbum, SavedTrack, SearchResult, SearchType, Show, ShowId,
-    SimplifiedTrack, TrackId, UserId,
+    AdditionalType, AlbumId, AlbumType, ArtistId, CurrentPlaybackContext, CursorBasedPage, Device,
+    EpisodeId, FullAlbum, FullArtist, FullEpisode, FullPlaylist, FullShow, FullTrack,
+    ItemPositions, Market, Page, PlayableId, PlaylistId, PrivateUser, Recommendations, SavedAlbum,
+    SavedTrack, SearchResult, SearchType, Show, ShowId, SimplifiedTrack, TrackId, UserId,
 };
 use rspotify::{prelude::*, AuthCodeSpotify, ClientError, ClientResult, Token};
 use std::collections::HashSet;
@@ -51,6 +51,19 @@ impl WebApi {
         Self::default()
     }
 
+    /// An API client that sends its requests to `base_url` instead of the Spotify Web API.
+    #[cfg(test)]
+    pub fn with_base_url(base_url: &str) -> WebApi {
+        let mut api = Self::default();
+        api.api.config.api_base_url = base_url.to_string();
+        *api.api.token.lock().unwrap() = Some(Token {
+            access_token: "token".to_string(),
+            expires_at: None,
+            ..Default::default()
+        });
+        api
+    }
+
     pub fn set_user(&mut self, user: Option<String>) {
         self.user = user;
     }
@@ -287,6 +300,23 @@ impl WebApi {
         self.api_with_retry(|api| api.track(tid.clone()))
     }
 
+    /// The tracks with the ids `track_ids`, which are fetched in batches of 50.
+    #[cfg(feature = "connect")]
+    pub fn tracks(&self, track_ids: &[String]) -> Option<Vec<FullTrack>> {
+        let tids = track_ids
+            .iter()
+            .map(|id| TrackId::from_id(id.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+        let mut tracks = Vec::with_capacity(tids.len());
+        for batch in tids.chunks(50) {
+            tracks.extend(self.api_with_retry(|api| {
+                api.tracks(batch.iter().cloned(), Some(Market::FromToken))
+            })?);
+        }
+        Some(tracks)
+    }
+
     pub fn get_show(&self, show_id: &str) -> Option<FullShow> {
         let sid = ShowId::from_id(show_id).ok()?;
         self.api_with_retry(|api| api.get_a_show(sid.clone(), Some(Market::FromToken)))
@@ -476,7 +506,14 @@ impl WebApi {
                     Ok(page) => Ok(ApiPage {
                         offset: page.offset,
                         total: page.total,
-                        items: page.items.iter().map(|se| se.into()).collect(),
+                        items: page
+                            .items
+                            .iter()
+                            .map(|se| Episode {
+                                show_id: Some(show_id.clone()),
+                                ..se.into()
+                            })
+                            .collect(),
                     }),
                     Err(e) => Err(e),
                 }
@@ -608,6 +645,48 @@ impl WebApi {
             .map(|fa| fa.iter().map(|a| a.into()).collect())
     }
 
+    /// The Spotify Connect devices of the user.
+    pub fn devices(&self) -> Option<Vec<Device>> {
+        self.api_with_retry(|api| api.device())
+    }
+
+    /// The playback of the user on the active device, None if there is no active device.
+    pub fn current_playback(&self) -> Option<CurrentPlaybackContext> {
+        self.api_with_retry(|api| api.current_playback(None, None::<&[AdditionalType]>))
+            .flatten()
+    }
+
+    /// Move the playback of the user to the device `device_id`, keeping whether it plays.
+    pub fn transfer_playback(&self, device_id: &str) -> Option<()> {
+        self.api_with_retry(|api| api.transfer_playback(device_id, None))
+    }
+
+    pub fn remote_play(&self, device_id: &str) -> Option<()> {
+        self.api_with_retry(|api| api.resume_playback(Some(device_id), None))
+    }
+
+    pub fn remote_pause(&self, device_id: &str) -> Option<()> {
+        self.api_with_retry(|api| api.pause_playback(Some(device_id)))
+    }
+
+    pub fn remote_next(&self, device_id: &str) -> Option<()> {
+        self.api_with_retry(|api| api.next_track(Some(device_id)))
+    }
+
+    pub fn remote_previous(&self, device_id: &str) -> Option<()> {
+        self.api_with_retry(|api| api.previous_track(Some(device_id)))
+    }
+
+    pub fn remote_seek(&self, device_id: &str, position_ms: u32) -> Option<()> {
+        let position = ChronoDuration::milliseconds(position_ms.into());
+        self.api_with_retry(|api| api.seek_track(position, Some(device_id)))
+    }
+
+    /// Set the volume of the device `device_id` to `percent`, at most 100.
+    pub fn remote_volume(&self, device_id: &str, percent: u8) -> Option<()> {
+        self.api_with_retry(|api| api.volume(percent.min(100), Some(device_id)))
+    }
+
     pub fn categories(&self) -> ApiResult<Category> {
         const MAX_LIMIT: u32 = 50;
         let spotify = self.clone();
@@ -661,3 +740,71 @@ impl WebApi {
         self.api_with_retry(|api| api.current_user())
     }
 }
+
+/// Answer one request of the Web API per body in `responses`, returning the address and the
+/// request lines of the received requests.
+#[cfg(test)]
+pub fn stub_api(responses: Vec<&'static str>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+    let responses = responses
+        .into_iter()
+        .map(|body| match body {
+            "" => ("204 No Content", body),
+            body => ("200 OK", body),
+        })
+        .collect();
+    let (address, server) = stub_server(responses);
+    let requests = std::thread::spawn(move || {
+        let requests = server.join().unwrap();
+        requests.into_iter().map(|(request, _)| request).collect()
+    });
+    (address, requests)
+}
+
+/// Answer one HTTP request per status and body in `responses`, returning the address and the
+/// request lines and bodies of the received requests.
+#[cfg(test)]
+pub fn stub_server(
+    responses: Vec<(&'static str, &'static str)>,
+) -> (String, std::thread::JoinHandle<Vec<(String, String)>>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("http://{}/", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        responses
+            .into_iter()
+            .map(|(status, body)| {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut request_body = vec![0; length];
+                reader.read_exact(&mut request_body).unwrap();
+
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+                (
+                    request.trim_end().trim_end_matches(" HTTP/1.1").to_string(),
+                    String::from_utf8(request_body).unwrap(),
+                )
+            })
+            .collect()
+    });
+    (address, server)
+}
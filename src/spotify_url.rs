@@ -1,6 +1,14 @@
 use std::fmt;
 
+use crate::model::album::Album;
+use crate::model::artist::Artist;
+use crate::model::episode::Episode;
+use crate::model::playlist::Playlist;
+use crate::model::show::Show;
+use crate::model::track::Track;
 use crate::spotify::UriType;
+use crate::spotify_api::WebApi;
+use crate::traits::ListItem;
 
 use url::{Host, Url};
 
@@ -73,6 +81,42 @@ impl SpotifyUrl {
 
         Some(SpotifyUrl::new(id, uri_type))
     }
+
+    /// Get media id and type from either an open.spotify.com url or a Spotify URI like
+    /// `spotify:track:4uLU6hMCjMI75M1A2tKUQC`.
+    pub fn from_uri<S: AsRef<str>>(s: S) -> Option<SpotifyUrl> {
+        let s = s.as_ref();
+        if let Some(uri_type) = UriType::from_uri(s) {
+            let id = &s[s.rfind(':')? + 1..];
+            (!id.is_empty()).then(|| SpotifyUrl::new(id, uri_type))
+        } else {
+            Self::from_url(s)
+        }
+    }
+
+    /// Fetch the item this url points to using the Web API.
+    pub fn resolve(&self, api: &WebApi) -> Option<Box<dyn ListItem>> {
+        match self.uri_type {
+            UriType::Track => api
+                .track(&self.id)
+                .map(|track| Track::from(&track).as_listitem()),
+            UriType::Album => api
+                .album(&self.id)
+                .map(|album| Album::from(&album).as_listitem()),
+            UriType::Playlist => api
+                .playlist(&self.id)
+                .map(|playlist| Playlist::from(&playlist).as_listitem()),
+            UriType::Artist => api
+                .artist(&self.id)
+                .map(|artist| Artist::from(&artist).as_listitem()),
+            UriType::Episode => api
+                .episode(&self.id)
+                .map(|episode| Episode::from(&episode).as_listitem()),
+            UriType::Show => api
+                .get_show(&self.id)
+                .map(|show| Show::from(&show).as_listitem()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -81,6 +125,7 @@ mod tests {
 
     use super::SpotifyUrl;
     use crate::spotify::UriType;
+    use crate::spotify_api::{stub_api, WebApi};
 
     #[test]
     fn test_urls() {
@@ -116,4 +161,77 @@ mod tests {
             assert_eq!(result.uri_type, case.1.uri_type);
         }
     }
+
+    #[test]
+    fn parses_uris() {
+        let test_cases = [
+            ("spotify:track:6fRJg3R90w0juYoCJXxj2d", UriType::Track),
+            ("spotify:album:1XFxe8bkTryTODn0lk4CNa", UriType::Album),
+            ("spotify:artist:6LEeAFiJF8OuPx747e1wxR", UriType::Artist),
+            ("spotify:playlist:0OgoSs65CLDPn6AF6tsZVg", UriType::Playlist),
+            (
+                "spotify:user:villainy:playlist:0OgoSs65CLDPn6AF6tsZVg",
+                UriType::Playlist,
+            ),
+            ("spotify:show:4MZfJbM2MXzZdPbv6gi5lJ", UriType::Show),
+            ("spotify:episode:3QE6rfmjRaeqXSqeWcIWF6", UriType::Episode),
+            (
+                "https://open.spotify.com/track/6fRJg3R90w0juYoCJXxj2d",
+                UriType::Track,
+            ),
+        ];
+
+        for (uri, uri_type) in test_cases {
+            let result = SpotifyUrl::from_uri(uri).unwrap();
+            assert_eq!(result.id, uri.rsplit(['/', ':']).next().unwrap());
+            assert_eq!(result.uri_type, uri_type);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_uris() {
+        for uri in [
+            "",
+            "spotify:track:",
+            "spotify:foo:6fRJg3R90w0juYoCJXxj2d",
+            "track:6fRJg3R90w0juYoCJXxj2d",
+            "https://example.com/track/6fRJg3R90w0juYoCJXxj2d",
+            "https://open.spotify.com/user/villainy/6fRJg3R90w0juYoCJXxj2d",
+        ] {
+            assert!(SpotifyUrl::from_uri(uri).is_none(), "{uri}");
+        }
+    }
+
+    #[test]
+    fn ignores_local_uris() {
+        assert!(SpotifyUrl::from_uri("spotify:local:Artist:Album:Title:180").is_none());
+        assert!(SpotifyUrl::from_uri("file:///home/user/Music/track.flac").is_none());
+    }
+
+    #[test]
+    fn resolves_urls() {
+        let (address, server) = stub_api(vec![
+            r#"{"external_urls": {}, "followers": {"href": null, "total": 1},
+                "genres": [], "href": "", "id": "6LEeAFiJF8OuPx747e1wxR", "images": [],
+                "name": "Artist", "popularity": 50,
+                "type": "artist", "uri": "spotify:artist:6LEeAFiJF8OuPx747e1wxR"}"#,
+            "not json",
+        ]);
+        let api = WebApi::with_base_url(&address);
+        let url = SpotifyUrl::new("6LEeAFiJF8OuPx747e1wxR", UriType::Artist);
+        let artist = url.resolve(&api).unwrap();
+        assert_eq!(
+            artist.share_url().as_deref(),
+            Some("https://open.spotify.com/artist/6LEeAFiJF8OuPx747e1wxR")
+        );
+        assert!(url.resolve(&api).is_none());
+
+        assert_eq!(
+            server.join().unwrap(),
+            [
+                "GET /artists/6LEeAFiJF8OuPx747e1wxR",
+                "GET /artists/6LEeAFiJF8OuPx747e1wxR"
+            ]
+        );
+    }
 }
@@ -1,18 +1,26 @@
+use crate::audio_cache::{self, AudioCache, CachedFile};
 use crate::config;
+use crate::crossfade::{self, Gain};
 use crate::events::{Event, EventManager};
+use crate::local_player::{LocalPlayer, LocalPlayerEvent, Source};
 use crate::model::playable::Playable;
 use crate::queue::QueueEvent;
 use crate::spotify::PlayerEvent;
+use crate::time_stretch::Speed;
 use futures::channel::oneshot;
+use futures::future::OptionFuture;
 use futures::{Future, FutureExt};
 use librespot_core::keymaster::Token;
 use librespot_core::session::Session;
 use librespot_core::spotify_id::{SpotifyAudioType, SpotifyId};
+use librespot_metadata::AudioItem;
+use librespot_playback::config::PlayerConfig;
 use librespot_playback::mixer::Mixer;
 use librespot_playback::player::{Player, PlayerEvent as LibrespotPlayerEvent};
 use log::{debug, error, info, warn};
-use std::time::Duration;
-use std::{pin::Pin, time::SystemTime};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::mpsc;
 use tokio::time;
 use tokio_stream::wrappers::UnboundedReceiverStream;
@@ -27,39 +35,148 @@ pub(crate) enum WorkerCommand {
     Seek(u32),
     SetVolume(u16),
     RequestToken(oneshot::Sender<Option<Token>>),
-    Preload(Playable),
+    Preload(Playable, Option<Duration>),
+    /// Turn volume normalisation on or off, continuing the current track at the position.
+    SetNormalisation(bool, u32),
+    /// Switch to the output device, or the default one, continuing the current item at the
+    /// position.
+    SetDevice(Option<String>, u32),
+    /// Whether the next track is normalised by the loudness of its album in the auto mode.
+    SetAlbumGain(bool),
     Shutdown,
 }
 
+/// A librespot player along with its events and the gain it is faded with.
+pub(crate) struct Deck {
+    player: Player,
+    events: UnboundedReceiverStream<LibrespotPlayerEvent>,
+    gain: Gain,
+}
+
+impl Deck {
+    pub(crate) fn new(
+        player: Player,
+        events: mpsc::UnboundedReceiver<LibrespotPlayerEvent>,
+        gain: Gain,
+    ) -> Self {
+        Self {
+            player,
+            events: UnboundedReceiverStream::new(events),
+            gain,
+        }
+    }
+}
+
+/// The players of the worker that are always needed, which play on the same output device.
+pub(crate) struct Players {
+    pub deck: Deck,
+    pub local_player: (LocalPlayer, mpsc::UnboundedReceiver<LocalPlayerEvent>),
+}
+
+/// Creates the players for a player configuration and an output device, with volumes of the
+/// mixer.
+pub(crate) type PlayerFactory =
+    Box<dyn Fn(PlayerConfig, Option<String>, &dyn Mixer) -> Players + Send>;
+
+/// Creates a deck for a player configuration and an output device, with volumes of the mixer.
+pub(crate) type DeckFactory = Box<dyn Fn(PlayerConfig, Option<String>, &dyn Mixer) -> Deck + Send>;
+
+/// The item on the current player, to load it again when the players are recreated.
+enum Loaded {
+    /// A track and whether it was loaded with album gain.
+    Track(SpotifyId, bool),
+    LocalFile(PathBuf),
+}
+
+/// A crossfade into the track that is loaded on the next deck.
+struct Crossfade {
+    uri: String,
+    duration: Duration,
+    /// Whether the track finished loading and can be started right away.
+    ready: bool,
+}
+
+/// A crossfade in progress, from the next deck to the current one.
+struct Fade {
+    uri: String,
+    start: Instant,
+    duration: Duration,
+}
+
 pub struct Worker {
     events: EventManager,
-    player_events: UnboundedReceiverStream<LibrespotPlayerEvent>,
     commands: UnboundedReceiverStream<WorkerCommand>,
     session: Session,
-    player: Player,
+    player_config: PlayerConfig,
+    /// The output device, the default one of the backend if not set.
+    device: Option<String>,
+    create_players: PlayerFactory,
+    create_deck: DeckFactory,
+    /// The player of the current track.
+    deck: Deck,
+    /// The player the next track is loaded on before crossfading into it, and the previous track
+    /// fades out on afterwards. It is only created for the first crossfade.
+    next_deck: Option<Deck>,
+    crossfade: Option<Crossfade>,
+    fade: Option<Fade>,
+    /// When the current track started or continued playing and how much of it was left then at
+    /// normal speed, while it is playing.
+    track_end: Option<(Instant, Duration)>,
+    speed: Speed,
+    /// Whether tracks are normalised by the loudness of their album in the auto mode.
+    album_gain: bool,
+    loaded: Option<Loaded>,
+    local_player: LocalPlayer,
+    local_player_events: UnboundedReceiverStream<LocalPlayerEvent>,
+    /// Whether the current item is a local file, which is played by `local_player`.
+    local: bool,
     token_task: Pin<Box<dyn Future<Output = ()> + Send>>,
     active: bool,
     mixer: Box<dyn Mixer>,
+    audio_cache: AudioCache,
 }
 
 impl Worker {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         events: EventManager,
-        player_events: mpsc::UnboundedReceiver<LibrespotPlayerEvent>,
         commands: mpsc::UnboundedReceiver<WorkerCommand>,
         session: Session,
-        player: Player,
+        player_config: PlayerConfig,
+        device: Option<String>,
+        create_players: PlayerFactory,
+        create_deck: DeckFactory,
         mixer: Box<dyn Mixer>,
+        audio_cache: AudioCache,
+        speed: Speed,
     ) -> Worker {
+        let Players {
+            deck,
+            local_player: (local_player, local_player_events),
+        } = create_players(player_config.clone(), device.clone(), mixer.as_ref());
         Worker {
             events,
-            player_events: UnboundedReceiverStream::new(player_events),
             commands: UnboundedReceiverStream::new(commands),
-            player,
             session,
+            player_config,
+            device,
+            create_players,
+            create_deck,
+            deck,
+            next_deck: None,
+            crossfade: None,
+            fade: None,
+            track_end: None,
+            speed,
+            album_gain: false,
+            loaded: None,
+            local_player,
+            local_player_events: UnboundedReceiverStream::new(local_player_events),
+            local: false,
             token_task: Box::pin(futures::future::pending()),
             active: false,
             mixer,
+            audio_cache,
         }
     }
 }
@@ -67,7 +184,11 @@ impl Worker {
 impl Drop for Worker {
     fn drop(&mut self) {
         debug!("Worker thread is shutting down, stopping player");
-        self.player.stop();
+        self.deck.player.stop();
+        if let Some(next_deck) = &self.next_deck {
+            next_deck.player.stop();
+        }
+        self.local_player.stop();
     }
 }
 
@@ -98,8 +219,185 @@ impl Worker {
         )
     }
 
+    /// Fetch the id and the key of the audio file librespot plays for the track `id`, so that it
+    /// can be played from the audio cache without a connection once the file is complete.
+    fn remember_file(&self, id: SpotifyId) {
+        let Ok(base62) = id.to_base62() else {
+            return;
+        };
+        if !self.audio_cache.is_enabled() || self.audio_cache.contains(&base62) {
+            return;
+        }
+        let (session, audio_cache) = (self.session.clone(), self.audio_cache.clone());
+        let formats = audio_cache::file_formats(self.player_config.bitrate);
+        tokio::spawn(async move {
+            let item = match AudioItem::get_audio_item(&session, id).await {
+                Ok(item) => item,
+                Err(e) => return debug!("could not fetch the files of {}: {:?}", base62, e),
+            };
+            let Some(&file_id) = formats.iter().find_map(|format| item.files.get(format)) else {
+                return;
+            };
+            match session.audio_key().request(id, file_id).await {
+                Ok(key) => audio_cache.add(base62, CachedFile::new(file_id, key)),
+                Err(e) => debug!("could not fetch the key of {}: {:?}", base62, e),
+            }
+        });
+    }
+
+    /// Whether `playable` is faded in already, so loading it would restart it.
+    fn is_fading_in(&self, playable: &Playable) -> bool {
+        self.fade
+            .as_ref()
+            .is_some_and(|fade| fade.uri == playable.uri())
+    }
+
+    /// The deck to crossfade into the next track on, which is created when it is first needed.
+    fn next_deck(&mut self) -> &mut Deck {
+        self.next_deck.get_or_insert_with(|| {
+            let next_deck = (self.create_deck)(
+                self.player_config.clone(),
+                self.device.clone(),
+                self.mixer.as_ref(),
+            );
+            next_deck
+                .player
+                .set_auto_normalise_as_album(self.album_gain);
+            next_deck
+        })
+    }
+
+    /// How long the current track plays until it ends at the current speed, while it is playing.
+    fn time_to_track_end(&self) -> Option<Duration> {
+        let (since, remaining) = self.track_end?;
+        let speed = self.speed.get();
+        Some(
+            remaining
+                .saturating_sub(since.elapsed().mul_f64(speed))
+                .div_f64(speed),
+        )
+    }
+
+    /// Stop the previous track if it is still fading out.
+    fn finish_fade(&mut self) {
+        let fading = self.fade.take().is_some();
+        self.deck.gain.set(1.0);
+        if let Some(next_deck) = &self.next_deck {
+            if fading {
+                next_deck.player.stop();
+            }
+            next_deck.gain.set(1.0);
+        }
+    }
+
+    /// Unload the next track if it was loaded for a crossfade.
+    fn unload_next_track(&mut self) {
+        if self.crossfade.take().is_some() {
+            if let Some(next_deck) = &self.next_deck {
+                next_deck.player.stop();
+            }
+        }
+    }
+
+    /// Stop crossfading and unload the next track.
+    fn cancel_crossfade(&mut self) {
+        self.finish_fade();
+        self.unload_next_track();
+    }
+
+    /// Replace the players with ones for the current player configuration and output device,
+    /// which librespot can't change while playing. The current item is loaded again and continues
+    /// at `position_ms`.
+    fn recreate_players(&mut self, position_ms: u32) {
+        self.cancel_crossfade();
+        self.deck.player.stop();
+        self.local_player.stop();
+        let Players {
+            deck,
+            local_player: (local_player, local_player_events),
+        } = (self.create_players)(
+            self.player_config.clone(),
+            self.device.clone(),
+            self.mixer.as_ref(),
+        );
+        self.deck = deck;
+        // the next deck is created again with the new configuration for the next crossfade
+        self.next_deck = None;
+        self.local_player = local_player;
+        self.local_player_events = UnboundedReceiverStream::new(local_player_events);
+        self.track_end = None;
+
+        let album_gain = match self.loaded {
+            Some(Loaded::Track(_, album_gain)) => album_gain,
+            _ => self.album_gain,
+        };
+        // the gain is only picked when the track starts, after it finished loading
+        self.deck.player.set_auto_normalise_as_album(album_gain);
+        match &self.loaded {
+            Some(Loaded::Track(id, _)) if !self.local => {
+                self.deck.player.load(*id, self.active, position_ms);
+            }
+            Some(Loaded::LocalFile(path)) if self.local => {
+                self.local_player
+                    .load(Source::File(path.clone()), self.active, position_ms);
+            }
+            _ => {}
+        }
+    }
+
+    /// Start crossfading into the next track once the current one is about to end, and adjust the
+    /// gains of a crossfade in progress.
+    fn update_crossfade(&mut self) {
+        let now = Instant::now();
+        if let Some(fade) = &self.fade {
+            let progress =
+                now.duration_since(fade.start).as_secs_f64() / fade.duration.as_secs_f64();
+            if progress >= 1.0 {
+                self.finish_fade();
+            } else {
+                let (outgoing, incoming) = crossfade::gains(progress);
+                if let Some(next_deck) = &self.next_deck {
+                    next_deck.gain.set(outgoing);
+                }
+                self.deck.gain.set(incoming);
+            }
+            return;
+        }
+
+        let remaining = match (&self.crossfade, self.time_to_track_end()) {
+            (Some(crossfade), Some(remaining)) if crossfade.ready => {
+                if remaining > crossfade.duration {
+                    return;
+                }
+                remaining
+            }
+            _ => return,
+        };
+        let (Some(crossfade), Some(next_deck)) = (self.crossfade.take(), self.next_deck.as_mut())
+        else {
+            return;
+        };
+
+        info!("crossfading into {}", crossfade.uri);
+        next_deck.gain.set(0.0);
+        next_deck.player.play();
+        std::mem::swap(&mut self.deck, next_deck);
+        self.track_end = None;
+        self.loaded = SpotifyId::from_uri(&crossfade.uri)
+            .ok()
+            .map(|id| Loaded::Track(id, self.album_gain));
+        self.fade = Some(Fade {
+            uri: crossfade.uri,
+            start: now,
+            duration: remaining.max(Duration::from_millis(100)),
+        });
+        // the queue moves on to the next track, which keeps playing when it is loaded
+        self.events.send(Event::Player(PlayerEvent::FinishedTrack));
+    }
+
     pub async fn run_loop(&mut self) {
         let mut ui_refresh = time::interval(Duration::from_millis(400));
+        let mut fade_tick = time::interval(Duration::from_millis(50));
 
         loop {
             if self.session.is_invalid() {
@@ -110,7 +408,24 @@ impl Worker {
 
             tokio::select! {
                 cmd = self.commands.next() => match cmd {
+                    Some(WorkerCommand::Load(playable, ..)) if self.is_fading_in(&playable) => {
+                        debug!("{} is faded in already", playable);
+                    }
+                    Some(WorkerCommand::Load(Playable::LocalFile(file), start_playing, position_ms)) => {
+                        self.cancel_crossfade();
+                        if !self.local {
+                            self.deck.player.stop();
+                            self.local = true;
+                        }
+                        self.local_player.load(Source::File(file.path.clone()), start_playing, position_ms);
+                        self.loaded = Some(Loaded::LocalFile(file.path));
+                    }
                     Some(WorkerCommand::Load(playable, start_playing, position_ms)) => {
+                        self.cancel_crossfade();
+                        if self.local {
+                            self.local_player.stop();
+                            self.local = false;
+                        }
                         match SpotifyId::from_uri(&playable.uri()) {
                             Ok(id) => {
                                 info!("player loading track: {:?}", id);
@@ -118,7 +433,10 @@ impl Worker {
                                     warn!("track is not playable");
                                     self.events.send(Event::Player(PlayerEvent::FinishedTrack));
                                 } else {
-                                    self.player.load(id, start_playing, position_ms);
+                                    self.deck.player.load(id, start_playing, position_ms);
+                                    self.loaded = Some(Loaded::Track(id, self.album_gain));
+                                    self.audio_cache.update();
+                                    self.remember_file(id);
                                 }
                             }
                             Err(e) => {
@@ -127,17 +445,34 @@ impl Worker {
                             }
                         }
                     }
+                    Some(WorkerCommand::Play) if self.local => {
+                        self.local_player.play();
+                    }
                     Some(WorkerCommand::Play) => {
-                        self.player.play();
+                        self.deck.player.play();
+                    }
+                    Some(WorkerCommand::Pause) if self.local => {
+                        self.local_player.pause();
                     }
                     Some(WorkerCommand::Pause) => {
-                        self.player.pause();
+                        self.finish_fade();
+                        self.deck.player.pause();
+                    }
+                    Some(WorkerCommand::Stop) if self.local => {
+                        self.local_player.stop();
+                        self.loaded = None;
                     }
                     Some(WorkerCommand::Stop) => {
-                        self.player.stop();
+                        self.cancel_crossfade();
+                        self.deck.player.stop();
+                        self.loaded = None;
+                    }
+                    Some(WorkerCommand::Seek(pos)) if self.local => {
+                        self.local_player.seek(pos);
                     }
                     Some(WorkerCommand::Seek(pos)) => {
-                        self.player.seek(pos);
+                        self.finish_fade();
+                        self.deck.player.seek(pos);
                     }
                     Some(WorkerCommand::SetVolume(volume)) => {
                         self.mixer.set_volume(volume);
@@ -145,27 +480,71 @@ impl Worker {
                     Some(WorkerCommand::RequestToken(sender)) => {
                         self.token_task = self.get_token(sender);
                     }
-                    Some(WorkerCommand::Preload(playable)) => {
+                    Some(WorkerCommand::Preload(playable, crossfade)) => {
                         if let Ok(id) = SpotifyId::from_uri(&playable.uri()) {
-                            debug!("Preloading {:?}", id);
-                            self.player.preload(id);
+                            self.unload_next_track();
+                            self.remember_file(id);
+                            match crossfade {
+                                // the next deck is still busy with the previous track while fading
+                                Some(duration) if !self.local && self.fade.is_none() => {
+                                    debug!("Preloading {:?} to crossfade into it", id);
+                                    self.next_deck().player.load(id, false, 0);
+                                    self.crossfade = Some(Crossfade {
+                                        uri: playable.uri(),
+                                        duration,
+                                        ready: false,
+                                    });
+                                }
+                                _ => {
+                                    debug!("Preloading {:?}", id);
+                                    self.deck.player.preload(id);
+                                }
+                            }
+                        }
+                    }
+                    Some(WorkerCommand::SetNormalisation(enabled, position_ms)) => {
+                        if self.player_config.normalisation != enabled {
+                            info!("turning volume normalisation {}", if enabled { "on" } else { "off" });
+                            self.player_config.normalisation = enabled;
+                            self.recreate_players(position_ms);
+                        }
+                    }
+                    Some(WorkerCommand::SetDevice(device, position_ms)) => {
+                        info!("switching to output device {:?}", device);
+                        self.device = device;
+                        self.recreate_players(position_ms);
+                    }
+                    Some(WorkerCommand::SetAlbumGain(album_gain)) => {
+                        self.album_gain = album_gain;
+                        self.deck.player.set_auto_normalise_as_album(album_gain);
+                        if let Some(next_deck) = &self.next_deck {
+                            next_deck.player.set_auto_normalise_as_album(album_gain);
                         }
                     }
                     Some(WorkerCommand::Shutdown) => {
-                        self.player.stop();
+                        self.deck.player.stop();
+                        if let Some(next_deck) = &self.next_deck {
+                            next_deck.player.stop();
+                        }
+                        self.local_player.stop();
                         self.session.shutdown();
                     }
                     None => info!("empty stream")
                 },
-                event = self.player_events.next() => match event {
+                event = self.deck.events.next() => match event {
+                    // Events of the librespot player, i.e. it being stopped, are irrelevant
+                    // while a local file is played.
+                    Some(_) if self.local => {}
                     Some(LibrespotPlayerEvent::Playing {
                         play_request_id: _,
                         track_id: _,
                         position_ms,
-                        duration_ms: _,
+                        duration_ms,
                     }) => {
                         let position = Duration::from_millis(position_ms as u64);
                         let playback_start = SystemTime::now() - position;
+                        let remaining = duration_ms.saturating_sub(position_ms);
+                        self.track_end = Some((Instant::now(), Duration::from_millis(remaining as u64)));
                         self.events
                             .send(Event::Player(PlayerEvent::Playing(playback_start)));
                         self.active = true;
@@ -180,13 +559,18 @@ impl Worker {
                         self.events
                             .send(Event::Player(PlayerEvent::Paused(position)));
                         self.active = false;
+                        self.track_end = None;
                     }
                     Some(LibrespotPlayerEvent::Stopped { .. }) => {
                         self.events.send(Event::Player(PlayerEvent::Stopped));
                         self.active = false;
+                        self.track_end = None;
+                        self.audio_cache.update();
                     }
                     Some(LibrespotPlayerEvent::EndOfTrack { .. }) => {
                         self.events.send(Event::Player(PlayerEvent::FinishedTrack));
+                        self.track_end = None;
+                        self.audio_cache.update();
                     }
                     Some(LibrespotPlayerEvent::TimeToPreloadNextTrack { .. }) => {
                         self.events
@@ -198,6 +582,43 @@ impl Worker {
                     },
                     _ => {}
                 },
+                // Events of the next deck only matter while the next track is loaded on it, the
+                // previous track fading out on it ends on its own.
+                event = OptionFuture::from(self.next_deck.as_mut().map(|deck| deck.events.next()))
+                    .map(Option::flatten), if self.next_deck.is_some() => match event {
+                    Some(LibrespotPlayerEvent::Paused { .. }) => {
+                        if let Some(crossfade) = self.crossfade.as_mut() {
+                            crossfade.ready = true;
+                        }
+                    }
+                    Some(
+                        LibrespotPlayerEvent::Unavailable { .. }
+                        | LibrespotPlayerEvent::EndOfTrack { .. },
+                    ) if self.crossfade.is_some() => {
+                        warn!("Can't crossfade into the next track, it couldn't be loaded");
+                        self.crossfade = None;
+                    }
+                    None => {
+                        warn!("Librespot player event channel died, terminating worker");
+                        break
+                    },
+                    _ => {}
+                },
+                event = self.local_player_events.next() => match event {
+                    Some(_) if !self.local => {}
+                    Some(event) => {
+                        let (event, active) = local_player_event(event);
+                        self.events.send(event);
+                        self.active = active.unwrap_or(self.active);
+                    }
+                    None => {
+                        warn!("Local player event channel died, terminating worker");
+                        break
+                    }
+                },
+                _ = fade_tick.tick(), if self.crossfade.is_some() || self.fade.is_some() => {
+                    self.update_crossfade();
+                },
                 _ = ui_refresh.tick() => {
                     if self.active {
                         self.events.trigger();
@@ -211,3 +632,125 @@ impl Worker {
         }
     }
 }
+
+/// The event for `event` of the local player, and whether it is active afterwards if that changed.
+fn local_player_event(event: LocalPlayerEvent) -> (Event, Option<bool>) {
+    match event {
+        LocalPlayerEvent::Playing { position_ms } => {
+            let position = Duration::from_millis(position_ms as u64);
+            let playback_start = SystemTime::now() - position;
+            (
+                Event::Player(PlayerEvent::Playing(playback_start)),
+                Some(true),
+            )
+        }
+        LocalPlayerEvent::Paused { position_ms } => {
+            let position = Duration::from_millis(position_ms as u64);
+            (Event::Player(PlayerEvent::Paused(position)), Some(false))
+        }
+        LocalPlayerEvent::Stopped => (Event::Player(PlayerEvent::Stopped), Some(false)),
+        LocalPlayerEvent::EndOfTrack => (Event::Player(PlayerEvent::FinishedTrack), None),
+        LocalPlayerEvent::TimeToPreloadNextTrack => {
+            (Event::Queue(QueueEvent::PreloadTrackRequest), None)
+        }
+    }
+}
+
+/// Plays local files and tracks from the audio cache in place of the [Worker] while Spotify can't
+/// be reached.
+pub struct OfflineWorker {
+    events: EventManager,
+    commands: UnboundedReceiverStream<WorkerCommand>,
+    local_player: LocalPlayer,
+    local_player_events: UnboundedReceiverStream<LocalPlayerEvent>,
+    mixer: Box<dyn Mixer>,
+    audio_cache: AudioCache,
+    active: bool,
+}
+
+impl OfflineWorker {
+    pub(crate) fn new(
+        events: EventManager,
+        commands: mpsc::UnboundedReceiver<WorkerCommand>,
+        (local_player, local_player_events): (
+            LocalPlayer,
+            mpsc::UnboundedReceiver<LocalPlayerEvent>,
+        ),
+        mixer: Box<dyn Mixer>,
+        audio_cache: AudioCache,
+    ) -> OfflineWorker {
+        OfflineWorker {
+            events,
+            commands: UnboundedReceiverStream::new(commands),
+            local_player,
+            local_player_events: UnboundedReceiverStream::new(local_player_events),
+            mixer,
+            audio_cache,
+            active: false,
+        }
+    }
+
+    /// Load `playable` from the audio cache, or stop if it isn't cached.
+    fn load_cached(&self, playable: &Playable, start_playing: bool, position_ms: u32) {
+        let audio = playable
+            .id()
+            .ok_or_else(|| String::from("it has no id"))
+            .and_then(|id| self.audio_cache.audio(&id));
+        match audio {
+            Ok(audio) => {
+                let source = Source::Cached(playable.uri(), Box::new(audio));
+                self.local_player.load(source, start_playing, position_ms);
+            }
+            Err(e) => {
+                error!("can't play {} offline: {}", playable, e);
+                self.local_player.stop();
+            }
+        }
+    }
+
+    pub async fn run_loop(&mut self) {
+        let mut ui_refresh = time::interval(Duration::from_millis(400));
+
+        loop {
+            tokio::select! {
+                cmd = self.commands.next() => match cmd {
+                    Some(WorkerCommand::Load(Playable::LocalFile(file), start_playing, position_ms)) => {
+                        self.local_player.load(Source::File(file.path), start_playing, position_ms);
+                    }
+                    Some(WorkerCommand::Load(playable, start_playing, position_ms)) => {
+                        self.load_cached(&playable, start_playing, position_ms);
+                    }
+                    Some(WorkerCommand::Play) => self.local_player.play(),
+                    Some(WorkerCommand::Pause) => self.local_player.pause(),
+                    Some(WorkerCommand::Stop) => self.local_player.stop(),
+                    Some(WorkerCommand::Seek(pos)) => self.local_player.seek(pos),
+                    Some(WorkerCommand::SetVolume(volume)) => self.mixer.set_volume(volume),
+                    Some(WorkerCommand::RequestToken(sender)) => {
+                        sender.send(None).ok();
+                    }
+                    Some(WorkerCommand::Shutdown) | None => {
+                        self.local_player.stop();
+                        break;
+                    }
+                    Some(cmd) => debug!("ignoring {:?} while offline", cmd),
+                },
+                event = self.local_player_events.next() => match event {
+                    Some(event) => {
+                        let (event, active) = local_player_event(event);
+                        self.events.send(event);
+                        self.active = active.unwrap_or(self.active);
+                    }
+                    None => {
+                        warn!("Local player event channel died, terminating worker");
+                        break
+                    }
+                },
+                _ = ui_refresh.tick() => {
+                    if self.active {
+                        self.events.trigger();
+                    }
+                }
+            }
+        }
+    }
+}
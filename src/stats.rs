@@ -0,0 +1,240 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{Local, NaiveDate};
+
+use crate::command::StatsWindow;
+use crate::model::album::Album;
+use crate::model::artist::Artist;
+use crate::model::play::Play;
+use crate::model::playable::Playable;
+use crate::model::ranked::Ranked;
+use crate::traits::ListItem;
+
+/// How many entries are kept in each of the top lists.
+const TOP_ENTRIES: usize = 100;
+
+/// Listening statistics computed from the listening history.
+#[derive(Default)]
+pub struct Stats {
+    /// The amount of plays within the window.
+    pub plays: usize,
+    /// The total listening time within the window.
+    pub played: Duration,
+    /// The listening time of every day in the window, oldest first.
+    pub days: Vec<(NaiveDate, Duration)>,
+    pub artists: Vec<Ranked<Artist>>,
+    pub albums: Vec<Ranked<Album>>,
+    pub tracks: Vec<Ranked<Playable>>,
+}
+
+/// Accumulates the plays of items that are identified by a key, in order of first appearance.
+struct Ranking<I: ListItem + Clone> {
+    entries: Vec<Ranked<I>>,
+    indexes: HashMap<String, usize>,
+}
+
+impl<I: ListItem + Clone> Ranking<I> {
+    fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            indexes: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, key: String, item: impl FnOnce() -> I, played: Duration) {
+        let entries = &mut self.entries;
+        let index = *self.indexes.entry(key).or_insert_with(|| {
+            entries.push(Ranked {
+                item: item(),
+                plays: 0,
+                played: Duration::ZERO,
+            });
+            entries.len() - 1
+        });
+        self.entries[index].plays += 1;
+        self.entries[index].played += played;
+    }
+
+    /// The entries with the most plays first, using the listening time to break ties.
+    fn top(mut self) -> Vec<Ranked<I>> {
+        // stable, so equally ranked entries stay in order of their first appearance
+        self.entries
+            .sort_by_key(|entry| Reverse((entry.plays, entry.played)));
+        self.entries.truncate(TOP_ENTRIES);
+        self.entries
+    }
+}
+
+impl Stats {
+    /// Compute the statistics of the plays in `history` that happened during `window`, which
+    /// ends with `today`. Days are determined in the local timezone.
+    pub fn new(history: &[Play], window: StatsWindow, today: NaiveDate) -> Self {
+        let play_date = |play: &Play| play.played_at.with_timezone(&Local).date_naive();
+        let first_day = match window {
+            StatsWindow::Week => today - chrono::Duration::days(6),
+            StatsWindow::Month => today - chrono::Duration::days(29),
+            StatsWindow::All => history.iter().map(play_date).min().unwrap_or(today),
+        };
+
+        let mut days: Vec<(NaiveDate, Duration)> = first_day
+            .iter_days()
+            .take_while(|day| *day <= today)
+            .map(|day| (day, Duration::ZERO))
+            .collect();
+        let mut stats = Self::default();
+        let mut artists = Ranking::new();
+        let mut albums = Ranking::new();
+        let mut tracks = Ranking::new();
+
+        // the history is most recent first, rank in chronological order instead
+        for play in history.iter().rev() {
+            let date = play_date(play);
+            if date < first_day || date > today {
+                continue;
+            }
+
+            let played = Duration::from_millis(play.played as u64);
+            stats.plays += 1;
+            stats.played += played;
+            if let Some(day) = days.get_mut((date - first_day).num_days() as usize) {
+                day.1 += played;
+            }
+
+            tracks.add(play.playable.uri(), || play.playable.clone(), played);
+            if let Playable::Track(track) = &play.playable {
+                for (id, name) in track.artist_ids.iter().zip(track.artists.iter()) {
+                    artists.add(id.clone(), || Artist::new(id.clone(), name.clone()), played);
+                }
+                if let Some(album) = Album::from_track(track) {
+                    albums.add(album.id.clone().unwrap_or_default(), || album, played);
+                }
+            }
+        }
+
+        stats.days = days;
+        stats.artists = artists.top();
+        stats.albums = albums.top();
+        stats.tracks = tracks.top();
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::model::track::Track;
+
+    fn track(id: &str, artists: &[(&str, &str)], album: Option<(&str, &str)>) -> Playable {
+        Playable::Track(Track {
+            id: Some(id.to_string()),
+            artists: artists.iter().map(|(_, name)| name.to_string()).collect(),
+            artist_ids: artists.iter().map(|(id, _)| id.to_string()).collect(),
+            album: album.map(|(_, title)| title.to_string()),
+            album_id: album.map(|(id, _)| id.to_string()),
+            ..Track::test(id)
+        })
+    }
+
+    fn play(playable: &Playable, day: NaiveDate, played_secs: u32) -> Play {
+        let played_at = Local
+            .from_local_datetime(&day.and_hms_opt(12, 0, 0).unwrap())
+            .unwrap()
+            .with_timezone(&Utc);
+        Play {
+            playable: playable.clone(),
+            played_at,
+            played: played_secs * 1000,
+        }
+    }
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 3, 31).unwrap()
+    }
+
+    fn days_ago(days: i64) -> NaiveDate {
+        today() - chrono::Duration::days(days)
+    }
+
+    #[test]
+    fn windows_limit_plays() {
+        let a = track("a", &[("x", "X")], None);
+        // most recent first, like the history in the library
+        let history = vec![
+            play(&a, days_ago(0), 60),
+            play(&a, days_ago(6), 60),
+            play(&a, days_ago(7), 60),
+            play(&a, days_ago(29), 60),
+            play(&a, days_ago(30), 60),
+        ];
+
+        let week = Stats::new(&history, StatsWindow::Week, today());
+        assert_eq!(week.plays, 2);
+        assert_eq!(week.played, Duration::from_secs(120));
+        assert_eq!(week.days.len(), 7);
+        assert_eq!(week.days[0], (days_ago(6), Duration::from_secs(60)));
+        assert_eq!(week.days[6], (today(), Duration::from_secs(60)));
+
+        let month = Stats::new(&history, StatsWindow::Month, today());
+        assert_eq!(month.plays, 4);
+        assert_eq!(month.days.len(), 30);
+
+        let all = Stats::new(&history, StatsWindow::All, today());
+        assert_eq!(all.plays, 5);
+        assert_eq!(all.days.len(), 31);
+        assert_eq!(all.days[0].0, days_ago(30));
+    }
+
+    #[test]
+    fn ranks_by_plays_then_time() {
+        let a = track("a", &[("x", "X")], Some(("p", "P")));
+        let b = track("b", &[("x", "X"), ("y", "Y")], Some(("q", "Q")));
+        let c = track("c", &[("z", "Z")], None);
+        let history = vec![
+            play(&c, today(), 200),
+            play(&b, today(), 10),
+            play(&b, today(), 10),
+            play(&a, today(), 100),
+        ];
+
+        let stats = Stats::new(&history, StatsWindow::Week, today());
+        let tracks: Vec<(String, usize)> = stats
+            .tracks
+            .iter()
+            .map(|r| (r.item.uri(), r.plays))
+            .collect();
+        assert_eq!(
+            tracks,
+            vec![
+                ("spotify:track:b".to_string(), 2),
+                ("spotify:track:c".to_string(), 1),
+                ("spotify:track:a".to_string(), 1),
+            ]
+        );
+
+        let artists: Vec<(&str, usize, u64)> = stats
+            .artists
+            .iter()
+            .map(|r| (r.item.name.as_str(), r.plays, r.played.as_secs()))
+            .collect();
+        assert_eq!(artists, vec![("X", 3, 120), ("Y", 2, 20), ("Z", 1, 200)]);
+
+        let albums: Vec<(Option<&str>, usize)> = stats
+            .albums
+            .iter()
+            .map(|r| (r.item.id.as_deref(), r.plays))
+            .collect();
+        assert_eq!(albums, vec![(Some("q"), 2), (Some("p"), 1)]);
+    }
+
+    #[test]
+    fn empty_history() {
+        let stats = Stats::new(&[], StatsWindow::All, today());
+        assert_eq!(stats.plays, 0);
+        assert_eq!(stats.days, vec![(today(), Duration::ZERO)]);
+        assert!(stats.tracks.is_empty());
+    }
+}
@@ -0,0 +1,321 @@
+//! Changing the playback speed without changing the pitch.
+//!
+//! The audio is stretched with WSOLA (waveform similarity overlap-add): frames are taken from the
+//! input at a distance scaled by the speed and overlapped at a fixed distance in the output. Each
+//! frame is shifted slightly to where it resembles the continuation of the previous frame the
+//! most, which avoids the phasing of plain overlap-add.
+
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use librespot_playback::audio_backend::{Sink, SinkResult};
+use librespot_playback::convert::Converter;
+use librespot_playback::decoder::AudioPacket;
+use librespot_playback::NUM_CHANNELS;
+
+pub const MIN_SPEED: f64 = 0.5;
+pub const MAX_SPEED: f64 = 3.0;
+
+/// The length of a frame in samples per channel, about 23 ms.
+const FRAME: usize = 1024;
+/// The distance between frames in the output.
+const HOP: usize = FRAME / 2;
+/// How far a frame is shifted at most to match the previous one.
+const TOLERANCE: usize = 256;
+
+/// The playback speed, shared by the player and every sink.
+#[derive(Clone)]
+pub struct Speed(Arc<AtomicU64>);
+
+impl Default for Speed {
+    fn default() -> Self {
+        Self(Arc::new(AtomicU64::new(1.0_f64.to_bits())))
+    }
+}
+
+impl Speed {
+    /// Set the speed, limited to [MIN_SPEED] and [MAX_SPEED].
+    pub fn set(&self, speed: f64) {
+        self.0.store(
+            speed.clamp(MIN_SPEED, MAX_SPEED).to_bits(),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Whether `speed` is the normal speed, which doesn't need stretching.
+pub fn is_normal(speed: f64) -> bool {
+    (speed - 1.0).abs() < 0.001
+}
+
+/// Stretches interleaved audio by the inverse of the speed.
+pub struct TimeStretcher {
+    channels: usize,
+    speed: f64,
+    window: Vec<f64>,
+    /// The input that may still be used, interleaved.
+    input: Vec<f64>,
+    /// Where the next frame starts before it is shifted, in samples per channel into `input`.
+    position: f64,
+    /// Where the continuation of the previous frame starts in `input`, if there was one.
+    previous: Option<usize>,
+    /// The second half of the previous frame, which the first half of the next one is added to.
+    overlap: Vec<f64>,
+}
+
+impl TimeStretcher {
+    pub fn new(channels: usize, speed: f64) -> Self {
+        // a periodic Hann window, which sums up to 1 when overlapping by half
+        let window = (0..FRAME)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f64 / FRAME as f64).cos())
+            .collect();
+        Self {
+            channels,
+            speed,
+            window,
+            input: Vec::new(),
+            position: 0.0,
+            previous: None,
+            overlap: vec![0.0; HOP * channels],
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    /// Add `samples` to the input and return the output that is complete.
+    pub fn process(&mut self, samples: &[f64]) -> Vec<f64> {
+        self.input.extend_from_slice(samples);
+        let channels = self.channels;
+        let mut output = Vec::new();
+
+        while self.input.len() / channels >= self.position as usize + TOLERANCE + FRAME {
+            let nominal = self.position as usize;
+            let start = match self.previous {
+                Some(previous) => self.best_match(nominal, previous),
+                None => nominal,
+            };
+
+            for i in 0..HOP {
+                for channel in 0..channels {
+                    let sample = self.input[(start + i) * channels + channel] * self.window[i];
+                    output.push(sample + self.overlap[i * channels + channel]);
+                }
+            }
+            for i in 0..HOP {
+                for channel in 0..channels {
+                    self.overlap[i * channels + channel] =
+                        self.input[(start + HOP + i) * channels + channel] * self.window[HOP + i];
+                }
+            }
+            self.previous = Some(start + HOP);
+            self.position += HOP as f64 * self.speed;
+
+            // drop the input that can't be used anymore
+            let used = (self.position as usize)
+                .saturating_sub(TOLERANCE)
+                .min(start + HOP);
+            self.input.drain(..used * channels);
+            self.position -= used as f64;
+            self.previous = Some(start + HOP - used);
+        }
+        output
+    }
+
+    /// Return the rest of the input, continuing the output smoothly.
+    pub fn flush(mut self) -> Vec<f64> {
+        let channels = self.channels;
+        let Some(previous) = self.previous else {
+            let start = (self.position as usize * channels).min(self.input.len());
+            return self.input.split_off(start);
+        };
+
+        let mut output = self.input.split_off(previous * channels);
+        for (index, sample) in output.iter_mut().take(HOP * channels).enumerate() {
+            *sample = *sample * self.window[index / channels] + self.overlap[index];
+        }
+        output
+    }
+
+    /// The start of the frame within the tolerance around `nominal` that is the most similar to
+    /// the continuation of the previous frame at `previous`.
+    fn best_match(&self, nominal: usize, previous: usize) -> usize {
+        // the channels are mixed and every second sample is skipped, which is accurate enough
+        let mono = |start: usize| {
+            (0..HOP).step_by(2).map(move |i| {
+                let index = (start + i) * self.channels;
+                self.input[index..index + self.channels].iter().sum::<f64>()
+            })
+        };
+        let template: Vec<f64> = mono(previous).collect();
+
+        let similarity = |start: usize| {
+            let (product, energy) = mono(start).zip(&template).fold(
+                (0.0, 0.0),
+                |(product, energy), (sample, expected)| {
+                    (product + sample * expected, energy + sample * sample)
+                },
+            );
+            product / energy.sqrt().max(f64::EPSILON)
+        };
+
+        (nominal.saturating_sub(TOLERANCE)..=nominal + TOLERANCE)
+            .map(|start| (start, similarity(start)))
+            .fold((nominal, f64::MIN), |best, candidate| {
+                if candidate.1 > best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .0
+    }
+}
+
+/// A sink that plays at the [Speed] before writing to another sink.
+pub struct StretchSink {
+    sink: Box<dyn Sink>,
+    speed: Speed,
+    stretcher: Option<TimeStretcher>,
+}
+
+impl StretchSink {
+    pub fn new(sink: Box<dyn Sink>, speed: Speed) -> Self {
+        Self {
+            sink,
+            speed,
+            stretcher: None,
+        }
+    }
+}
+
+impl Sink for StretchSink {
+    fn start(&mut self) -> SinkResult<()> {
+        self.sink.start()
+    }
+
+    fn stop(&mut self) -> SinkResult<()> {
+        self.stretcher = None;
+        self.sink.stop()
+    }
+
+    fn write(&mut self, packet: AudioPacket, converter: &mut Converter) -> SinkResult<()> {
+        let speed = self.speed.get();
+        let samples = match packet {
+            AudioPacket::Samples(samples) => samples,
+            packet => return self.sink.write(packet, converter),
+        };
+
+        if is_normal(speed) {
+            if let Some(stretcher) = self.stretcher.take() {
+                self.sink
+                    .write(AudioPacket::Samples(stretcher.flush()), converter)?;
+            }
+            return self.sink.write(AudioPacket::Samples(samples), converter);
+        }
+
+        let stretcher = self
+            .stretcher
+            .get_or_insert_with(|| TimeStretcher::new(NUM_CHANNELS as usize, speed));
+        stretcher.set_speed(speed);
+        let output = stretcher.process(&samples);
+        if output.is_empty() {
+            Ok(())
+        } else {
+            self.sink.write(AudioPacket::Samples(output), converter)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: usize = 44_100;
+
+    /// `seconds` of a stereo sine wave.
+    fn sine(freq: f64, seconds: f64) -> Vec<f64> {
+        (0..(RATE as f64 * seconds) as usize)
+            .flat_map(|n| {
+                let sample = 0.5 * (2.0 * PI * freq * n as f64 / RATE as f64).sin();
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    /// Stretch `samples` in packets of the size librespot uses.
+    fn stretch(samples: &[f64], speed: f64) -> Vec<f64> {
+        let mut stretcher = TimeStretcher::new(2, speed);
+        let mut output: Vec<f64> = samples
+            .chunks(2 * 1024)
+            .flat_map(|packet| stretcher.process(packet))
+            .collect();
+        output.extend(stretcher.flush());
+        output
+    }
+
+    /// The frequency of the left channel of a sine wave, from its zero crossings.
+    fn frequency(samples: &[f64]) -> f64 {
+        let left: Vec<f64> = samples.iter().step_by(2).copied().collect();
+        let crossings = left
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        crossings as f64 * RATE as f64 / left.len() as f64
+    }
+
+    #[test]
+    fn changes_duration() {
+        let input = sine(440.0, 1.0);
+        for speed in [MIN_SPEED, 1.5, MAX_SPEED] {
+            let output = stretch(&input, speed);
+            let expected = input.len() as f64 / speed;
+            assert!(
+                (output.len() as f64 - expected).abs() < (2 * FRAME) as f64 * 2.0,
+                "{speed}x: {} samples instead of {expected}",
+                output.len()
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_pitch() {
+        let input = sine(440.0, 1.0);
+        for speed in [MIN_SPEED, 1.5, MAX_SPEED] {
+            let output = stretch(&input, speed);
+            // the start fades in from the first window
+            let frequency = frequency(&output[HOP * 2..]);
+            assert!(
+                (frequency - 440.0).abs() < 440.0 * 0.02,
+                "{speed}x: {frequency} Hz"
+            );
+        }
+    }
+
+    #[test]
+    fn smooth_output() {
+        let output = stretch(&sine(220.0, 0.5), 1.25);
+        // no jumps between frames, which a sine of this frequency never has
+        let max_step = 0.5 * 2.0 * PI * 220.0 / RATE as f64;
+        let steps = output[HOP * 2..].iter().step_by(2).collect::<Vec<_>>();
+        for pair in steps.windows(2) {
+            assert!((pair[1] - pair[0]).abs() < max_step * 1.5);
+        }
+    }
+
+    #[test]
+    fn limits_speed() {
+        let speed = Speed::default();
+        assert_eq!(speed.get(), 1.0);
+        speed.set(10.0);
+        assert_eq!(speed.get(), MAX_SPEED);
+        speed.set(0.0);
+        assert_eq!(speed.get(), MIN_SPEED);
+    }
+}
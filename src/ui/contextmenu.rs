@@ -3,14 +3,17 @@ use std::sync::Arc;
 use cursive::view::{Margins, ViewWrapper};
 use cursive::views::{Dialog, NamedView, ScrollView, SelectView};
 use cursive::Cursive;
+use rspotify::model::Device as ConnectDevice;
 
 use crate::commands::CommandResult;
+use crate::devices;
 use crate::ext_traits::SelectViewExt;
 use crate::library::Library;
 use crate::model::artist::Artist;
 use crate::model::playable::Playable;
 use crate::model::playlist::Playlist;
 use crate::model::track::Track;
+use crate::outputs::Device;
 use crate::queue::Queue;
 #[cfg(feature = "share_clipboard")]
 use crate::sharing::write_share;
@@ -37,6 +40,14 @@ pub struct SelectArtistActionMenu {
     dialog: Modal<Dialog>,
 }
 
+pub struct SelectOutputMenu {
+    dialog: Modal<Dialog>,
+}
+
+pub struct SelectDeviceMenu {
+    dialog: Modal<Dialog>,
+}
+
 enum ContextMenuAction {
     ShowItem(Box<dyn ListItem>),
     SelectArtist(Vec<Artist>),
@@ -347,6 +358,83 @@ impl ContextMenu {
         }
         .with_name("contextmenu")
     }
+
+    pub fn select_output_dialog(
+        spotify: Spotify,
+        devices: Vec<Device>,
+    ) -> NamedView<SelectOutputMenu> {
+        let mut output_select = SelectView::<Option<String>>::new();
+        output_select.add_item("Default", None);
+        for device in devices {
+            output_select.add_item(device.label().to_string(), Some(device.name));
+        }
+
+        let current = spotify.output_device();
+        let current = output_select
+            .iter()
+            .position(|(_, device)| *device == current);
+        if let Some(index) = current {
+            output_select.set_selection(index);
+        }
+
+        output_select.set_on_submit(move |s, device: &Option<String>| {
+            spotify.set_output_device(device.clone());
+            s.pop_layer();
+        });
+
+        let dialog = Dialog::new()
+            .title("Select output device")
+            .dismiss_button("Close")
+            .padding(Margins::lrtb(1, 1, 1, 0))
+            .content(ScrollView::new(output_select.with_name("output_select")));
+
+        SelectOutputMenu {
+            dialog: Modal::new_ext(dialog),
+        }
+        .with_name("selectoutput")
+    }
+
+    pub fn select_device_dialog(
+        spotify: Spotify,
+        devices: Vec<ConnectDevice>,
+    ) -> NamedView<SelectDeviceMenu> {
+        let mut device_select = SelectView::<Option<ConnectDevice>>::new();
+        device_select.add_item("ncspot (this device)", None);
+        // devices without an id can't be controlled through the Web API
+        for device in devices.into_iter().filter(|device| device.id.is_some()) {
+            device_select.add_item(devices::label(&device), Some(device));
+        }
+
+        let current = spotify.remote_device.get().and_then(|device| device.id);
+        let current = device_select
+            .iter()
+            .position(|(_, device)| device.as_ref().and_then(|d| d.id.clone()) == current);
+        if let Some(index) = current {
+            device_select.set_selection(index);
+        }
+
+        device_select.set_on_submit(move |s, device: &Option<ConnectDevice>| {
+            // transferring playback goes through the Web API
+            let (spotify, device) = (spotify.clone(), device.clone());
+            std::thread::spawn(move || {
+                if let Err(e) = devices::control(&spotify, device) {
+                    log::error!("{e}");
+                }
+            });
+            s.pop_layer();
+        });
+
+        let dialog = Dialog::new()
+            .title("Select device to play on")
+            .dismiss_button("Close")
+            .padding(Margins::lrtb(1, 1, 1, 0))
+            .content(ScrollView::new(device_select.with_name("device_select")));
+
+        SelectDeviceMenu {
+            dialog: Modal::new_ext(dialog),
+        }
+        .with_name("selectdevice")
+    }
 }
 
 impl ViewExt for AddToPlaylistMenu {
@@ -375,6 +463,18 @@ impl ViewExt for SelectArtistActionMenu {
     }
 }
 
+impl ViewExt for SelectOutputMenu {
+    fn on_command(&mut self, s: &mut Cursive, cmd: &Command) -> Result<CommandResult, String> {
+        handle_move_command::<Option<String>>(&mut self.dialog, s, cmd, "output_select")
+    }
+}
+
+impl ViewExt for SelectDeviceMenu {
+    fn on_command(&mut self, s: &mut Cursive, cmd: &Command) -> Result<CommandResult, String> {
+        handle_move_command::<Option<ConnectDevice>>(&mut self.dialog, s, cmd, "device_select")
+    }
+}
+
 fn handle_move_command<T: 'static>(
     sel: &mut Modal<Dialog>,
     s: &mut Cursive,
@@ -410,3 +510,11 @@ impl ViewWrapper for SelectArtistMenu {
 impl ViewWrapper for SelectArtistActionMenu {
     wrap_impl!(self.dialog: Modal<Dialog>);
 }
+
+impl ViewWrapper for SelectOutputMenu {
+    wrap_impl!(self.dialog: Modal<Dialog>);
+}
+
+impl ViewWrapper for SelectDeviceMenu {
+    wrap_impl!(self.dialog: Modal<Dialog>);
+}
@@ -59,6 +59,11 @@ impl LibraryView {
                 LibraryTab::Browse => {
                     tabview.add_tab("browse", BrowseView::new(queue.clone(), library.clone()))
                 }
+                LibraryTab::History => tabview.add_tab(
+                    "history",
+                    ListView::new(library.history.clone(), queue.clone(), library.clone())
+                        .with_title("History"),
+                ),
             }
         }
 
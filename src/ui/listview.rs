@@ -15,17 +15,11 @@ use crate::command::{Command, GotoMode, InsertSource, JumpMode, MoveAmount, Move
 use crate::commands::CommandResult;
 use crate::ext_traits::CursiveExt;
 use crate::library::Library;
-use crate::model::album::Album;
-use crate::model::artist::Artist;
-use crate::model::episode::Episode;
 use crate::model::playable::Playable;
-use crate::model::playlist::Playlist;
-use crate::model::show::Show;
 use crate::model::track::Track;
 use crate::queue::Queue;
 #[cfg(feature = "share_clipboard")]
 use crate::sharing::{read_share, write_share};
-use crate::spotify::UriType;
 use crate::traits::{IntoBoxedViewExt, ListItem, ViewExt};
 use crate::ui::album::AlbumView;
 use crate::ui::artist::ArtistView;
@@ -715,32 +709,7 @@ impl<I: ListItem + Clone> ViewExt for ListView<I> {
                 let spotify = self.queue.get_spotify();
 
                 if let Some(url) = url {
-                    let target: Option<Box<dyn ListItem>> = match url.uri_type {
-                        UriType::Track => spotify
-                            .api
-                            .track(&url.id)
-                            .map(|track| Track::from(&track).as_listitem()),
-                        UriType::Album => spotify
-                            .api
-                            .album(&url.id)
-                            .map(|album| Album::from(&album).as_listitem()),
-                        UriType::Playlist => spotify
-                            .api
-                            .playlist(&url.id)
-                            .map(|playlist| Playlist::from(&playlist).as_listitem()),
-                        UriType::Artist => spotify
-                            .api
-                            .artist(&url.id)
-                            .map(|artist| Artist::from(&artist).as_listitem()),
-                        UriType::Episode => spotify
-                            .api
-                            .episode(&url.id)
-                            .map(|episode| Episode::from(&episode).as_listitem()),
-                        UriType::Show => spotify
-                            .api
-                            .get_show(&url.id)
-                            .map(|show| Show::from(&show).as_listitem()),
-                    };
+                    let target = url.resolve(&spotify.api);
 
                     let queue = self.queue.clone();
                     let library = self.library.clone();
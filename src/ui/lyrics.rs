@@ -0,0 +1,270 @@
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use cursive::align::HAlign;
+use cursive::event::{Event, EventResult, MouseButton, MouseEvent};
+use cursive::theme::{ColorStyle, ColorType};
+use cursive::traits::View;
+use cursive::{Cursive, Printer, Vec2};
+use unicode_width::UnicodeWidthStr;
+
+use crate::command::{Command, MoveAmount, MoveMode};
+use crate::commands::CommandResult;
+use crate::lyrics::{Lyrics, LyricsProvider};
+use crate::model::playable::Playable;
+use crate::queue::Queue;
+use crate::traits::ViewExt;
+
+/// The result of looking up the lyrics of an item.
+enum LyricsState {
+    Loading,
+    Missing,
+    Found { provider: String, lyrics: Lyrics },
+}
+
+pub struct LyricsView {
+    queue: Arc<Queue>,
+    providers: Arc<Vec<Box<dyn LyricsProvider>>>,
+    /// The URI of the item the lyrics are shown for, and its lyrics.
+    lyrics: Arc<RwLock<Option<(String, LyricsState)>>>,
+    /// The line selected by the user. Playback isn't followed while a line is selected.
+    selected: Option<usize>,
+    last_size: Vec2,
+}
+
+impl LyricsView {
+    pub fn new(queue: Arc<Queue>, providers: Vec<Box<dyn LyricsProvider>>) -> Self {
+        Self {
+            queue,
+            providers: Arc::new(providers),
+            lyrics: Arc::new(RwLock::new(None)),
+            selected: None,
+            last_size: Vec2::zero(),
+        }
+    }
+
+    /// Look up the lyrics of `playable` in the background, trying the providers in order.
+    fn load(&mut self, playable: Playable) {
+        let uri = playable.uri();
+        *self.lyrics.write().unwrap() = Some((uri.clone(), LyricsState::Loading));
+        self.selected = None;
+
+        let providers = self.providers.clone();
+        let lyrics = self.lyrics.clone();
+        thread::spawn(move || {
+            let state = providers
+                .iter()
+                .find_map(|provider| {
+                    provider.lyrics(&playable).map(|lyrics| LyricsState::Found {
+                        provider: provider.name().to_string(),
+                        lyrics,
+                    })
+                })
+                .unwrap_or(LyricsState::Missing);
+
+            // the current item may have changed in the meantime
+            let mut lyrics = lyrics.write().unwrap();
+            if lyrics.as_ref().map(|(current, _)| current) == Some(&uri) {
+                *lyrics = Some((uri, state));
+            }
+        });
+    }
+
+    /// The line that is sung right now.
+    fn current_line(&self, lyrics: &Lyrics) -> Option<usize> {
+        lyrics.current_line(self.queue.get_spotify().get_current_progress())
+    }
+
+    /// Move the selection by `delta` lines, starting at the current line.
+    fn move_selection(&mut self, delta: i32) {
+        if let Some((_, LyricsState::Found { lyrics, .. })) = self.lyrics.read().unwrap().as_ref() {
+            let last = lyrics.lines.len().saturating_sub(1) as i32;
+            let from = self
+                .selected
+                .or_else(|| self.current_line(lyrics))
+                .unwrap_or(0) as i32;
+            self.selected = Some((from + delta).clamp(0, last) as usize);
+        }
+    }
+
+    /// Seek to the start of the selected line and follow playback again.
+    /// The time of the selected line in milliseconds, None without a selection.
+    fn selected_time(&self) -> Option<u32> {
+        match self.lyrics.read().unwrap().as_ref() {
+            Some((_, LyricsState::Found { lyrics, .. })) => self
+                .selected
+                .and_then(|index| lyrics.lines.get(index))
+                .map(|line| line.time.as_millis() as u32),
+            _ => None,
+        }
+    }
+
+    /// Seek to the selected line and follow playback again. Returns false without a selection.
+    fn seek_to_selected(&mut self) -> bool {
+        match self.selected_time() {
+            Some(time) => {
+                self.queue.get_spotify().seek(time);
+                self.selected = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The index of the first line that is shown, so that `focus` is centered vertically.
+    fn first_visible_line(&self, focus: usize) -> usize {
+        focus.saturating_sub(self.last_size.y / 2)
+    }
+
+    fn draw_message(&self, printer: &Printer<'_, '_>, message: &str) {
+        let offset = HAlign::Center.get_offset(message.width(), printer.size.x);
+        printer.with_color(ColorStyle::secondary(), |printer| {
+            printer.print((offset, printer.size.y / 2), message);
+        });
+    }
+}
+
+impl View for LyricsView {
+    fn draw(&self, printer: &Printer<'_, '_>) {
+        let lyrics = self.lyrics.read().unwrap();
+        let lyrics = match lyrics.as_ref() {
+            None => return self.draw_message(printer, "Nothing is playing"),
+            Some((_, LyricsState::Loading)) => {
+                return self.draw_message(printer, "Looking for lyrics...")
+            }
+            Some((_, LyricsState::Missing)) => {
+                return self.draw_message(printer, "No lyrics found")
+            }
+            Some((_, LyricsState::Found { lyrics, .. })) => lyrics,
+        };
+
+        let current = self.current_line(lyrics);
+        let first = self.first_visible_line(self.selected.or(current).unwrap_or(0));
+        let playing = ColorStyle::new(
+            ColorType::Color(*printer.theme.palette.custom("playing").unwrap()),
+            ColorType::Color(*printer.theme.palette.custom("playing_bg").unwrap()),
+        );
+
+        for (y, (index, line)) in lyrics
+            .lines
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(printer.size.y)
+            .enumerate()
+        {
+            let style = if self.selected == Some(index) {
+                ColorStyle::highlight()
+            } else if current == Some(index) {
+                playing
+            } else {
+                ColorStyle::primary()
+            };
+            let text = if line.text.is_empty() {
+                "♪"
+            } else {
+                &line.text
+            };
+            let offset = HAlign::Center.get_offset(text.width(), printer.size.x);
+            printer.with_color(style, |printer| {
+                printer.print((offset, y), text);
+            });
+        }
+    }
+
+    fn layout(&mut self, size: Vec2) {
+        self.last_size = size;
+
+        let current = self.queue.get_current();
+        let shown = self
+            .lyrics
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|(uri, _)| uri.clone());
+        match current {
+            Some(playable) if shown.as_ref() != Some(&playable.uri()) => self.load(playable),
+            None if shown.is_some() => {
+                *self.lyrics.write().unwrap() = None;
+                self.selected = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn on_event(&mut self, event: Event) -> EventResult {
+        match event {
+            Event::Mouse {
+                event: MouseEvent::WheelUp,
+                ..
+            } => self.move_selection(-1),
+            Event::Mouse {
+                event: MouseEvent::WheelDown,
+                ..
+            } => self.move_selection(1),
+            Event::Mouse {
+                offset,
+                position,
+                event: MouseEvent::Press(MouseButton::Left),
+            } => {
+                let lyrics = self.lyrics.read().unwrap();
+                let clicked = match (lyrics.as_ref(), position.checked_sub(offset)) {
+                    (Some((_, LyricsState::Found { lyrics, .. })), Some(position)) => {
+                        let focus = self.selected.or_else(|| self.current_line(lyrics));
+                        let index = self.first_visible_line(focus.unwrap_or(0)) + position.y;
+                        (index < lyrics.lines.len()).then_some(index)
+                    }
+                    _ => None,
+                };
+                drop(lyrics);
+
+                // clicking the selected line seeks there, like pressing enter
+                if clicked.is_some() && clicked == self.selected {
+                    self.seek_to_selected();
+                } else if clicked.is_some() {
+                    self.selected = clicked;
+                }
+            }
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed(None)
+    }
+}
+
+impl ViewExt for LyricsView {
+    fn title(&self) -> String {
+        "Lyrics".to_string()
+    }
+
+    fn title_sub(&self) -> String {
+        match self.lyrics.read().unwrap().as_ref() {
+            Some((_, LyricsState::Found { provider, .. })) => format!("from {provider}"),
+            _ => "".to_string(),
+        }
+    }
+
+    fn on_command(&mut self, _s: &mut Cursive, cmd: &Command) -> Result<CommandResult, String> {
+        match cmd {
+            // both jump to the selected line, and act as usual without one
+            Command::Seek(_) | Command::Play => match self.seek_to_selected() {
+                true => Ok(CommandResult::Consumed(None)),
+                false => Ok(CommandResult::Ignored),
+            },
+            Command::Move(MoveMode::Playing, _) => {
+                self.selected = None;
+                Ok(CommandResult::Consumed(None))
+            }
+            Command::Move(mode @ (MoveMode::Up | MoveMode::Down), amount) => {
+                let direction = if let MoveMode::Up = mode { -1 } else { 1 };
+                let delta = match amount {
+                    MoveAmount::Integer(amount) => *amount,
+                    MoveAmount::Float(scale) => (self.last_size.y as f32 * scale) as i32,
+                    MoveAmount::Extreme => i32::MAX / 2,
+                };
+                self.move_selection(direction * delta);
+                Ok(CommandResult::Consumed(None))
+            }
+            _ => Ok(CommandResult::Ignored),
+        }
+    }
+}
@@ -9,6 +9,7 @@ pub mod help;
 pub mod layout;
 pub mod library;
 pub mod listview;
+pub mod lyrics;
 pub mod modal;
 pub mod pagination;
 pub mod playlist;
@@ -17,6 +18,7 @@ pub mod queue;
 pub mod search;
 pub mod search_results;
 pub mod show;
+pub mod stats;
 pub mod statusbar;
 pub mod tabview;
 
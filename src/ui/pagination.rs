@@ -122,6 +122,11 @@ impl<I: ListItem + Clone> Pagination<I> {
         *self.callback.write().unwrap() = Some(callback);
     }
 
+    /// Change the number of items that can be loaded, i.e. when some are filtered out.
+    pub fn set_max_content(&self, max_content: usize) {
+        *self.max_content.write().unwrap() = Some(max_content);
+    }
+
     pub fn loaded_content(&self) -> usize {
         *self.loaded_content.read().unwrap()
     }
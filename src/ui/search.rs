@@ -41,13 +41,22 @@ impl SearchView {
         let searchfield = EditView::new()
             .on_submit(move |s, input| {
                 if !input.is_empty() {
-                    let results = SearchResultsView::new(
+                    match SearchResultsView::new(
                         input.to_string(),
                         events.clone(),
                         queue.clone(),
                         library.clone(),
-                    );
-                    s.call_on_name("main", move |v: &mut Layout| v.push_view(Box::new(results)));
+                    ) {
+                        Ok(results) => {
+                            s.call_on_name("main", move |v: &mut Layout| {
+                                v.push_view(Box::new(results))
+                            });
+                        }
+                        // reported like the errors of commands
+                        Err(e) => {
+                            s.call_on_name("main", move |v: &mut Layout| v.set_result(Err(e)));
+                        }
+                    }
                 }
             })
             .with_name(EDIT_ID);
@@ -9,6 +9,7 @@ use crate::model::playlist::Playlist;
 use crate::model::show::Show;
 use crate::model::track::Track;
 use crate::queue::Queue;
+use crate::search_query::SearchQuery;
 use crate::spotify::{Spotify, UriType};
 use crate::spotify_url::SpotifyUrl;
 use crate::traits::{ListItem, ViewExt};
@@ -19,10 +20,15 @@ use cursive::view::ViewWrapper;
 use cursive::Cursive;
 use rspotify::model::search::SearchResult;
 use rspotify::model::SearchType;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 
+/// The number of results that are fetched at once.
+const SEARCH_LIMIT: usize = 50;
+
 pub struct SearchResultsView {
     search_term: String,
+    query: SearchQuery,
     results_tracks: Arc<RwLock<Vec<Track>>>,
     pagination_tracks: Pagination<Track>,
     results_albums: Arc<RwLock<Vec<Album>>>,
@@ -40,16 +46,22 @@ pub struct SearchResultsView {
     events: EventManager,
 }
 
-type SearchHandler<I> =
+type LookupHandler<I> =
     Box<dyn Fn(&Spotify, &Arc<RwLock<Vec<I>>>, &str, usize, bool) -> u32 + Send + Sync>;
+type SearchHandler<I> =
+    Box<dyn Fn(&Spotify, &Arc<RwLock<Vec<I>>>, &SearchQuery, usize, bool) -> u32 + Send + Sync>;
 
 impl SearchResultsView {
+    /// Search for `search_term`, or return an error if it isn't a valid query.
     pub fn new(
         search_term: String,
         events: EventManager,
         queue: Arc<Queue>,
         library: Arc<Library>,
-    ) -> SearchResultsView {
+    ) -> Result<SearchResultsView, String> {
+        let query = search_term
+            .parse::<SearchQuery>()
+            .map_err(|e| e.to_string())?;
         let results_tracks = Arc::new(RwLock::new(Vec::new()));
         let results_albums = Arc::new(RwLock::new(Vec::new()));
         let results_artists = Arc::new(RwLock::new(Vec::new()));
@@ -68,10 +80,10 @@ impl SearchResultsView {
         let pagination_playlists = list_playlists.get_pagination().clone();
         let list_shows = ListView::new(results_shows.clone(), queue.clone(), library.clone());
         let pagination_shows = list_shows.get_pagination().clone();
-        let list_episodes = ListView::new(results_episodes.clone(), queue.clone(), library);
+        let list_episodes = ListView::new(results_episodes.clone(), queue.clone(), library.clone());
         let pagination_episodes = list_episodes.get_pagination().clone();
 
-        let tabs = TabView::new()
+        let mut tabs = TabView::new()
             .tab("tracks", list_tracks.with_title("Tracks"))
             .tab("albums", list_albums.with_title("Albums"))
             .tab("artists", list_artists.with_title("Artists"))
@@ -79,8 +91,16 @@ impl SearchResultsView {
             .tab("shows", list_shows.with_title("Podcasts"))
             .tab("episodes", list_episodes.with_title("Podcast Episodes"));
 
+        // Local files are searched right away, as they are already known.
+        if library.cfg.values().music_directory.is_some() && query.types.is_empty() {
+            let results_local_files = Arc::new(RwLock::new(library.search_local_files(&query)));
+            let list_local_files = ListView::new(results_local_files, queue.clone(), library);
+            tabs = tabs.tab("local", list_local_files.with_title("Local Files"));
+        }
+
         let mut view = SearchResultsView {
             search_term,
+            query,
             results_tracks,
             pagination_tracks,
             results_albums,
@@ -99,7 +119,7 @@ impl SearchResultsView {
         };
 
         view.run_search();
-        view
+        Ok(view)
     }
 
     fn get_track(
@@ -121,16 +141,25 @@ impl SearchResultsView {
     fn search_track(
         spotify: &Spotify,
         tracks: &Arc<RwLock<Vec<Track>>>,
-        query: &str,
+        query: &SearchQuery,
         offset: usize,
         append: bool,
     ) -> u32 {
-        if let Some(SearchResult::Tracks(results)) =
-            spotify
-                .api
-                .search(SearchType::Track, query, 50, offset as u32)
-        {
-            let mut t = results.items.iter().map(|ft| ft.into()).collect();
+        let Some(spotify_query) = query.spotify_query(SearchType::Track) else {
+            return 0;
+        };
+        if let Some(SearchResult::Tracks(results)) = spotify.api.search(
+            SearchType::Track,
+            &spotify_query,
+            SEARCH_LIMIT as u32,
+            offset as u32,
+        ) {
+            let mut t = results
+                .items
+                .iter()
+                .map(Track::from)
+                .filter(|item| query.matches(item, Some(SearchType::Track)))
+                .collect();
             let mut r = tracks.write().unwrap();
 
             if append {
@@ -162,16 +191,25 @@ impl SearchResultsView {
     fn search_album(
         spotify: &Spotify,
         albums: &Arc<RwLock<Vec<Album>>>,
-        query: &str,
+        query: &SearchQuery,
         offset: usize,
         append: bool,
     ) -> u32 {
-        if let Some(SearchResult::Albums(results)) =
-            spotify
-                .api
-                .search(SearchType::Album, query, 50, offset as u32)
-        {
-            let mut a = results.items.iter().map(|sa| sa.into()).collect();
+        let Some(spotify_query) = query.spotify_query(SearchType::Album) else {
+            return 0;
+        };
+        if let Some(SearchResult::Albums(results)) = spotify.api.search(
+            SearchType::Album,
+            &spotify_query,
+            SEARCH_LIMIT as u32,
+            offset as u32,
+        ) {
+            let mut a = results
+                .items
+                .iter()
+                .map(Album::from)
+                .filter(|item| query.matches(item, Some(SearchType::Album)))
+                .collect();
             let mut r = albums.write().unwrap();
 
             if append {
@@ -203,16 +241,25 @@ impl SearchResultsView {
     fn search_artist(
         spotify: &Spotify,
         artists: &Arc<RwLock<Vec<Artist>>>,
-        query: &str,
+        query: &SearchQuery,
         offset: usize,
         append: bool,
     ) -> u32 {
-        if let Some(SearchResult::Artists(results)) =
-            spotify
-                .api
-                .search(SearchType::Artist, query, 50, offset as u32)
-        {
-            let mut a = results.items.iter().map(|fa| fa.into()).collect();
+        let Some(spotify_query) = query.spotify_query(SearchType::Artist) else {
+            return 0;
+        };
+        if let Some(SearchResult::Artists(results)) = spotify.api.search(
+            SearchType::Artist,
+            &spotify_query,
+            SEARCH_LIMIT as u32,
+            offset as u32,
+        ) {
+            let mut a = results
+                .items
+                .iter()
+                .map(Artist::from)
+                .filter(|item| query.matches(item, Some(SearchType::Artist)))
+                .collect();
             let mut r = artists.write().unwrap();
 
             if append {
@@ -244,16 +291,25 @@ impl SearchResultsView {
     fn search_playlist(
         spotify: &Spotify,
         playlists: &Arc<RwLock<Vec<Playlist>>>,
-        query: &str,
+        query: &SearchQuery,
         offset: usize,
         append: bool,
     ) -> u32 {
-        if let Some(SearchResult::Playlists(results)) =
-            spotify
-                .api
-                .search(SearchType::Playlist, query, 50, offset as u32)
-        {
-            let mut pls = results.items.iter().map(|sp| sp.into()).collect();
+        let Some(spotify_query) = query.spotify_query(SearchType::Playlist) else {
+            return 0;
+        };
+        if let Some(SearchResult::Playlists(results)) = spotify.api.search(
+            SearchType::Playlist,
+            &spotify_query,
+            SEARCH_LIMIT as u32,
+            offset as u32,
+        ) {
+            let mut pls = results
+                .items
+                .iter()
+                .map(Playlist::from)
+                .filter(|item| query.matches(item, Some(SearchType::Playlist)))
+                .collect();
             let mut r = playlists.write().unwrap();
 
             if append {
@@ -285,16 +341,25 @@ impl SearchResultsView {
     fn search_show(
         spotify: &Spotify,
         shows: &Arc<RwLock<Vec<Show>>>,
-        query: &str,
+        query: &SearchQuery,
         offset: usize,
         append: bool,
     ) -> u32 {
-        if let Some(SearchResult::Shows(results)) =
-            spotify
-                .api
-                .search(SearchType::Show, query, 50, offset as u32)
-        {
-            let mut pls = results.items.iter().map(|sp| sp.into()).collect();
+        let Some(spotify_query) = query.spotify_query(SearchType::Show) else {
+            return 0;
+        };
+        if let Some(SearchResult::Shows(results)) = spotify.api.search(
+            SearchType::Show,
+            &spotify_query,
+            SEARCH_LIMIT as u32,
+            offset as u32,
+        ) {
+            let mut pls = results
+                .items
+                .iter()
+                .map(Show::from)
+                .filter(|item| query.matches(item, Some(SearchType::Show)))
+                .collect();
             let mut r = shows.write().unwrap();
 
             if append {
@@ -326,16 +391,25 @@ impl SearchResultsView {
     fn search_episode(
         spotify: &Spotify,
         episodes: &Arc<RwLock<Vec<Episode>>>,
-        query: &str,
+        query: &SearchQuery,
         offset: usize,
         append: bool,
     ) -> u32 {
-        if let Some(SearchResult::Episodes(results)) =
-            spotify
-                .api
-                .search(SearchType::Episode, query, 50, offset as u32)
-        {
-            let mut e = results.items.iter().map(|se| se.into()).collect();
+        let Some(spotify_query) = query.spotify_query(SearchType::Episode) else {
+            return 0;
+        };
+        if let Some(SearchResult::Episodes(results)) = spotify.api.search(
+            SearchType::Episode,
+            &spotify_query,
+            SEARCH_LIMIT as u32,
+            offset as u32,
+        ) {
+            let mut e = results
+                .items
+                .iter()
+                .map(Episode::from)
+                .filter(|item| query.matches(item, Some(SearchType::Episode)))
+                .collect();
             let mut r = episodes.write().unwrap();
 
             if append {
@@ -348,38 +422,64 @@ impl SearchResultsView {
         0
     }
 
+    /// Look up the item with the id `id` in the background.
+    fn perform_lookup<I: ListItem>(
+        &self,
+        handler: LookupHandler<I>,
+        results: &Arc<RwLock<Vec<I>>>,
+        id: &str,
+    ) {
+        let spotify = self.spotify.clone();
+        let id = id.to_owned();
+        let results = results.clone();
+        let ev = self.events.clone();
+
+        std::thread::spawn(move || {
+            handler(&spotify, &results, &id, 0, false);
+            ev.trigger();
+        });
+    }
+
+    /// Search for `query` in the background, loading more results through `paginator`.
     fn perform_search<I: ListItem + Clone>(
         &self,
         handler: SearchHandler<I>,
         results: &Arc<RwLock<Vec<I>>>,
-        query: &str,
-        paginator: Option<&Pagination<I>>,
+        query: &SearchQuery,
+        paginator: &Pagination<I>,
     ) {
         let spotify = self.spotify.clone();
-        let query = query.to_owned();
+        let query = query.clone();
         let results = results.clone();
         let ev = self.events.clone();
-        let paginator = paginator.cloned();
+        let mut paginator = paginator.clone();
 
         std::thread::spawn(move || {
             let total_items = handler(&spotify, &results, &query, 0, false) as usize;
 
-            // register paginator if the API has more than one page of results
-            if let Some(mut paginator) = paginator {
-                let loaded_items = results.read().unwrap().len();
-                if total_items > loaded_items {
-                    let ev = ev.clone();
-
-                    // paginator callback
-                    let cb = move |items: Arc<RwLock<Vec<I>>>| {
-                        let offset = items.read().unwrap().len();
-                        handler(&spotify, &results, &query, offset, true);
-                        ev.trigger();
-                    };
-                    paginator.set(loaded_items, total_items, Box::new(cb));
-                } else {
-                    paginator.clear()
-                }
+            // register paginator if the API has more than one page of results, the API offset is
+            // counted separately as results may be filtered out
+            let offset = total_items.min(SEARCH_LIMIT);
+            let loaded_items = results.read().unwrap().len();
+            if total_items > offset {
+                let ev = ev.clone();
+                let pagination = paginator.clone();
+                let filtered = offset - loaded_items;
+                let offset = AtomicUsize::new(offset);
+
+                // paginator callback
+                let cb = move |items: Arc<RwLock<Vec<I>>>| {
+                    let current = offset.load(Ordering::Relaxed);
+                    handler(&spotify, &results, &query, current, true);
+                    let next = (current + SEARCH_LIMIT).min(total_items);
+                    offset.store(next, Ordering::Relaxed);
+                    let filtered = next - items.read().unwrap().len();
+                    pagination.set_max_content(total_items - filtered);
+                    ev.trigger();
+                };
+                paginator.set(loaded_items, total_items - filtered, Box::new(cb));
+            } else {
+                paginator.clear()
             }
             ev.trigger();
         });
@@ -397,56 +497,34 @@ impl SearchResultsView {
         if let Some(uritype) = UriType::from_uri(&query) {
             match uritype {
                 UriType::Track => {
-                    self.perform_search(
-                        Box::new(Self::get_track),
-                        &self.results_tracks,
-                        &query,
-                        None,
-                    );
+                    self.perform_lookup(Box::new(Self::get_track), &self.results_tracks, &query);
                     self.tabs.move_focus_to(0);
                 }
                 UriType::Album => {
-                    self.perform_search(
-                        Box::new(Self::get_album),
-                        &self.results_albums,
-                        &query,
-                        None,
-                    );
+                    self.perform_lookup(Box::new(Self::get_album), &self.results_albums, &query);
                     self.tabs.move_focus_to(1);
                 }
                 UriType::Artist => {
-                    self.perform_search(
-                        Box::new(Self::get_artist),
-                        &self.results_artists,
-                        &query,
-                        None,
-                    );
+                    self.perform_lookup(Box::new(Self::get_artist), &self.results_artists, &query);
                     self.tabs.move_focus_to(2);
                 }
                 UriType::Playlist => {
-                    self.perform_search(
+                    self.perform_lookup(
                         Box::new(Self::get_playlist),
                         &self.results_playlists,
                         &query,
-                        None,
                     );
                     self.tabs.move_focus_to(3);
                 }
                 UriType::Show => {
-                    self.perform_search(
-                        Box::new(Self::get_show),
-                        &self.results_shows,
-                        &query,
-                        None,
-                    );
+                    self.perform_lookup(Box::new(Self::get_show), &self.results_shows, &query);
                     self.tabs.move_focus_to(4);
                 }
                 UriType::Episode => {
-                    self.perform_search(
+                    self.perform_lookup(
                         Box::new(Self::get_episode),
                         &self.results_episodes,
                         &query,
-                        None,
                     );
                     self.tabs.move_focus_to(5);
                 }
@@ -456,56 +534,34 @@ impl SearchResultsView {
         } else if let Some(url) = SpotifyUrl::from_url(&query) {
             match url.uri_type {
                 UriType::Track => {
-                    self.perform_search(
-                        Box::new(Self::get_track),
-                        &self.results_tracks,
-                        &url.id,
-                        None,
-                    );
+                    self.perform_lookup(Box::new(Self::get_track), &self.results_tracks, &url.id);
                     self.tabs.move_focus_to(0);
                 }
                 UriType::Album => {
-                    self.perform_search(
-                        Box::new(Self::get_album),
-                        &self.results_albums,
-                        &url.id,
-                        None,
-                    );
+                    self.perform_lookup(Box::new(Self::get_album), &self.results_albums, &url.id);
                     self.tabs.move_focus_to(1);
                 }
                 UriType::Artist => {
-                    self.perform_search(
-                        Box::new(Self::get_artist),
-                        &self.results_artists,
-                        &url.id,
-                        None,
-                    );
+                    self.perform_lookup(Box::new(Self::get_artist), &self.results_artists, &url.id);
                     self.tabs.move_focus_to(2);
                 }
                 UriType::Playlist => {
-                    self.perform_search(
+                    self.perform_lookup(
                         Box::new(Self::get_playlist),
                         &self.results_playlists,
                         &url.id,
-                        None,
                     );
                     self.tabs.move_focus_to(3);
                 }
                 UriType::Show => {
-                    self.perform_search(
-                        Box::new(Self::get_show),
-                        &self.results_shows,
-                        &url.id,
-                        None,
-                    );
+                    self.perform_lookup(Box::new(Self::get_show), &self.results_shows, &url.id);
                     self.tabs.move_focus_to(4);
                 }
                 UriType::Episode => {
-                    self.perform_search(
+                    self.perform_lookup(
                         Box::new(Self::get_episode),
                         &self.results_episodes,
                         &url.id,
-                        None,
                     );
                     self.tabs.move_focus_to(5);
                 }
@@ -514,39 +570,53 @@ impl SearchResultsView {
             self.perform_search(
                 Box::new(Self::search_track),
                 &self.results_tracks,
-                &query,
-                Some(&self.pagination_tracks),
+                &self.query,
+                &self.pagination_tracks,
             );
             self.perform_search(
                 Box::new(Self::search_album),
                 &self.results_albums,
-                &query,
-                Some(&self.pagination_albums),
+                &self.query,
+                &self.pagination_albums,
             );
             self.perform_search(
                 Box::new(Self::search_artist),
                 &self.results_artists,
-                &query,
-                Some(&self.pagination_artists),
+                &self.query,
+                &self.pagination_artists,
             );
             self.perform_search(
                 Box::new(Self::search_playlist),
                 &self.results_playlists,
-                &query,
-                Some(&self.pagination_playlists),
+                &self.query,
+                &self.pagination_playlists,
             );
             self.perform_search(
                 Box::new(Self::search_show),
                 &self.results_shows,
-                &query,
-                Some(&self.pagination_shows),
+                &self.query,
+                &self.pagination_shows,
             );
             self.perform_search(
                 Box::new(Self::search_episode),
                 &self.results_episodes,
-                &query,
-                Some(&self.pagination_episodes),
+                &self.query,
+                &self.pagination_episodes,
             );
+            // show the results of the type that was searched for
+            let tab = [
+                SearchType::Track,
+                SearchType::Album,
+                SearchType::Artist,
+                SearchType::Playlist,
+                SearchType::Show,
+                SearchType::Episode,
+            ]
+            .iter()
+            .position(|kind| self.query.types.first() == Some(kind));
+            if let Some(tab) = tab {
+                self.tabs.move_focus_to(tab);
+            }
         }
     }
 }
@@ -0,0 +1,240 @@
+use std::cmp::max;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use cursive::align::HAlign;
+use cursive::event::{Event, EventResult};
+use cursive::theme::{ColorStyle, Effect};
+use cursive::traits::View;
+use cursive::{Cursive, Printer, Vec2};
+use unicode_width::UnicodeWidthStr;
+
+use crate::command::{Command, StatsWindow};
+use crate::commands::CommandResult;
+use crate::library::Library;
+use crate::model::album::Album;
+use crate::model::artist::Artist;
+use crate::model::playable::Playable;
+use crate::model::ranked::Ranked;
+use crate::queue::Queue;
+use crate::stats::Stats;
+use crate::traits::ViewExt;
+use crate::ui::listview::ListView;
+use crate::ui::tabview::TabView;
+use crate::utils::format_duration;
+
+/// The height of the histogram bars in rows.
+const HISTOGRAM_HEIGHT: usize = 8;
+/// The rows above the tabs: the summary, the histogram, its axis and a blank line.
+const HEADER_HEIGHT: usize = HISTOGRAM_HEIGHT + 3;
+/// Partially filled blocks, used to draw the tops of the bars in eighths of a row.
+const BLOCKS: [&str; 8] = ["▁", "▂", "▃", "▄", "▅", "▆", "▇", "█"];
+
+pub struct StatsView {
+    library: Arc<Library>,
+    window: StatsWindow,
+    /// The history length, window and day the statistics were last computed for.
+    computed_for: Option<(usize, Option<DateTime<Utc>>, StatsWindow, NaiveDate)>,
+    plays: usize,
+    played: Duration,
+    days: Vec<(NaiveDate, Duration)>,
+    artists: Arc<RwLock<Vec<Ranked<Artist>>>>,
+    albums: Arc<RwLock<Vec<Ranked<Album>>>>,
+    tracks: Arc<RwLock<Vec<Ranked<Playable>>>>,
+    tabs: TabView,
+}
+
+impl StatsView {
+    pub fn new(queue: Arc<Queue>, library: Arc<Library>) -> Self {
+        let artists = Arc::new(RwLock::new(Vec::new()));
+        let albums = Arc::new(RwLock::new(Vec::new()));
+        let tracks = Arc::new(RwLock::new(Vec::new()));
+
+        let tabs = TabView::new()
+            .tab(
+                "artists",
+                ListView::new(artists.clone(), queue.clone(), library.clone())
+                    .with_title("Top Artists"),
+            )
+            .tab(
+                "tracks",
+                ListView::new(tracks.clone(), queue.clone(), library.clone())
+                    .with_title("Top Tracks"),
+            )
+            .tab(
+                "albums",
+                ListView::new(albums.clone(), queue, library.clone()).with_title("Top Albums"),
+            );
+
+        let window = library
+            .cfg
+            .values()
+            .stats_window
+            .unwrap_or(StatsWindow::Month);
+
+        Self {
+            library,
+            window,
+            computed_for: None,
+            plays: 0,
+            played: Duration::ZERO,
+            days: Vec::new(),
+            artists,
+            albums,
+            tracks,
+            tabs,
+        }
+    }
+
+    /// Recompute the statistics if the history, the window or the current day changed.
+    fn refresh(&mut self) {
+        let history = self.library.history.read().unwrap();
+        let today = Local::now().date_naive();
+        let key = (
+            history.len(),
+            history.first().map(|play| play.played_at),
+            self.window,
+            today,
+        );
+        if self.computed_for == Some(key) {
+            return;
+        }
+
+        let stats = Stats::new(&history, self.window, today);
+        self.computed_for = Some(key);
+        self.plays = stats.plays;
+        self.played = stats.played;
+        self.days = stats.days;
+        *self.artists.write().unwrap() = stats.artists;
+        *self.albums.write().unwrap() = stats.albums;
+        *self.tracks.write().unwrap() = stats.tracks;
+    }
+
+    fn window_title(&self) -> &'static str {
+        match self.window {
+            StatsWindow::Week => "Last 7 days",
+            StatsWindow::Month => "Last 30 days",
+            StatsWindow::All => "All time",
+        }
+    }
+
+    /// Draw the listening time per day as vertical bars, combining consecutive days into one
+    /// bar if there isn't enough room for all of them.
+    fn draw_histogram(&self, printer: &Printer<'_, '_>) {
+        let width = printer.size.x;
+        if self.days.is_empty() || width == 0 {
+            return;
+        }
+
+        let days_per_bar = self.days.len().div_ceil(width);
+        let bars: Vec<Duration> = self
+            .days
+            .chunks(days_per_bar)
+            .map(|days| days.iter().map(|(_, played)| *played).sum())
+            .collect();
+        let longest = bars.iter().max().copied().unwrap_or_default();
+        let bar_width = max(width / bars.len(), 1);
+        // leave a gap between bars when they are wide enough
+        let fill_width = if bar_width > 2 {
+            bar_width - 1
+        } else {
+            bar_width
+        };
+
+        for (i, played) in bars.iter().enumerate() {
+            let eighths = if longest.is_zero() {
+                0
+            } else {
+                (played.as_secs_f64() / longest.as_secs_f64() * (HISTOGRAM_HEIGHT * 8) as f64)
+                    .round() as usize
+            };
+            let x = i * bar_width;
+            for row in 0..HISTOGRAM_HEIGHT {
+                let filled = eighths.saturating_sub(row * 8).min(8);
+                if filled == 0 {
+                    break;
+                }
+                let y = HISTOGRAM_HEIGHT - 1 - row;
+                printer.print_hline((x, y), fill_width, BLOCKS[filled - 1]);
+            }
+        }
+
+        if let (Some((first, _)), Some((last, _))) = (self.days.first(), self.days.last()) {
+            printer.with_color(ColorStyle::secondary(), |printer| {
+                let first = first.format("%Y-%m-%d").to_string();
+                let last = last.format("%Y-%m-%d").to_string();
+                printer.print((0, HISTOGRAM_HEIGHT), &first);
+                if first != last {
+                    let offset = HAlign::Right.get_offset(last.width(), width);
+                    printer.print((offset, HISTOGRAM_HEIGHT), &last);
+                }
+                if !longest.is_zero() {
+                    let most = format!(
+                        "max {}",
+                        format_duration(&Duration::from_secs(longest.as_secs()))
+                    );
+                    let offset = HAlign::Center.get_offset(most.width(), width);
+                    printer.print((offset, HISTOGRAM_HEIGHT), &most);
+                }
+            });
+        }
+    }
+}
+
+impl View for StatsView {
+    fn draw(&self, printer: &Printer<'_, '_>) {
+        let summary = format!(
+            "{}: {} plays, {} of listening",
+            self.window_title(),
+            self.plays,
+            format_duration(&Duration::from_secs(self.played.as_secs()))
+        );
+        printer.with_effect(Effect::Bold, |printer| {
+            printer.print((0, 0), &summary);
+        });
+
+        let histogram = printer
+            .offset((0, 1))
+            .cropped((printer.size.x, HISTOGRAM_HEIGHT + 1));
+        self.draw_histogram(&histogram);
+
+        if printer.size.y > HEADER_HEIGHT {
+            let tabs = printer
+                .offset((0, HEADER_HEIGHT))
+                .cropped((printer.size.x, printer.size.y - HEADER_HEIGHT));
+            self.tabs.draw(&tabs);
+        }
+    }
+
+    fn layout(&mut self, size: Vec2) {
+        self.refresh();
+        // the tabs need at least a row for their titles
+        let height = max(size.y.saturating_sub(HEADER_HEIGHT), 1);
+        self.tabs.layout(Vec2::new(size.x, height));
+    }
+
+    fn on_event(&mut self, event: Event) -> EventResult {
+        self.tabs.on_event(event.relativized((0, HEADER_HEIGHT)))
+    }
+}
+
+impl ViewExt for StatsView {
+    fn title(&self) -> String {
+        "Statistics".to_string()
+    }
+
+    fn title_sub(&self) -> String {
+        self.window_title().to_string()
+    }
+
+    fn on_command(&mut self, s: &mut Cursive, cmd: &Command) -> Result<CommandResult, String> {
+        if let Command::Stats(window) = cmd {
+            self.window = *window;
+            self.refresh();
+            return Ok(CommandResult::Consumed(None));
+        }
+
+        self.tabs.on_command(s, cmd)
+    }
+}
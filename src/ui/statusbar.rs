@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use cursive::align::HAlign;
 use cursive::event::{Event, EventResult, MouseButton, MouseEvent};
@@ -11,7 +12,9 @@ use unicode_width::UnicodeWidthStr;
 use crate::library::Library;
 use crate::model::playable::Playable;
 use crate::queue::{Queue, RepeatSetting};
+use crate::sleep_timer::SleepStatus;
 use crate::spotify::{PlayerEvent, Spotify};
+use crate::time_stretch;
 use crate::utils::ms_to_hms;
 
 pub struct StatusBar {
@@ -71,6 +74,67 @@ impl StatusBar {
         )
     }
 
+    /// The time or number of tracks left until the sleep timer stops playback.
+    fn sleep_display(&self) -> String {
+        let left = match self.spotify.sleep_timer.status(SystemTime::now()) {
+            Some(SleepStatus {
+                remaining: Some(remaining),
+                ..
+            }) => ms_to_hms(remaining.as_millis().try_into().unwrap_or(0)),
+            Some(SleepStatus {
+                tracks: Some(1), ..
+            }) => "1 track".to_string(),
+            Some(SleepStatus {
+                tracks: Some(tracks),
+                ..
+            }) => format!("{tracks} tracks"),
+            _ => return String::new(),
+        };
+        if self.use_nerdfont() {
+            format!("\u{f04b2} {left} ")
+        } else {
+            format!("[S {left}] ")
+        }
+    }
+
+    /// The Spotify Connect controller whose queue is played.
+    #[cfg(feature = "connect")]
+    fn connect_display(&self) -> String {
+        match self.queue.controller() {
+            Some(controller) if self.use_nerdfont() => format!("\u{f0118} {controller} "),
+            Some(controller) => format!("[{controller}] "),
+            None => String::new(),
+        }
+    }
+
+    /// The other device that is controlled, while ncspot acts as a remote.
+    fn remote_display(&self) -> String {
+        match self.spotify.remote_device.get() {
+            Some(device) if self.use_nerdfont() => format!("\u{f0454} {} ", device.name),
+            Some(device) => format!("[→ {}] ", device.name),
+            None => String::new(),
+        }
+    }
+
+    /// The name of the active equalizer preset.
+    fn eq_display(&self) -> String {
+        match self.spotify.equalizer.preset_name() {
+            Some(preset) if self.use_nerdfont() => format!("\u{f1c0} {preset} "),
+            Some(preset) => format!("[EQ {preset}] "),
+            None => String::new(),
+        }
+    }
+
+    /// The playback speed, unless it is the normal speed.
+    fn speed_display(&self) -> String {
+        let speed = self.spotify.speed();
+        if time_stretch::is_normal(speed) {
+            String::new()
+        } else {
+            format!("[{speed}x] ")
+        }
+    }
+
     fn format_track(&self, t: &Playable) -> String {
         let format = self
             .library
@@ -157,6 +221,29 @@ impl View for StatusBar {
             ""
         };
 
+        let offline = if self.queue.get_offline() {
+            if self.use_nerdfont() {
+                "\u{f0b5a} "
+            } else {
+                "[O] "
+            }
+        } else {
+            ""
+        };
+
+        #[cfg(feature = "connect")]
+        let connect = self.connect_display();
+        #[cfg(not(feature = "connect"))]
+        let connect = String::new();
+
+        let remote = self.remote_display();
+
+        let sleep = self.sleep_display();
+
+        let eq = self.eq_display();
+
+        let speed = self.speed_display();
+
         let volume = self.volume_display();
 
         printer.with_color(style_bar_bg, |printer| {
@@ -176,6 +263,12 @@ impl View for StatusBar {
         let right = updating.to_string()
             + repeat
             + shuffle
+            + offline
+            + &connect
+            + &remote
+            + &sleep
+            + &eq
+            + &speed
             // + saved
             + &playback_duration_status
             + &volume;
@@ -59,3 +59,32 @@ pub fn download(url: String, path: std::path::PathBuf) -> Result<(), std::io::Er
     std::io::copy(&mut resp, &mut file)?;
     Ok(())
 }
+
+/// Expand a leading `~/` in `path` to the home directory of the user.
+pub fn expand_home(path: &str) -> std::path::PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(relative), Some(home)) => std::path::Path::new(&home).join(relative),
+        _ => std::path::PathBuf::from(path),
+    }
+}
+
+/// Compare two tokens in constant time, so the expected token can't be guessed from the time it
+/// takes to reject a wrong one.
+pub fn tokens_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// A path in the temporary directory that is unique to `name` and the test run, with nothing left
+/// at it by a previous run.
+#[cfg(test)]
+pub fn temp_path(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("ncspot-{}-{}", std::process::id(), name));
+    std::fs::remove_dir_all(&path).ok();
+    std::fs::remove_file(&path).ok();
+    path
+}
@@ -0,0 +1,482 @@
+//! A small HTTP server that serves a remote control web interface and a REST API to control
+//! ncspot from a browser, i.e. on a tablet.
+//!
+//! | Method | Path                   | Description                                              |
+//! |--------|------------------------|----------------------------------------------------------|
+//! | `GET`  | `/`                    | The web interface                                        |
+//! | `GET`  | `/api/status`          | Playback status, current item, volume, shuffle, repeat  |
+//! | `GET`  | `/api/queue`           | The items in the queue and the index of the current one |
+//! | `POST` | `/api/commands/<NAME>` | Execute the command `NAME`, the body holds its arguments |
+//!
+//! Requests to the API have to send the configured token as `Authorization: Bearer <TOKEN>`, and
+//! requests from a browser have to come from a page served by ncspot itself.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use log::{debug, error, info};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tokio::sync::oneshot;
+
+use crate::command::{self, Command};
+use crate::events::{Event, EventManager};
+use crate::library::Library;
+use crate::queue::Queue;
+use crate::spotify::PlayerEvent;
+use crate::utils::tokens_match;
+
+/// The web interface, served at `/`.
+const INDEX_HTML: &str = include_str!("index.html");
+
+/// The operations the web server needs to serve its API.
+pub trait Remote: Send + Sync + 'static {
+    /// The current playback status.
+    fn status(&self) -> Value;
+    /// The current contents of the queue.
+    fn queue(&self) -> Value;
+    /// Execute the command line `command` and return its output.
+    fn execute(&self, command: String) -> Result<Option<String>, String>;
+}
+
+/// Serves the API from the state of a running ncspot instance.
+pub struct Controller {
+    ev: EventManager,
+    queue: Arc<Queue>,
+    library: Arc<Library>,
+}
+
+impl Controller {
+    pub fn new(ev: EventManager, queue: Arc<Queue>, library: Arc<Library>) -> Self {
+        Self { ev, queue, library }
+    }
+}
+
+impl Remote for Controller {
+    fn status(&self) -> Value {
+        let spotify = self.queue.get_spotify();
+        let playable = self.queue.get_current();
+        let mode = match spotify.get_current_status() {
+            PlayerEvent::Playing(_) => "playing",
+            PlayerEvent::Paused(_) => "paused",
+            PlayerEvent::Stopped | PlayerEvent::FinishedTrack => "stopped",
+        };
+        json!({
+            "mode": mode,
+            "cover_url": playable.as_ref().and_then(|p| p.cover_url()),
+            "saved": playable.as_ref().map(|p| self.library.is_saved_track(p)).unwrap_or(false),
+            "playable": playable,
+            "progress_ms": spotify.get_current_progress().as_millis() as u64,
+            "volume": (spotify.volume() as f64 / 65535_f64 * 100.0).round() as u16,
+            "shuffle": self.queue.get_shuffle(),
+            "repeat": self.queue.get_repeat(),
+        })
+    }
+
+    fn queue(&self) -> Value {
+        json!({
+            "current": self.queue.get_current_index(),
+            "items": *self.queue.queue.read().unwrap(),
+        })
+    }
+
+    fn execute(&self, command: String) -> Result<Option<String>, String> {
+        // Commands need access to the UI, so they are executed by the main event loop, which
+        // reports the result back.
+        let (tx, rx) = oneshot::channel();
+        self.ev.send(Event::IpcCommand(command, tx));
+        rx.blocking_recv()
+            .map_err(|_| String::from("Command was not executed"))?
+    }
+}
+
+/// Decides which requests may use the API.
+struct Access {
+    /// The token clients have to send as `Authorization: Bearer <TOKEN>`.
+    token: String,
+    /// The address the server is listening on.
+    address: SocketAddr,
+}
+
+impl Access {
+    /// Check that `request` is sent to this server, from a page served by it if it comes from a
+    /// browser, and with the configured token. Returns the status and the reason otherwise.
+    fn check(&self, request: &Request) -> Result<(), (u16, &'static str)> {
+        let header_value = |name: &'static str| {
+            request
+                .headers()
+                .iter()
+                .find(|header| header.field.equiv(name))
+                .map(|header| header.value.as_str())
+        };
+
+        if !header_value("Host").is_some_and(|host| self.is_own_host(host)) {
+            return Err((403, "Invalid host"));
+        }
+        if let Some(origin) = header_value("Origin") {
+            match origin.strip_prefix("http://") {
+                Some(host) if self.is_own_host(host) => {}
+                _ => return Err((403, "Invalid origin")),
+            }
+        }
+        let token = header_value("Authorization").and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) if tokens_match(&self.token, token) => Ok(()),
+            _ => Err((401, "Invalid token")),
+        }
+    }
+
+    /// Whether `host`, the value of a Host header, is the address the server is listening on.
+    /// Only IP addresses and `localhost` are accepted, so that a domain name can't be rebound to
+    /// the server.
+    fn is_own_host(&self, host: &str) -> bool {
+        let (name, port) = match host.rsplit_once(':') {
+            Some((name, port)) if !port.ends_with(']') => (name, port.parse::<u16>().ok()),
+            _ => (host, Some(80)),
+        };
+        let bound = self.address.ip();
+        let is_bound_ip = if name.eq_ignore_ascii_case("localhost") {
+            bound.is_loopback()
+        } else {
+            match name
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+            {
+                Ok(ip) => ip == bound,
+                Err(_) => return false,
+            }
+        };
+        port == Some(self.address.port()) && (is_bound_ip || bound.is_unspecified())
+    }
+}
+
+/// The HTTP server, which is stopped when dropped.
+pub struct WebServer {
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WebServer {
+    /// Start serving the API of `remote` on `address`, i.e. `127.0.0.1:8990`, to clients that
+    /// authenticate with `token`.
+    pub fn start<R: Remote>(address: &str, token: String, remote: R) -> Result<Self, String> {
+        let server = Arc::new(Server::http(address).map_err(|e| e.to_string())?);
+        let access = Access {
+            token,
+            address: server
+                .server_addr()
+                .to_ip()
+                .ok_or("Web interface is not listening on an IP address")?,
+        };
+
+        let worker_server = server.clone();
+        let thread = std::thread::spawn(move || {
+            for request in worker_server.incoming_requests() {
+                Self::handle(&remote, &access, request);
+            }
+        });
+
+        let web_server = Self {
+            server,
+            thread: Some(thread),
+        };
+        if let Some(address) = web_server.address() {
+            info!("Serving web interface on http://{address}");
+        }
+        Ok(web_server)
+    }
+
+    /// The address the server is listening on.
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    fn handle<R: Remote>(remote: &R, access: &Access, mut request: Request) {
+        debug!("HTTP request: {} {}", request.method(), request.url());
+
+        let path = request
+            .url()
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let denied = if path.starts_with("/api/") {
+            access.check(&request).err()
+        } else {
+            None
+        };
+        let response = match (denied, request.method(), path.as_str()) {
+            (Some((status, e)), _, _) => json_response(status, &json!({ "error": e })),
+            (None, Method::Get, "/") => Response::from_string(INDEX_HTML)
+                .with_header(header("Content-Type", "text/html; charset=utf-8")),
+            (None, Method::Get, "/api/status") => json_response(200, &remote.status()),
+            (None, Method::Get, "/api/queue") => json_response(200, &remote.queue()),
+            (None, Method::Post, path) if path.starts_with("/api/commands/") => {
+                let name = &path["/api/commands/".len()..];
+                let mut args = String::new();
+                match request.as_reader().read_to_string(&mut args) {
+                    Ok(_) => match Self::execute(remote, name, &args) {
+                        Ok(output) => json_response(200, &json!({ "result": output })),
+                        Err((status, e)) => json_response(status, &json!({ "error": e })),
+                    },
+                    Err(e) => json_response(400, &json!({ "error": e.to_string() })),
+                }
+            }
+            (None, Method::Get, _) | (None, Method::Post, _) => {
+                json_response(404, &json!({ "error": "Not found" }))
+            }
+            _ => json_response(405, &json!({ "error": "Method not allowed" })),
+        };
+
+        if let Err(e) = request.respond(response) {
+            error!("Could not send HTTP response: {e}");
+        }
+    }
+
+    /// Execute the single command `name` with the arguments `args`. Returns the status and the
+    /// error message if the command is invalid or not allowed.
+    fn execute<R: Remote>(
+        remote: &R,
+        name: &str,
+        args: &str,
+    ) -> Result<Option<String>, (u16, String)> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.') {
+            return Err((400, format!("Invalid command name \"{name}\"")));
+        }
+
+        let args = args.trim();
+        let line = if args.is_empty() {
+            name.to_string()
+        } else {
+            format!("{name} {args}")
+        };
+        let commands = command::parse(&line).map_err(|e| (400, e.to_string()))?;
+        match commands.as_slice() {
+            [cmd] if is_allowed(cmd) => remote.execute(line).map_err(|e| (400, e)),
+            [cmd] => Err((
+                403,
+                format!("\"{cmd}\" can't be executed through the web interface"),
+            )),
+            _ => Err((400, String::from("Expected a single command"))),
+        }
+    }
+}
+
+impl Drop for WebServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Web server thread panicked");
+            }
+        }
+    }
+}
+
+/// Whether `cmd` may be executed through the API, which excludes commands that run programs or
+/// write files.
+fn is_allowed(cmd: &Command) -> bool {
+    !matches!(
+        cmd,
+        Command::Execute(_) | Command::ExportHistory(_) | Command::Logout
+    )
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+fn json_response(status: u16, value: &Value) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(value.to_string())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct StubRemote {
+        executed: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Remote for StubRemote {
+        fn status(&self) -> Value {
+            json!({ "mode": "paused", "volume": 42 })
+        }
+
+        fn queue(&self) -> Value {
+            json!({ "current": null, "items": [] })
+        }
+
+        fn execute(&self, command: String) -> Result<Option<String>, String> {
+            self.executed.lock().unwrap().push(command);
+            Ok(None)
+        }
+    }
+
+    const TOKEN: &str = "secret";
+
+    fn start() -> (WebServer, String, Arc<Mutex<Vec<String>>>) {
+        let remote = StubRemote::default();
+        let executed = remote.executed.clone();
+        let server = WebServer::start("127.0.0.1:0", TOKEN.to_string(), remote).unwrap();
+        let base = format!("http://{}", server.address().unwrap());
+        (server, base, executed)
+    }
+
+    fn post(base: &str, name: &str, args: &'static str) -> reqwest::blocking::RequestBuilder {
+        reqwest::blocking::Client::new()
+            .post(format!("{base}/api/commands/{name}"))
+            .bearer_auth(TOKEN)
+            .body(args)
+    }
+
+    #[test]
+    fn serves_status_and_interface() {
+        let (_server, base, _) = start();
+        let client = reqwest::blocking::Client::new();
+
+        let status: Value = client
+            .get(format!("{base}/api/status"))
+            .bearer_auth(TOKEN)
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(status["volume"], 42);
+
+        let index = client.get(&base).send().unwrap();
+        assert_eq!(index.status(), 200);
+        assert!(index.text().unwrap().contains("ncspot"));
+    }
+
+    #[test]
+    fn executes_commands() {
+        let (_server, base, executed) = start();
+
+        let response = post(&base, "seek", "+10s").send().unwrap();
+        assert_eq!(response.status(), 200);
+
+        let response = post(&base, "next", "")
+            .header("Origin", base.clone())
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        assert_eq!(*executed.lock().unwrap(), vec!["seek +10s", "next"]);
+    }
+
+    #[test]
+    fn rejects_invalid_commands() {
+        let (_server, base, executed) = start();
+
+        for (name, args) in [("nosuchcommand", ""), ("next", "; quit"), ("volup", "abc")] {
+            let response = post(&base, name, args).send().unwrap();
+            assert_eq!(response.status(), 400, "{name} {args}");
+        }
+
+        assert!(executed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_commands_that_run_programs_or_write_files() {
+        let (_server, base, executed) = start();
+
+        for (name, args) in [
+            ("exec", "touch /tmp/pwned"),
+            ("exporthistory", "/tmp/history.json"),
+            ("logout", ""),
+        ] {
+            let response = post(&base, name, args).send().unwrap();
+            assert_eq!(response.status(), 403, "{name} {args}");
+        }
+
+        assert!(executed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_requests_without_the_token() {
+        let (_server, base, executed) = start();
+        let client = reqwest::blocking::Client::new();
+
+        let response = client.get(format!("{base}/api/status")).send().unwrap();
+        assert_eq!(response.status(), 401);
+        let response = client
+            .post(format!("{base}/api/commands/next"))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 401);
+        let response = client
+            .post(format!("{base}/api/commands/next"))
+            .bearer_auth("secreT")
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 401);
+        let response = client
+            .post(format!("{base}/api/commands/next"))
+            .header("Authorization", TOKEN)
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 401);
+
+        assert!(executed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_requests_from_other_hosts_and_origins() {
+        let (server, base, executed) = start();
+        let port = server.address().unwrap().port();
+
+        let response = post(&base, "next", "")
+            .header("Host", format!("attacker.example:{port}"))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 403);
+        for origin in [
+            "http://attacker.example",
+            "null",
+            &base.replace("http", "https"),
+        ] {
+            let response = post(&base, "next", "")
+                .header("Origin", origin)
+                .send()
+                .unwrap();
+            assert_eq!(response.status(), 403, "{origin}");
+        }
+
+        assert!(executed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn accepts_only_the_bound_address_as_host() {
+        let access = |address: &str| Access {
+            token: TOKEN.to_string(),
+            address: address.parse().unwrap(),
+        };
+
+        let loopback = access("127.0.0.1:8990");
+        assert!(loopback.is_own_host("127.0.0.1:8990"));
+        assert!(loopback.is_own_host("localhost:8990"));
+        assert!(!loopback.is_own_host("127.0.0.1:8991"));
+        assert!(!loopback.is_own_host("127.0.0.1"));
+        assert!(!loopback.is_own_host("192.168.1.2:8990"));
+        assert!(!loopback.is_own_host("attacker.example:8990"));
+
+        let any = access("0.0.0.0:80");
+        assert!(any.is_own_host("192.168.1.2"));
+        assert!(any.is_own_host("192.168.1.2:80"));
+        assert!(any.is_own_host("[fe80::1]"));
+        assert!(!any.is_own_host("attacker.example"));
+
+        let ipv6 = access("[::1]:8990");
+        assert!(ipv6.is_own_host("[::1]:8990"));
+        assert!(ipv6.is_own_host("localhost:8990"));
+        assert!(!ipv6.is_own_host("[::2]:8990"));
+    }
+}